@@ -0,0 +1,113 @@
+use std::time::Instant;
+
+use glam::Vec2;
+
+use crate::{
+    SimParams,
+    cpu::{CpuBackend, CpuOptions, CpuSim},
+    sim::Particle,
+};
+
+/// Relative error of the Barnes-Hut forces on one culture's particles
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ErrorStats {
+    pub mean: f32,
+    pub max: f32,
+    /// Particles compared, those feeling no exact force are skipped
+    pub samples: usize,
+}
+
+impl ErrorStats {
+    fn from_errors(errors: impl Iterator<Item = f32>) -> Self {
+        let (sum, max, samples) = errors.fold((0.0, 0.0f32, 0), |(sum, max, n), e| {
+            (sum + e, max.max(e), n + 1)
+        });
+        Self {
+            mean: if samples > 0 {
+                sum / samples as f32
+            } else {
+                0.0
+            },
+            max,
+            samples,
+        }
+    }
+}
+
+/// Barnes-Hut error and cost at one opening angle
+#[derive(Clone, Debug)]
+pub struct ThetaAccuracy {
+    pub theta: f32,
+    /// Time to build the quadtrees and compute every force
+    pub force_ms: f64,
+    pub total: ErrorStats,
+    pub cultures: Vec<ErrorStats>,
+}
+
+/// Exact forces and the Barnes-Hut error against them across a sweep of thetas
+#[derive(Clone, Debug)]
+pub struct ThetaSweep {
+    pub naive_ms: f64,
+    pub thetas: Vec<ThetaAccuracy>,
+}
+
+fn timed(f: impl FnOnce()) -> f64 {
+    let start = Instant::now();
+    f();
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Compare [`CpuSim::compute_force`] against [`CpuSim::compute_force_naive`] for one world
+/// state at each theta. The relative error of a particle is `|f_bh - f| / |f|` where `f` is the
+/// exact force.
+pub fn theta_sweep(
+    params: &SimParams,
+    particles: &[Particle],
+    bound: Vec2,
+    thetas: &[f32],
+) -> ThetaSweep {
+    let opts = |backend, theta| CpuOptions {
+        backend,
+        theta,
+        bound,
+        seed: Some(0),
+    };
+    let mut naive = CpuSim::with_particles(
+        params.clone(),
+        opts(CpuBackend::Naive, 0.0),
+        particles.to_vec(),
+    );
+    let naive_ms = timed(|| naive.compute_force_naive());
+    let exact = naive.forces();
+
+    let cs = params.culture_size as usize;
+    let thetas = thetas
+        .iter()
+        .map(|&theta| {
+            let mut sim = CpuSim::with_particles(
+                params.clone(),
+                opts(CpuBackend::BarnesHut, theta),
+                particles.to_vec(),
+            );
+            let force_ms = timed(|| sim.compute_force());
+            let errors = exact
+                .iter()
+                .zip(sim.forces())
+                .map(|(&f, &approx)| {
+                    let norm = f.length();
+                    (norm > 0.0).then(|| (approx - f).length() / norm)
+                })
+                .collect::<Vec<_>>();
+            ThetaAccuracy {
+                theta,
+                force_ms,
+                total: ErrorStats::from_errors(errors.iter().flatten().copied()),
+                cultures: errors
+                    .chunks(cs)
+                    .map(|c| ErrorStats::from_errors(c.iter().flatten().copied()))
+                    .collect(),
+            }
+        })
+        .collect();
+    ThetaSweep { naive_ms, thetas }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::{Particle, SimParams, grid::Grid};
+
+/// DBSCAN settings
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClusterParams {
+    /// Neighbourhood radius
+    pub eps: f32,
+    /// Neighbours within eps, counting the particle itself, that make a core particle
+    pub min_points: usize,
+    /// Smaller clusters are dropped as noise
+    pub min_size: usize,
+}
+
+impl Default for ClusterParams {
+    fn default() -> Self {
+        Self {
+            eps: 10.0,
+            min_points: 4,
+            min_size: 10,
+        }
+    }
+}
+
+impl ClusterParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.eps.is_finite() && self.eps > 0.0) {
+            return Err(format!("cluster eps must be positive, got {}", self.eps));
+        }
+        if self.min_points == 0 {
+            return Err("cluster min points must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// Label particles with DBSCAN, finding neighbours with a grid of eps sized cells. Labels are
+/// consecutive from 0, noise is None.
+pub fn dbscan(
+    particles: &[Particle],
+    bound: Vec2,
+    eps: f32,
+    min_points: usize,
+) -> Vec<Option<u32>> {
+    let grid = Grid::new(particles, bound, eps);
+    let eps2 = eps * eps;
+    let neighbours = |i: usize| {
+        let pos = particles[i].pos;
+        grid.neighbours(pos)
+            .filter(move |&j| particles[j].pos.distance_squared(pos) <= eps2)
+    };
+
+    let mut labels = vec![None; particles.len()];
+    let mut visited = vec![false; particles.len()];
+    let mut next = 0;
+    let mut queue = VecDeque::new();
+    for i in 0..particles.len() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+        if neighbours(i).count() < min_points {
+            continue;
+        }
+        labels[i] = Some(next);
+        queue.extend(neighbours(i));
+        while let Some(j) = queue.pop_front() {
+            if labels[j].is_none() {
+                labels[j] = Some(next);
+            }
+            if visited[j] {
+                continue;
+            }
+            visited[j] = true;
+            if neighbours(j).count() >= min_points {
+                queue.extend(neighbours(j));
+            }
+        }
+        next += 1;
+    }
+    labels
+}
+
+/// Convex hull of `points`, counter-clockwise
+pub fn convex_hull(points: &[Vec2]) -> Vec<Vec2> {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let cross = |o: Vec2, a: Vec2, b: Vec2| (a - o).perp_dot(b - o);
+    let mut hull: Vec<Vec2> = vec![];
+    for pass in [points.clone(), points.into_iter().rev().collect()] {
+        let start = hull.len();
+        for p in pass {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        // The last point of each half is the first of the other
+        hull.pop();
+    }
+    hull
+}
+
+/// A group of particles found by [`ClusterTracker::update`]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Cluster {
+    /// Passed on across updates to the new cluster sharing the most particles with it
+    pub id: u64,
+    pub size: usize,
+    /// Particles of each culture
+    pub cultures: Vec<u32>,
+    pub centroid: Vec2,
+    /// Mean velocity
+    pub velocity: Vec2,
+    /// Convex hull of the particles, counter-clockwise
+    pub outline: Vec<Vec2>,
+    /// Updates since the cluster was first seen
+    pub age: u32,
+}
+
+impl Cluster {
+    /// Culture with the most particles in the cluster and its share of them
+    pub fn dominant(&self) -> (usize, f32) {
+        let (culture, &n) = self
+            .cultures
+            .iter()
+            .enumerate()
+            .max_by_key(|&(_, n)| n)
+            .unwrap_or((0, &0));
+        (culture, n as f32 / self.size.max(1) as f32)
+    }
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (culture, share) = self.dominant();
+        write!(
+            f,
+            "#{}: {} particles, {:.0}% culture {}, at ({:.0}, {:.0}) moving ({:.2}, {:.2})",
+            self.id,
+            self.size,
+            share * 100.0,
+            culture,
+            self.centroid.x,
+            self.centroid.y,
+            self.velocity.x,
+            self.velocity.y
+        )
+    }
+}
+
+/// Clusters particles on every update and matches them with the previous update's clusters, so
+/// a cluster keeps its id as it moves, grows and sheds particles.
+///
+/// A new cluster takes the id of the old cluster it shares the most particles with, largest
+/// overlaps first, so when a cluster splits the biggest part keeps the id and when two merge
+/// the id of the one contributing most survives.
+#[derive(Clone, Debug, Default)]
+pub struct ClusterTracker {
+    pub params: ClusterParams,
+    clusters: Vec<Cluster>,
+    /// Index into `clusters` of each particle's cluster
+    labels: Vec<Option<usize>>,
+    next_id: u64,
+}
+
+impl ClusterTracker {
+    pub fn new(params: ClusterParams) -> Self {
+        Self {
+            params,
+            ..Default::default()
+        }
+    }
+
+    pub fn clusters(&self) -> &[Cluster] {
+        &self.clusters
+    }
+
+    /// Cluster of each particle, as an index into [`ClusterTracker::clusters`]
+    pub fn labels(&self) -> &[Option<usize>] {
+        &self.labels
+    }
+
+    /// Forget the tracked clusters, e.g. after a respawn
+    pub fn reset(&mut self) {
+        self.clusters.clear();
+        self.labels.clear();
+    }
+
+    /// Cluster culture-ordered `particles` and match them with the last update
+    pub fn update(&mut self, simp: &SimParams, bound: Vec2, particles: &[Particle]) -> &[Cluster] {
+        let ClusterParams {
+            eps,
+            min_points,
+            min_size,
+        } = self.params;
+        let raw = dbscan(particles, bound, eps, min_points);
+
+        let mut members = vec![];
+        for (i, label) in raw.iter().enumerate() {
+            if let Some(l) = label {
+                let l = *l as usize;
+                if l >= members.len() {
+                    members.resize(l + 1, vec![]);
+                }
+                members[l].push(i);
+            }
+        }
+        members.retain(|m| m.len() >= min_size);
+        members.sort_by_key(|m| std::cmp::Reverse(m.len()));
+
+        let ids = self.match_ids(&members);
+        let cs = simp.culture_size.max(1) as usize;
+        let mut labels = vec![None; particles.len()];
+        self.clusters = members
+            .iter()
+            .zip(ids)
+            .enumerate()
+            .map(|(c, (m, (id, age)))| {
+                let mut cultures = vec![0; simp.num_cultures as usize];
+                let (mut pos, mut vel) = (Vec2::ZERO, Vec2::ZERO);
+                for &i in m {
+                    labels[i] = Some(c);
+                    if let Some(n) = cultures.get_mut(i / cs) {
+                        *n += 1;
+                    }
+                    pos += particles[i].pos;
+                    vel += particles[i].vel;
+                }
+                let n = m.len() as f32;
+                let points = m.iter().map(|&i| particles[i].pos).collect::<Vec<_>>();
+                Cluster {
+                    id,
+                    size: m.len(),
+                    cultures,
+                    centroid: pos / n,
+                    velocity: vel / n,
+                    outline: convex_hull(&points),
+                    age,
+                }
+            })
+            .collect();
+        self.labels = labels;
+        &self.clusters
+    }
+
+    /// Id and age for each new cluster, reusing the ids of the clusters they overlap most
+    fn match_ids(&mut self, members: &[Vec<usize>]) -> Vec<(u64, u32)> {
+        let mut overlaps = HashMap::<(usize, usize), usize>::new();
+        for (new, m) in members.iter().enumerate() {
+            for &i in m {
+                if let Some(Some(old)) = self.labels.get(i) {
+                    *overlaps.entry((new, *old)).or_default() += 1;
+                }
+            }
+        }
+        let mut overlaps = overlaps.into_iter().collect::<Vec<_>>();
+        overlaps.sort_by_key(|&(pair, n)| (std::cmp::Reverse(n), pair));
+
+        let mut ids = vec![None; members.len()];
+        let mut taken = vec![false; self.clusters.len()];
+        for ((new, old), _) in overlaps {
+            if ids[new].is_none() && !taken[old] {
+                taken[old] = true;
+                let old = &self.clusters[old];
+                ids[new] = Some((old.id, old.age + 1));
+            }
+        }
+        ids.into_iter()
+            .map(|id| {
+                id.unwrap_or_else(|| {
+                    self.next_id += 1;
+                    (self.next_id, 0)
+                })
+            })
+            .collect()
+    }
+}
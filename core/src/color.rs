@@ -0,0 +1,14 @@
+use rand::Rng;
+use random_color::RandomColor;
+
+/// Random bright rgba color with components in [0, 1]
+pub fn random_color(rng: &mut impl Rng) -> [f32; 4] {
+    RandomColor::new()
+        .seed(rng.random::<u64>())
+        .to_f32_rgba_array()
+}
+
+/// Random color for each culture
+pub fn random_colors(num_cultures: usize, rng: &mut impl Rng) -> Vec<[f32; 4]> {
+    (0..num_cultures).map(|_| random_color(rng)).collect()
+}
@@ -0,0 +1,124 @@
+use std::{
+    f32::consts::PI,
+    io::{self, Write},
+};
+
+use glam::Vec2;
+use serde::Serialize;
+
+use crate::{Particle, SimParams, grid::Grid};
+
+/// Pair distance histogram and g(r) of one culture pair
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PairHistogram {
+    pub a: u32,
+    pub b: u32,
+    /// Ordered pairs of distinct particles, one from `a` and one from `b`, per distance bin
+    pub counts: Vec<u64>,
+    /// Counts over those expected of uniformly spread particles, 1 for no correlation
+    pub g: Vec<f32>,
+}
+
+/// Pair correlation g(r) of every culture pair, binned up to a radius.
+///
+/// There is no edge correction, so g falls below 1 at larger radii in small or crowded worlds,
+/// where a good part of each shell lies outside the bound.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PairCorrelation {
+    pub radius: f32,
+    pub num_bins: usize,
+    /// Pairs with `a <= b`, in row-major order
+    pub pairs: Vec<PairHistogram>,
+}
+
+impl PairCorrelation {
+    /// Bin the distances between culture-ordered particles closer than `radius`, finding them
+    /// with a grid of radius sized cells
+    pub fn compute(
+        params: &SimParams,
+        bound: Vec2,
+        particles: &[Particle],
+        radius: f32,
+        num_bins: usize,
+    ) -> Self {
+        assert!(
+            radius > 0.0 && num_bins > 0,
+            "need a radius and at least one bin"
+        );
+        let k = params.num_cultures as usize;
+        let cs = params.culture_size.max(1) as usize;
+        let dr = radius / num_bins as f32;
+        let grid = Grid::new(particles, bound, radius);
+        let mut counts = vec![0u64; k * k * num_bins];
+        for (i, p) in particles.iter().enumerate() {
+            let ca = i / cs;
+            for j in grid.neighbours(p.pos) {
+                let d = p.pos.distance(particles[j].pos);
+                if j == i || d >= radius {
+                    continue;
+                }
+                let bin = ((d / dr) as usize).min(num_bins - 1);
+                counts[((ca * k) + j / cs) * num_bins + bin] += 1;
+            }
+        }
+
+        let area = bound.x * bound.y;
+        let sizes = (0..k)
+            .map(|c| particles.len().saturating_sub(c * cs).min(cs) as f32)
+            .collect::<Vec<_>>();
+        let mut pairs = vec![];
+        for a in 0..k {
+            for b in a..k {
+                let counts = counts[(a * k + b) * num_bins..][..num_bins].to_vec();
+                let others = sizes[b] - if a == b { 1.0 } else { 0.0 };
+                let density = others / area;
+                let g = counts
+                    .iter()
+                    .enumerate()
+                    .map(|(bin, &n)| {
+                        let (r0, r1) = (bin as f32 * dr, (bin + 1) as f32 * dr);
+                        let expected = sizes[a] * density * PI * (r1 * r1 - r0 * r0);
+                        if expected > 0.0 {
+                            n as f32 / expected
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect();
+                pairs.push(PairHistogram {
+                    a: a as u32,
+                    b: b as u32,
+                    counts,
+                    g,
+                });
+            }
+        }
+        Self {
+            radius,
+            num_bins,
+            pairs,
+        }
+    }
+
+    /// Distance at the center of a bin
+    pub fn r(&self, bin: usize) -> f32 {
+        (bin as f32 + 0.5) * self.radius / self.num_bins as f32
+    }
+
+    /// Histogram of a culture pair, in either order
+    pub fn pair(&self, a: u32, b: u32) -> Option<&PairHistogram> {
+        let (a, b) = (a.min(b), a.max(b));
+        self.pairs.iter().find(|p| p.a == a && p.b == b)
+    }
+
+    /// One row per pair and bin, `a,b,r,count,g`
+    pub fn write_csv(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "a,b,r,count,g")?;
+        for pair in &self.pairs {
+            for (bin, (n, g)) in pair.counts.iter().zip(&pair.g).enumerate() {
+                writeln!(out, "{},{},{},{},{}", pair.a, pair.b, self.r(bin), n, g)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use glam::{Vec2, vec2};
+use quadtree::{BHQuadtree, WeightedPoint};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, StandardNormal};
+
+use crate::{
+    SimParams,
+    sim::{Cursor, Particle, Simulator, pair_force},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuBackend {
+    /// Exact O(n^2) force computation
+    Naive,
+    /// Barnes-Hut quadtree approximation
+    BarnesHut,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CpuOptions {
+    pub backend: CpuBackend,
+    /// Barnes-Hut opening angle
+    pub theta: f32,
+    /// World size, particles live in `[0, bound]`
+    pub bound: Vec2,
+    pub seed: Option<u64>,
+}
+
+impl Default for CpuOptions {
+    fn default() -> Self {
+        Self {
+            backend: CpuBackend::BarnesHut,
+            theta: 0.9,
+            bound: vec2(1000.0, 800.0),
+            seed: None,
+        }
+    }
+}
+
+/// Clamp the length of `v` to `max`, 0 meaning no limit
+fn limit(v: Vec2, max: f32) -> Vec2 {
+    if max > 0.0 {
+        v.clamp_length_max(max)
+    } else {
+        v
+    }
+}
+
+/// CPU simulation with either exact or Barnes-Hut forces
+pub struct CpuSim {
+    params: SimParams,
+    opts: CpuOptions,
+    particles: Vec<Particle>,
+    forces: Vec<Vec2>,
+    quadtrees: Vec<BHQuadtree>,
+    cursor: Option<Cursor>,
+    rng: StdRng,
+    i: u64,
+}
+
+impl CpuSim {
+    pub fn new(params: SimParams, opts: CpuOptions) -> Self {
+        let mut rng = match opts.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(&mut rand::rng()),
+        };
+        let particles = spawn_particles(params.num_particles() as usize, opts.bound, &mut rng);
+        Self::from_particles(params, opts, particles, rng)
+    }
+
+    pub fn naive(params: SimParams) -> Self {
+        let opts = CpuOptions {
+            backend: CpuBackend::Naive,
+            ..Default::default()
+        };
+        Self::new(params, opts)
+    }
+
+    pub fn barnes_hut(params: SimParams, theta: f32) -> Self {
+        let opts = CpuOptions {
+            backend: CpuBackend::BarnesHut,
+            theta,
+            ..Default::default()
+        };
+        Self::new(params, opts)
+    }
+
+    /// Create a simulation starting from the given particles instead of a random spawn
+    pub fn with_particles(params: SimParams, opts: CpuOptions, particles: Vec<Particle>) -> Self {
+        assert_eq!(particles.len(), params.num_particles() as usize);
+        let rng = match opts.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(&mut rand::rng()),
+        };
+        Self::from_particles(params, opts, particles, rng)
+    }
+
+    fn from_particles(
+        params: SimParams,
+        opts: CpuOptions,
+        particles: Vec<Particle>,
+        rng: StdRng,
+    ) -> Self {
+        let quadtrees = (0..params.num_cultures)
+            .map(|_| BHQuadtree::new(10, 8, opts.theta))
+            .collect();
+        Self {
+            forces: vec![Vec2::ZERO; particles.len()],
+            particles,
+            quadtrees,
+            cursor: None,
+            rng,
+            i: 0,
+            params,
+            opts,
+        }
+    }
+
+    pub fn options(&self) -> &CpuOptions {
+        &self.opts
+    }
+
+    pub fn set_backend(&mut self, backend: CpuBackend) {
+        self.opts.backend = backend;
+    }
+
+    pub fn set_cursor(&mut self, cursor: Option<Cursor>) {
+        self.cursor = cursor;
+    }
+
+    /// Mesh and repulsion forces on each particle from the last force computation
+    pub fn forces(&self) -> &[Vec2] {
+        &self.forces
+    }
+
+    fn culture_size(&self) -> usize {
+        self.params.culture_size as usize
+    }
+
+    /// Compute mesh forces exactly by visiting every pair of particles
+    pub fn compute_force_naive(&mut self) {
+        let cs = self.culture_size();
+        let aoe2 = self.params.aoe * self.params.aoe;
+        let repulsion = self.params.repulsion;
+        for (i, f) in self.forces.iter_mut().enumerate() {
+            let p1 = self.particles[i].pos;
+            let c1 = i / cs;
+            let force = self
+                .particles
+                .iter()
+                .enumerate()
+                .fold(Vec2::ZERO, |acc, (j, p2)| {
+                    let g = self.params.mesh.get(c1, j / cs);
+                    acc + pair_force(p1, p2.pos, g, aoe2, repulsion)
+                });
+            *f = force;
+        }
+    }
+
+    /// Compute mesh forces with a Barnes-Hut quadtree per culture
+    pub fn compute_force(&mut self) {
+        let cs = self.culture_size();
+        let aoe2 = self.params.aoe * self.params.aoe;
+
+        // Regenerate quadtrees
+        for (qt, culture) in self.quadtrees.iter_mut().zip(self.particles.chunks(cs)) {
+            let items = culture
+                .iter()
+                .map(|p| WeightedPoint::new(p.pos, 1.0))
+                .collect::<Vec<_>>();
+            qt.build(items);
+        }
+
+        let repulsion = self.params.repulsion;
+        let num_cultures = self.quadtrees.len();
+        for (i, f) in self.forces.iter_mut().enumerate() {
+            let p1 = self.particles[i].pos;
+            let c1 = i / cs;
+            let force = (0..num_cultures).fold(Vec2::ZERO, |acc, c2| {
+                let g = self.params.mesh.get(c1, c2);
+                acc + self.quadtrees[c2].accumulate(p1, |wp| {
+                    pair_force(p1, wp.pos, g, aoe2, repulsion) * wp.mass
+                })
+            });
+            *f = force;
+        }
+    }
+
+    fn apply_forces(&mut self, tau: f32) {
+        let bound = self.opts.bound;
+        let params = &self.params;
+        let t = self.i as f32;
+        let sigma = params.temperature.sqrt();
+        let rng = &mut self.rng;
+        for (particle, &mesh_force) in self.particles.iter_mut().zip(&self.forces) {
+            let cursor_force = self.cursor.map_or(Vec2::ZERO, |c| c.force(particle.pos));
+            let force = mesh_force + cursor_force + params.fields.force(particle.pos, t);
+            let force = limit(force, params.max_force);
+            particle.vel = (particle.vel + force) * params.damping;
+            if sigma > 0.0 {
+                let noise: [f32; 2] = [StandardNormal.sample(rng), StandardNormal.sample(rng)];
+                particle.vel += Vec2::from(noise) * sigma;
+            }
+            particle.vel = limit(particle.vel, params.max_speed);
+            if particle.pos.x <= 0. {
+                particle.vel.x = particle.vel.x.abs();
+                particle.pos.x = 0.;
+            } else if particle.pos.x >= bound.x {
+                particle.vel.x = -particle.vel.x.abs();
+                particle.pos.x = bound.x;
+            }
+            if particle.pos.y <= 0. {
+                particle.vel.y = particle.vel.y.abs();
+                particle.pos.y = 0.;
+            } else if particle.pos.y >= bound.y {
+                particle.vel.y = -particle.vel.y.abs();
+                particle.pos.y = bound.y;
+            }
+            let prev = particle.pos;
+            particle.pos += particle.vel * tau;
+            params
+                .obstacles
+                .resolve(prev, &mut particle.pos, &mut particle.vel);
+        }
+    }
+}
+
+impl Simulator for CpuSim {
+    fn step(&mut self, dt: f32) {
+        match self.opts.backend {
+            CpuBackend::Naive => self.compute_force_naive(),
+            CpuBackend::BarnesHut => self.compute_force(),
+        }
+        self.apply_forces(dt);
+        self.i += 1;
+    }
+
+    fn step_count(&self) -> u64 {
+        self.i
+    }
+
+    fn particles(&mut self) -> &[Particle] {
+        &self.particles
+    }
+
+    fn params(&self) -> &SimParams {
+        &self.params
+    }
+
+    fn set_params(&mut self, params: SimParams) {
+        let same_shape = params.num_cultures == self.params.num_cultures
+            && params.culture_size == self.params.culture_size;
+        if let Some(bound) = params.bound {
+            self.opts.bound = bound;
+        }
+        if !same_shape {
+            let n = params.num_particles() as usize;
+            self.particles = spawn_particles(n, self.opts.bound, &mut self.rng);
+            self.forces = vec![Vec2::ZERO; n];
+            self.quadtrees = (0..params.num_cultures)
+                .map(|_| BHQuadtree::new(10, 8, self.opts.theta))
+                .collect();
+            self.i = 0;
+        }
+        self.params = params;
+    }
+}
+
+fn spawn_particles(n: usize, bound: Vec2, rng: &mut impl Rng) -> Vec<Particle> {
+    std::iter::repeat_with(|| Particle {
+        pos: vec2(
+            rng.random_range(0.0..bound.x),
+            rng.random_range(0.0..bound.y),
+        ),
+        vel: Vec2::ZERO,
+    })
+    .take(n)
+    .collect()
+}
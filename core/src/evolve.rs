@@ -0,0 +1,425 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+};
+
+use glam::Vec2;
+use rand::{SeedableRng, rngs::StdRng};
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Mesh, Particle, SimParams, Simulator,
+    cluster::{Cluster, ClusterParams, ClusterTracker},
+    sweep,
+};
+
+/// Std of a uniform draw in [-1, 1], the spread of the first generation
+const UNIFORM_STD: f32 = 0.577;
+
+/// The clusters of a run at one step
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub step: u64,
+    pub particles: Vec<Particle>,
+    pub clusters: Vec<Cluster>,
+    /// Cluster of each particle, as an index into `clusters`
+    pub labels: Vec<Option<usize>>,
+}
+
+/// What a fitness function sees of a run: the tracked clusters every few steps over the end of
+/// the run
+#[derive(Clone, Debug, Default)]
+pub struct Trajectory {
+    pub frames: Vec<Frame>,
+    /// The energy exploded and the run was cut short
+    pub exploded: bool,
+}
+
+/// Check a recording scores something, the warmup has to end before the run does
+pub fn validate_record(steps: u32, warmup: u32) -> Result<(), String> {
+    if warmup >= steps {
+        return Err(format!(
+            "warmup must be fewer steps than the run, got {warmup} of {steps}"
+        ));
+    }
+    Ok(())
+}
+
+/// Run `sim` for `steps` steps with `advance`, which steps it a given number of times, tracking
+/// clusters every `every` steps after the first `warmup`. Stops early if the energy explodes.
+/// Panics if the warmup doesn't end before the run, see [`validate_record`].
+pub fn record<S: Simulator>(
+    sim: &mut S,
+    steps: u32,
+    warmup: u32,
+    every: u32,
+    bound: Vec2,
+    clusters: ClusterParams,
+    advance: impl FnMut(&mut S, u32),
+) -> Trajectory {
+    if let Err(e) = validate_record(steps, warmup) {
+        panic!("{e}");
+    }
+    let mut tracker = ClusterTracker::new(clusters);
+    let mut frames = vec![];
+    let exploded = sweep::run_observed(sim, steps, warmup, every, advance, |sim| {
+        let simp = sim.params().clone();
+        let step = sim.step_count();
+        let particles = sim.particles().to_vec();
+        tracker.update(&simp, bound, &particles);
+        frames.push(Frame {
+            step,
+            particles,
+            clusters: tracker.clusters().to_vec(),
+            labels: tracker.labels().to_vec(),
+        });
+    });
+    Trajectory { frames, exploded }
+}
+
+/// Scores a run, higher is better. Implement it to search for other structures.
+pub trait Fitness: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn score(&self, trajectory: &Trajectory) -> f32;
+}
+
+/// Mean number of clusters moving faster than `min_speed`
+#[derive(Clone, Copy, Debug)]
+pub struct MovingClusters {
+    pub min_speed: f32,
+}
+
+impl Default for MovingClusters {
+    fn default() -> Self {
+        Self { min_speed: 0.05 }
+    }
+}
+
+impl Fitness for MovingClusters {
+    fn name(&self) -> &str {
+        "moving"
+    }
+
+    fn score(&self, trajectory: &Trajectory) -> f32 {
+        let moving = trajectory
+            .frames
+            .iter()
+            .map(|f| {
+                f.clusters
+                    .iter()
+                    .filter(|c| c.velocity.length() >= self.min_speed)
+                    .count()
+            })
+            .sum::<usize>();
+        moving as f32 / trajectory.frames.len().max(1) as f32
+    }
+}
+
+/// Share of the particles in the last frame whose cluster has been around since the first, in
+/// [0, 1]. Younger clusters count in proportion to their age.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Persistence;
+
+impl Fitness for Persistence {
+    fn name(&self) -> &str {
+        "persistence"
+    }
+
+    fn score(&self, trajectory: &Trajectory) -> f32 {
+        let (Some(last), n) = (trajectory.frames.last(), trajectory.frames.len()) else {
+            return 0.0;
+        };
+        let span = (n - 1).max(1);
+        let aged = last
+            .clusters
+            .iter()
+            .map(|c| c.size * (c.age as usize).min(span))
+            .sum::<usize>();
+        aged as f32 / (last.particles.len().max(1) * span) as f32
+    }
+}
+
+/// How much of the particles' motion is shared with their cluster, in [0, 1]: the length of
+/// each cluster's summed velocity over the summed speed of every particle, averaged over frames.
+/// 1 when every particle rides in a cluster moving as one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Coherence;
+
+impl Fitness for Coherence {
+    fn name(&self) -> &str {
+        "coherence"
+    }
+
+    fn score(&self, trajectory: &Trajectory) -> f32 {
+        let total = trajectory
+            .frames
+            .iter()
+            .map(|f| {
+                let mut momenta = vec![Vec2::ZERO; f.clusters.len()];
+                let mut speed = 0.0;
+                for (p, label) in f.particles.iter().zip(&f.labels) {
+                    speed += p.vel.length();
+                    if let Some(c) = label {
+                        momenta[*c] += p.vel;
+                    }
+                }
+                let shared = momenta.iter().map(|m| m.length()).sum::<f32>();
+                if speed > 0.0 { shared / speed } else { 0.0 }
+            })
+            .sum::<f32>();
+        total / trajectory.frames.len().max(1) as f32
+    }
+}
+
+/// Mean share of particles in a cluster, in [0, 1]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Clustered;
+
+impl Fitness for Clustered {
+    fn name(&self) -> &str {
+        "clustered"
+    }
+
+    fn score(&self, trajectory: &Trajectory) -> f32 {
+        let total = trajectory
+            .frames
+            .iter()
+            .map(|f| f.labels.iter().flatten().count() as f32 / f.labels.len().max(1) as f32)
+            .sum::<f32>();
+        total / trajectory.frames.len().max(1) as f32
+    }
+}
+
+/// Built in fitness functions by name
+pub fn fitness_by_name(name: &str) -> Option<Arc<dyn Fitness>> {
+    match name {
+        "moving" => Some(Arc::new(MovingClusters::default())),
+        "persistence" => Some(Arc::new(Persistence)),
+        "coherence" => Some(Arc::new(Coherence)),
+        "clustered" => Some(Arc::new(Clustered)),
+        _ => None,
+    }
+}
+
+/// A weighted fitness function, `name` or `name:weight` on the command line
+#[derive(Clone)]
+pub struct FitnessTerm {
+    pub fitness: Arc<dyn Fitness>,
+    pub weight: f32,
+}
+
+impl FromStr for FitnessTerm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, weight) = match s.split_once(':') {
+            Some((name, weight)) => (
+                name,
+                weight
+                    .parse()
+                    .map_err(|e| format!("invalid weight {weight:?}: {e}"))?,
+            ),
+            None => (s, 1.0),
+        };
+        let fitness = fitness_by_name(name).ok_or_else(|| {
+            format!(
+                "unknown fitness {name:?}, expected moving, persistence, coherence or clustered"
+            )
+        })?;
+        Ok(Self { fitness, weight })
+    }
+}
+
+impl fmt::Debug for FitnessTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.fitness.name(), self.weight)
+    }
+}
+
+/// Score of each term, 0 for a run that exploded
+pub fn score_terms(terms: &[FitnessTerm], trajectory: &Trajectory) -> Vec<f32> {
+    terms
+        .iter()
+        .map(|t| {
+            if trajectory.exploded {
+                0.0
+            } else {
+                t.fitness.score(trajectory)
+            }
+        })
+        .collect()
+}
+
+/// Weighted sum of term scores
+pub fn total_score(terms: &[FitnessTerm], scores: &[f32]) -> f32 {
+    terms.iter().zip(scores).map(|(t, s)| t.weight * s).sum()
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EvolveParams {
+    /// Candidates per generation, elites included
+    pub population: usize,
+    /// Best candidates carried over to the next generation, which the search distribution is
+    /// fit to
+    pub elites: usize,
+    /// Floor on the per coefficient std, so the search never stops exploring
+    pub min_sigma: f32,
+    /// Weight of the elites in the update of the search distribution
+    pub learning_rate: f32,
+}
+
+impl Default for EvolveParams {
+    fn default() -> Self {
+        Self {
+            population: 24,
+            elites: 6,
+            min_sigma: 0.05,
+            learning_rate: 0.7,
+        }
+    }
+}
+
+impl EvolveParams {
+    /// Checks there is at least one elite and room for new candidates next to them
+    pub fn validate(&self) -> Result<(), String> {
+        if self.elites == 0 || self.elites >= self.population {
+            return Err(format!(
+                "need 1 or more elites and fewer than the population, got {} of {}",
+                self.elites, self.population
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A scored mesh
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub mesh: Mesh,
+    pub score: f32,
+    /// Score of each fitness term
+    pub scores: Vec<f32>,
+    /// Generation the mesh was drawn in
+    pub generation: u32,
+}
+
+/// Evolution strategy over gravity mesh coefficients in the cross-entropy/CMA family: each
+/// generation samples meshes from a gaussian with a per coefficient mean and std, keeps the best
+/// and moves the gaussian towards them. The first generation is uniform like [`Mesh::random`].
+///
+/// Drive it with [`Evolution::ask`] for meshes to evaluate and [`Evolution::tell`] with their
+/// scores.
+pub struct Evolution {
+    pub params: EvolveParams,
+    num_cultures: usize,
+    mean: Vec<f32>,
+    sigma: Vec<f32>,
+    elites: Vec<Candidate>,
+    generation: u32,
+    rng: StdRng,
+}
+
+impl Evolution {
+    pub fn new(num_cultures: usize, params: EvolveParams, seed: u64) -> Self {
+        let n = num_cultures * num_cultures;
+        Self {
+            params,
+            num_cultures,
+            mean: vec![0.0; n],
+            sigma: vec![UNIFORM_STD; n],
+            elites: vec![],
+            generation: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Best candidates so far, best first
+    pub fn elites(&self) -> &[Candidate] {
+        &self.elites
+    }
+
+    /// Mean per coefficient std of the search distribution
+    pub fn mean_sigma(&self) -> f32 {
+        self.sigma.iter().sum::<f32>() / self.sigma.len().max(1) as f32
+    }
+
+    /// New meshes for this generation, filling the population up after the elites
+    pub fn ask(&mut self) -> Vec<Mesh> {
+        let count = self.params.population.saturating_sub(self.elites.len());
+        if self.generation == 0 {
+            return (0..count)
+                .map(|_| Mesh::random(self.num_cultures, &mut self.rng))
+                .collect();
+        }
+        (0..count)
+            .map(|_| {
+                let values = self
+                    .mean
+                    .iter()
+                    .zip(&self.sigma)
+                    .map(|(&mean, &sigma)| {
+                        let normal = Normal::new(mean, sigma).expect("sigma is positive");
+                        normal.sample(&mut self.rng).clamp(-1.0, 1.0)
+                    })
+                    .collect();
+                Mesh::from_flat(values).expect("square mesh")
+            })
+            .collect()
+    }
+
+    /// Rank the scored meshes from [`Evolution::ask`] with the elites, keep the best and move the
+    /// search distribution towards them
+    pub fn tell(&mut self, candidates: Vec<Candidate>) {
+        let mut pool = std::mem::take(&mut self.elites);
+        pool.extend(candidates);
+        pool.sort_by(|a, b| b.score.total_cmp(&a.score));
+        pool.truncate(self.params.elites.max(1));
+        self.elites = pool;
+
+        let n = self.elites.len() as f32;
+        let lr = self.params.learning_rate.clamp(0.0, 1.0);
+        for i in 0..self.mean.len() {
+            let mean = self.elites.iter().map(|c| c.mesh.as_flat()[i]).sum::<f32>() / n;
+            let var = self
+                .elites
+                .iter()
+                .map(|c| (c.mesh.as_flat()[i] - mean).powi(2))
+                .sum::<f32>()
+                / n;
+            self.mean[i] += lr * (mean - self.mean[i]);
+            self.sigma[i] = (self.sigma[i] + lr * (var.sqrt() - self.sigma[i]))
+                .max(self.params.min_sigma.max(1e-6));
+        }
+        self.generation += 1;
+    }
+}
+
+/// A mesh found by the search, saved with its scores. The sim params are flattened in, so the
+/// json loads as a SimParams blob.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Preset {
+    pub score: f32,
+    /// Score of each fitness term by name
+    pub scores: BTreeMap<String, f32>,
+    pub generation: u32,
+    #[serde(flatten)]
+    pub params: SimParams,
+}
+
+impl Preset {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self).map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,113 @@
+use glam::{Vec2, Vec3, vec2, vec3};
+use serde::{Deserialize, Serialize};
+
+/// Finite difference step used to take the curl of the noise potential
+const CURL_EPS: f32 = 0.01;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct PointField {
+    pub center: Vec2,
+    pub strength: f32,
+}
+
+/// Divergence free flow taken as the curl of a time varying noise potential
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct FlowField {
+    pub strength: f32,
+    /// Noise frequency in world units
+    pub scale: f32,
+    /// Noise time frequency in steps
+    pub speed: f32,
+}
+
+/// Global external forces applied to every particle on top of the gravity mesh
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ForceFields {
+    /// Uniform acceleration
+    #[serde(default)]
+    pub gravity: Vec2,
+    /// Constant magnitude pull towards the center, or push for negative strength
+    #[serde(default)]
+    pub attractor: PointField,
+    /// Constant magnitude swirl around the center, counterclockwise for positive strength
+    #[serde(default)]
+    pub vortex: PointField,
+    #[serde(default)]
+    pub flow: FlowField,
+}
+
+impl ForceFields {
+    /// Inactive fields with the point fields centered on `center`
+    pub fn centered(center: Vec2) -> Self {
+        let point = PointField {
+            center,
+            strength: 0.0,
+        };
+        Self {
+            gravity: Vec2::ZERO,
+            attractor: point,
+            vortex: point,
+            flow: FlowField {
+                strength: 0.0,
+                scale: 0.005,
+                speed: 0.01,
+            },
+        }
+    }
+
+    /// Get the external force on a particle at `pos` at step `t`
+    pub fn force(&self, pos: Vec2, t: f32) -> Vec2 {
+        let mut force = self.gravity;
+
+        if self.attractor.strength != 0.0 {
+            let dir = (self.attractor.center - pos).normalize_or_zero();
+            force += dir * self.attractor.strength;
+        }
+
+        if self.vortex.strength != 0.0 {
+            let dir = (self.vortex.center - pos).normalize_or_zero();
+            force += dir.perp() * self.vortex.strength;
+        }
+
+        if self.flow.strength != 0.0 {
+            let p = vec3(
+                pos.x * self.flow.scale,
+                pos.y * self.flow.scale,
+                t * self.flow.speed,
+            );
+            let dx = value_noise(p + Vec3::X * CURL_EPS) - value_noise(p - Vec3::X * CURL_EPS);
+            let dy = value_noise(p + Vec3::Y * CURL_EPS) - value_noise(p - Vec3::Y * CURL_EPS);
+            let curl = vec2(dy, -dx) / (2.0 * CURL_EPS);
+            force += curl * self.flow.strength;
+        }
+
+        force
+    }
+}
+
+/// PCG hash, matches `pcg` in compute.wgsl
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn hash3(x: i32, y: i32, z: i32) -> f32 {
+    let h = pcg(x as u32 ^ pcg(y as u32 ^ pcg(z as u32)));
+    h as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+/// Smoothly interpolated lattice noise in [-1, 1], matches `value_noise` in compute.wgsl
+fn value_noise(p: Vec3) -> f32 {
+    let i = p.floor();
+    let f = p - i;
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let (x, y, z) = (i.x as i32, i.y as i32, i.z as i32);
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(hash3(x, y, z), hash3(x + 1, y, z), u.x);
+    let x10 = lerp(hash3(x, y + 1, z), hash3(x + 1, y + 1, z), u.x);
+    let x01 = lerp(hash3(x, y, z + 1), hash3(x + 1, y, z + 1), u.x);
+    let x11 = lerp(hash3(x, y + 1, z + 1), hash3(x + 1, y + 1, z + 1), u.x);
+    lerp(lerp(x00, x10, u.y), lerp(x01, x11, u.y), u.z)
+}
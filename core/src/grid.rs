@@ -0,0 +1,55 @@
+use glam::Vec2;
+
+use crate::Particle;
+
+/// Particle indices binned into square cells, for neighbour searches within a cell size
+pub struct Grid {
+    cell_size: f32,
+    size: Vec2,
+    w: usize,
+    h: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    /// Bin `particles` into cells of `cell_size` covering `bound`. Particles outside the bound
+    /// go into the nearest edge cell, non-finite ones into the first. Cells are made at least as
+    /// wide as a particle's share of the area, so a tiny cell size doesn't allocate a cell per
+    /// pixel.
+    pub fn new(particles: &[Particle], bound: Vec2, cell_size: f32) -> Self {
+        let cell_size = cell_size.max((bound.x * bound.y / particles.len().max(1) as f32).sqrt());
+        let size = (bound / cell_size).ceil().max(Vec2::ONE);
+        let mut grid = Self {
+            cell_size,
+            size,
+            w: size.x as usize,
+            h: size.y as usize,
+            cells: vec![],
+        };
+        grid.cells = vec![vec![]; grid.w * grid.h];
+        for (i, p) in particles.iter().enumerate() {
+            let (x, y) = grid.cell(p.pos);
+            grid.cells[y * grid.w + x].push(i);
+        }
+        grid
+    }
+
+    fn cell(&self, pos: Vec2) -> (usize, usize) {
+        let pos = if pos.is_finite() { pos } else { Vec2::ZERO };
+        let c = (pos / self.cell_size)
+            .floor()
+            .clamp(Vec2::ZERO, self.size - 1.0);
+        (c.x as usize, c.y as usize)
+    }
+
+    /// Indices in the cell of `pos` and the 8 around it, a superset of the particles within a
+    /// cell size of it
+    pub fn neighbours(&self, pos: Vec2) -> impl Iterator<Item = usize> + '_ {
+        let (cx, cy) = self.cell(pos);
+        let xs = cx.saturating_sub(1)..=(cx + 1).min(self.w - 1);
+        (cy.saturating_sub(1)..=(cy + 1).min(self.h - 1)).flat_map(move |y| {
+            xs.clone()
+                .flat_map(move |x| self.cells[y * self.w + x].iter().copied())
+        })
+    }
+}
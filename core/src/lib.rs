@@ -0,0 +1,19 @@
+pub mod accuracy;
+pub mod cluster;
+pub mod color;
+pub mod correlation;
+pub mod cpu;
+pub mod evolve;
+pub mod field;
+pub mod grid;
+pub mod mesh;
+pub mod obstacle;
+pub mod params;
+pub mod sim;
+pub mod stability;
+pub mod stats;
+pub mod sweep;
+
+pub use mesh::Mesh;
+pub use params::{ParamsError, SimParams};
+pub use sim::{Particle, Simulator};
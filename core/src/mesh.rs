@@ -0,0 +1,103 @@
+use rand::Rng;
+use rand_distr::{Distribution, Uniform};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Square matrix of gravitational constants where `get(c1, c2)` is the pull culture `c2` exerts
+/// on culture `c1`.
+///
+/// Serializes as a flat row-major list and deserializes from either a flat list or a list of rows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mesh {
+    num_cultures: usize,
+    values: Vec<f32>,
+}
+
+impl Mesh {
+    /// Create a mesh from flat row-major values. Returns `None` if the length isn't a square.
+    pub fn from_flat(values: Vec<f32>) -> Option<Self> {
+        let num_cultures = (values.len() as f64).sqrt().round() as usize;
+        (num_cultures * num_cultures == values.len()).then_some(Self {
+            num_cultures,
+            values,
+        })
+    }
+
+    /// Create a mesh from rows. Returns `None` if the rows don't form a square matrix.
+    pub fn from_rows(rows: Vec<Vec<f32>>) -> Option<Self> {
+        let num_cultures = rows.len();
+        if rows.iter().any(|row| row.len() != num_cultures) {
+            return None;
+        }
+        Some(Self {
+            num_cultures,
+            values: rows.concat(),
+        })
+    }
+
+    /// Uniformly random mesh in [-1, 1]
+    pub fn random(num_cultures: usize, rng: &mut impl Rng) -> Self {
+        let distr = Uniform::new_inclusive(-1., 1.).unwrap();
+        Self {
+            num_cultures,
+            values: distr
+                .sample_iter(rng)
+                .take(num_cultures * num_cultures)
+                .collect(),
+        }
+    }
+
+    pub fn num_cultures(&self) -> usize {
+        self.num_cultures
+    }
+
+    pub fn get(&self, c1: usize, c2: usize) -> f32 {
+        self.values[c1 * self.num_cultures + c2]
+    }
+
+    pub fn set(&mut self, c1: usize, c2: usize, g: f32) {
+        self.values[c1 * self.num_cultures + c2] = g;
+    }
+
+    /// Row-major values, the layout of the gravity mesh storage buffer
+    pub fn as_flat(&self) -> &[f32] {
+        &self.values
+    }
+
+    pub fn as_flat_mut(&mut self) -> &mut [f32] {
+        &mut self.values
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[f32]> {
+        self.values.chunks(self.num_cultures.max(1))
+    }
+}
+
+impl Serialize for Mesh {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.values.serialize(serializer)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MeshRepr {
+    Flat(Vec<f32>),
+    Rows(Vec<Vec<f32>>),
+}
+
+impl<'de> Deserialize<'de> for Mesh {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        match MeshRepr::deserialize(deserializer)? {
+            MeshRepr::Flat(values) => {
+                let len = values.len();
+                Self::from_flat(values).ok_or_else(|| {
+                    D::Error::custom(format!("flat mesh of length {len} is not square"))
+                })
+            }
+            MeshRepr::Rows(rows) => Self::from_rows(rows)
+                .ok_or_else(|| D::Error::custom("mesh rows do not form a square matrix")),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use glam::{Vec2, vec2};
+use serde::{Deserialize, Serialize};
+
+/// Minimum collision radius of a wall segment so thin walls can't be tunneled through
+pub const MIN_SEGMENT_RADIUS: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CircleObstacle {
+    pub center: Vec2,
+    pub radius: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SegmentObstacle {
+    pub a: Vec2,
+    pub b: Vec2,
+    /// Half thickness of the wall
+    pub radius: f32,
+}
+
+impl SegmentObstacle {
+    pub fn closest_point(&self, p: Vec2) -> Vec2 {
+        let ab = self.b - self.a;
+        let len2 = ab.length_squared();
+        if len2 == 0.0 {
+            return self.a;
+        }
+        let t = ((p - self.a).dot(ab) / len2).clamp(0.0, 1.0);
+        self.a + ab * t
+    }
+}
+
+/// Painted grid of blocked cells covering the world bound
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OccupancyMask {
+    pub cell_size: f32,
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<bool>,
+}
+
+impl OccupancyMask {
+    /// Create an empty mask covering a world of the given size
+    pub fn new(size: Vec2, cell_size: f32) -> Self {
+        let width = (size.x / cell_size).ceil() as usize;
+        let height = (size.y / cell_size).ceil() as usize;
+        Self {
+            cell_size,
+            width,
+            height,
+            cells: vec![false; width * height],
+        }
+    }
+
+    fn cell(&self, p: Vec2) -> Option<usize> {
+        let x = (p.x / self.cell_size).floor();
+        let y = (p.y / self.cell_size).floor();
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            return None;
+        }
+        Some(y as usize * self.width + x as usize)
+    }
+
+    pub fn occupied(&self, p: Vec2) -> bool {
+        self.cell(p).is_some_and(|i| self.cells[i])
+    }
+
+    /// Set every cell whose center lies within `radius` of `center`
+    pub fn paint(&mut self, center: Vec2, radius: f32, value: bool) {
+        let r2 = radius * radius;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = (vec2(x as f32, y as f32) + 0.5) * self.cell_size;
+                if c.distance_squared(center) <= r2 {
+                    self.cells[y * self.width + x] = value;
+                }
+            }
+        }
+    }
+
+    /// Iterate over the top left corners of occupied cells
+    pub fn occupied_cells(&self) -> impl Iterator<Item = Vec2> + '_ {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, occupied)| **occupied)
+            .map(|(i, _)| vec2((i % self.width) as f32, (i / self.width) as f32) * self.cell_size)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Obstacles {
+    #[serde(default)]
+    pub circles: Vec<CircleObstacle>,
+    #[serde(default)]
+    pub segments: Vec<SegmentObstacle>,
+    #[serde(default)]
+    pub mask: Option<OccupancyMask>,
+}
+
+impl Obstacles {
+    pub fn is_empty(&self) -> bool {
+        self.circles.is_empty() && self.segments.is_empty() && self.mask.is_none()
+    }
+
+    /// Remove circles and segments touching `point`
+    pub fn remove_at(&mut self, point: Vec2) {
+        self.circles.retain(|c| c.center.distance(point) > c.radius);
+        self.segments
+            .retain(|s| s.closest_point(point).distance(point) > s.radius.max(MIN_SEGMENT_RADIUS));
+    }
+
+    /// Push a particle that moved from `prev` into `pos` back out of any obstacle and reflect the
+    /// normal component of its velocity.
+    pub fn resolve(&self, prev: Vec2, pos: &mut Vec2, vel: &mut Vec2) {
+        for c in &self.circles {
+            push_out(c.center, c.radius, prev, pos, vel);
+        }
+        for s in &self.segments {
+            let q = s.closest_point(*pos);
+            push_out(q, s.radius.max(MIN_SEGMENT_RADIUS), prev, pos, vel);
+        }
+        if let Some(mask) = &self.mask {
+            if !mask.occupied(*pos) || mask.occupied(prev) {
+                return;
+            }
+            let blocked_x = mask.occupied(vec2(pos.x, prev.y));
+            let blocked_y = mask.occupied(vec2(prev.x, pos.y));
+            if blocked_x || !blocked_y {
+                vel.x = -vel.x;
+                pos.x = prev.x;
+            }
+            if blocked_y || !blocked_x {
+                vel.y = -vel.y;
+                pos.y = prev.y;
+            }
+        }
+    }
+}
+
+fn push_out(center: Vec2, radius: f32, prev: Vec2, pos: &mut Vec2, vel: &mut Vec2) {
+    let d = *pos - center;
+    let d2 = d.length_squared();
+    if d2 >= radius * radius {
+        return;
+    }
+    let n = if d2 > 0.0 {
+        d / d2.sqrt()
+    } else {
+        (prev - center).try_normalize().unwrap_or(Vec2::X)
+    };
+    *pos = center + n * radius;
+    let vn = vel.dot(n);
+    if vn < 0.0 {
+        *vel -= 2.0 * vn * n;
+    }
+}
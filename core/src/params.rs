@@ -0,0 +1,186 @@
+use std::fmt;
+
+use glam::Vec2;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{field::ForceFields, mesh::Mesh, obstacle::Obstacles, sim::Repulsion};
+
+/// Current version of the [`SimParams`] format. Blobs without a version are treated as version 1.
+pub const SIM_PARAMS_VERSION: u32 = 1;
+
+fn default_version() -> u32 {
+    1
+}
+
+/// Simulation parameters shared by every frontend and backend.
+///
+/// This is the json blob the binaries print at startup and accept as their positional argument.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SimParams {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    pub num_cultures: u32,
+    pub culture_size: u32,
+    pub aoe: f32,
+    pub damping: f32,
+    pub mesh: Mesh,
+    #[serde(default)]
+    pub obstacles: Obstacles,
+    #[serde(default)]
+    pub fields: ForceFields,
+    /// Variance of the gaussian velocity noise added to each particle every step
+    #[serde(default)]
+    pub temperature: f32,
+    #[serde(default)]
+    pub repulsion: Repulsion,
+    /// Cap on the length of the total force applied to a particle each step, 0 for no cap
+    #[serde(default)]
+    pub max_force: f32,
+    /// Cap on particle speed, 0 for no cap
+    #[serde(default)]
+    pub max_speed: f32,
+    /// World size, particles live in `[0, bound]`. Each frontend picks its own default when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bound: Option<Vec2>,
+}
+
+#[derive(Debug)]
+pub enum ParamsError {
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    Invalid(String),
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "invalid sim params json: {e}"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "sim params version {v} is newer than supported version {SIM_PARAMS_VERSION}"
+            ),
+            Self::Invalid(msg) => write!(f, "invalid sim params: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+impl From<serde_json::Error> for ParamsError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl SimParams {
+    /// Create params with a random gravity mesh and no obstacles, fields or noise
+    pub fn random(
+        num_cultures: u32,
+        culture_size: u32,
+        aoe: f32,
+        damping: f32,
+        rng: &mut impl Rng,
+    ) -> Self {
+        Self {
+            version: SIM_PARAMS_VERSION,
+            num_cultures,
+            culture_size,
+            aoe,
+            damping,
+            mesh: Mesh::random(num_cultures as usize, rng),
+            obstacles: Obstacles::default(),
+            fields: ForceFields::default(),
+            temperature: 0.0,
+            repulsion: Repulsion::default(),
+            max_force: 0.0,
+            max_speed: 0.0,
+            bound: None,
+        }
+    }
+
+    pub fn num_particles(&self) -> u32 {
+        self.num_cultures * self.culture_size
+    }
+
+    /// Parse and validate a json blob
+    pub fn from_json(json: &str) -> Result<Self, ParamsError> {
+        let params: Self = serde_json::from_str(json)?;
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Sim params are serializable")
+    }
+
+    pub fn validate(&self) -> Result<(), ParamsError> {
+        let invalid = |msg: String| Err(ParamsError::Invalid(msg));
+
+        if self.version > SIM_PARAMS_VERSION {
+            return Err(ParamsError::UnsupportedVersion(self.version));
+        }
+        if self.num_cultures == 0 || self.culture_size == 0 {
+            return invalid("num_cultures and culture_size must be positive".into());
+        }
+        if self.mesh.num_cultures() != self.num_cultures as usize {
+            return invalid(format!(
+                "mesh is {n}x{n} but num_cultures is {}",
+                self.num_cultures,
+                n = self.mesh.num_cultures()
+            ));
+        }
+        if self.mesh.as_flat().iter().any(|g| !g.is_finite()) {
+            return invalid("mesh values must be finite".into());
+        }
+        if !(self.aoe.is_finite() && self.aoe > 0.0) {
+            return invalid(format!("aoe must be positive, got {}", self.aoe));
+        }
+        if !(self.damping.is_finite() && self.damping >= 0.0) {
+            return invalid(format!(
+                "damping must be non-negative, got {}",
+                self.damping
+            ));
+        }
+        if !(self.temperature.is_finite() && self.temperature >= 0.0) {
+            return invalid(format!(
+                "temperature must be non-negative, got {}",
+                self.temperature
+            ));
+        }
+        let Repulsion { radius, strength } = self.repulsion;
+        if !(radius.is_finite() && (0.0..=self.aoe).contains(&radius)) {
+            // Neighbour searches only reach the aoe
+            return invalid(format!(
+                "repulsion radius must be between 0 and the aoe, got {radius}"
+            ));
+        }
+        if !(strength.is_finite() && strength >= 0.0) {
+            return invalid(format!(
+                "repulsion strength must be non-negative, got {strength}"
+            ));
+        }
+        for (name, limit) in [("max_force", self.max_force), ("max_speed", self.max_speed)] {
+            if !(limit.is_finite() && limit >= 0.0) {
+                return invalid(format!("{name} must be non-negative, got {limit}"));
+            }
+        }
+        if let Some(bound) = self.bound
+            && !(bound.is_finite() && bound.x > 0.0 && bound.y > 0.0)
+        {
+            return invalid(format!("bound must be positive, got {bound}"));
+        }
+        if let Some(mask) = &self.obstacles.mask
+            && (mask.cells.len() != mask.width * mask.height
+                || !(mask.cell_size.is_finite() && mask.cell_size > 0.0))
+        {
+            return invalid("obstacle mask must have width * height cells of positive size".into());
+        }
+        let radii = self.obstacles.circles.iter().map(|c| c.radius);
+        let mut radii = radii.chain(self.obstacles.segments.iter().map(|s| s.radius));
+        if radii.any(|r| !(r.is_finite() && r >= 0.0)) {
+            return invalid("obstacle radii must be non-negative".into());
+        }
+        Ok(())
+    }
+}
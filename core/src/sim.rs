@@ -0,0 +1,110 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::{Mesh, SimParams};
+
+/// A particle's state. Its culture is implied by its index, `i / culture_size`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Particle {
+    pub pos: Vec2,
+    pub vel: Vec2,
+}
+
+/// Circular cursor force. Positive strength attracts, negative repels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cursor {
+    pub pos: Vec2,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+impl Cursor {
+    /// Get the force the cursor exerts on a particle at `pos`
+    pub fn force(&self, pos: Vec2) -> Vec2 {
+        let d2 = Vec2::distance_squared(self.pos, pos);
+        if d2 > 0.0 && d2 <= self.radius * self.radius {
+            (self.pos - pos).normalize() * self.strength
+        } else {
+            Vec2::ZERO
+        }
+    }
+}
+
+/// Short-range repulsion between any two particles, whatever their cultures, so attracted
+/// particles pack at a distance instead of collapsing onto one point. Off when `radius` is 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Repulsion {
+    /// Hard-core radius, at most the aoe
+    pub radius: f32,
+    /// Push at contact, falling linearly to 0 at `radius`
+    pub strength: f32,
+}
+
+impl Repulsion {
+    /// Get the penalty force a particle at `other` exerts on a particle at `pos`
+    pub fn force(&self, pos: Vec2, other: Vec2) -> Vec2 {
+        let d = pos - other;
+        let d2 = d.length_squared();
+        if d2 > 0.0 && d2 < self.radius * self.radius {
+            let dist = d2.sqrt();
+            d / dist * self.strength * (1.0 - dist / self.radius)
+        } else {
+            Vec2::ZERO
+        }
+    }
+}
+
+/// The force a particle at `other` exerts on a particle at `pos`: gravity `g` towards it within
+/// the aoe, plus the repulsion. Every backend sums it over the other particles unscaled, the GPU
+/// with `pair_force` in compute.wgsl.
+pub fn pair_force(pos: Vec2, other: Vec2, g: f32, aoe2: f32, repulsion: Repulsion) -> Vec2 {
+    let d2 = Vec2::distance_squared(pos, other);
+    if d2 > 0.0 && d2 <= aoe2 {
+        (other - pos).normalize() * g + repulsion.force(pos, other)
+    } else {
+        Vec2::ZERO
+    }
+}
+
+/// Full world state, enough to reproduce a run from the step it was taken
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub params: SimParams,
+    pub step: u64,
+    pub particles: Vec<Particle>,
+}
+
+/// Common surface of every simulation backend
+pub trait Simulator {
+    /// Advance the simulation by one step, integrating positions over `dt`
+    fn step(&mut self, dt: f32);
+
+    /// Number of steps taken since the world was spawned
+    fn step_count(&self) -> u64;
+
+    /// Current particles, ordered by culture
+    fn particles(&mut self) -> &[Particle];
+
+    fn params(&self) -> &SimParams;
+
+    /// Replace the params. Particles are kept if the number of cultures and culture size are
+    /// unchanged, and respawned otherwise.
+    fn set_params(&mut self, params: SimParams);
+
+    fn set_mesh(&mut self, mesh: Mesh) {
+        let params = SimParams {
+            num_cultures: mesh.num_cultures() as u32,
+            mesh,
+            ..self.params().clone()
+        };
+        self.set_params(params);
+    }
+
+    fn snapshot(&mut self) -> Snapshot {
+        Snapshot {
+            params: self.params().clone(),
+            step: self.step_count(),
+            particles: self.particles().to_vec(),
+        }
+    }
+}
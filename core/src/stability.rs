@@ -0,0 +1,164 @@
+use std::{fmt, str::FromStr};
+
+use crate::{Particle, Simulator};
+
+/// Mean kinetic energy per particle, `|v|^2 / 2`
+pub fn kinetic_energy(particles: &[Particle]) -> f32 {
+    let sum = particles
+        .iter()
+        .map(|p| p.vel.length_squared() as f64)
+        .sum::<f64>();
+    (0.5 * sum / particles.len().max(1) as f64) as f32
+}
+
+/// What to do when the energy explodes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnExplosion {
+    /// Don't check
+    Off,
+    /// Report it and carry on
+    #[default]
+    Warn,
+    /// Stop stepping until resumed
+    Pause,
+    /// Cap the speed near the energy before the explosion
+    Clamp,
+}
+
+impl FromStr for OnExplosion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "pause" => Ok(Self::Pause),
+            "clamp" => Ok(Self::Clamp),
+            _ => Err(format!(
+                "unknown explosion action {s:?}, expected off, warn, pause or clamp"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OnExplosion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Off => "off",
+            Self::Warn => "warn",
+            Self::Pause => "pause",
+            Self::Clamp => "clamp",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// A check where the energy jumped past the monitor's threshold
+#[derive(Clone, Copy, Debug)]
+pub struct Explosion {
+    pub step: u64,
+    pub energy: f32,
+    pub baseline: f32,
+    /// Speed cap applied by [`OnExplosion::Clamp`]
+    pub clamped_speed: Option<f32>,
+}
+
+impl fmt::Display for Explosion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "energy exploded at step {}: {:.3} against a baseline of {:.3}",
+            self.step, self.energy, self.baseline
+        )?;
+        if let Some(speed) = self.clamped_speed {
+            write!(f, ", capping speed at {speed:.3}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Weight of the newest check in the running baseline
+const SMOOTHING: f32 = 0.1;
+/// Most the baseline can grow per step, so it can't keep up with an exponential blow up
+const MAX_GROWTH: f32 = 1.01;
+/// Floor on the baseline so a world spawned at rest isn't flagged as it starts moving
+const MIN_BASELINE: f32 = 0.5;
+/// Multiple of the baseline rms speed a clamp allows
+const CLAMP_MARGIN: f32 = 2.0;
+
+/// Flags checks where the kinetic energy jumps far above its running average, which may only
+/// grow slowly. The baseline starts from a world at rest and restarts from the next check after
+/// a flag, so one blow up is reported once.
+#[derive(Clone, Debug)]
+pub struct EnergyMonitor {
+    /// Energy over the baseline that counts as an explosion
+    pub factor: f32,
+    baseline: Option<f32>,
+    last_step: u64,
+}
+
+impl Default for EnergyMonitor {
+    fn default() -> Self {
+        Self::new(100.0)
+    }
+}
+
+impl EnergyMonitor {
+    pub fn new(factor: f32) -> Self {
+        Self {
+            factor,
+            baseline: Some(MIN_BASELINE),
+            last_step: 0,
+        }
+    }
+
+    /// Take the next check as the baseline, e.g. after a respawn
+    pub fn reset(&mut self) {
+        self.baseline = None;
+    }
+
+    /// Check the energy of `sim` and apply `action`. Pausing is left to the caller.
+    pub fn check(&mut self, sim: &mut impl Simulator, action: OnExplosion) -> Option<Explosion> {
+        if action == OnExplosion::Off {
+            return None;
+        }
+        let energy = kinetic_energy(sim.particles());
+        let step = sim.step_count();
+        let steps = step.saturating_sub(self.last_step);
+        self.last_step = step;
+        let baseline = match self.baseline {
+            Some(b) => b,
+            None if energy.is_finite() => {
+                self.baseline = Some(energy.max(MIN_BASELINE));
+                return None;
+            }
+            None => MIN_BASELINE,
+        };
+        if energy.is_finite() && energy <= self.factor * baseline {
+            let average = baseline + (energy - baseline) * SMOOTHING;
+            let cap = baseline * MAX_GROWTH.powf(steps as f32);
+            self.baseline = Some(average.min(cap).max(MIN_BASELINE));
+            return None;
+        }
+
+        self.baseline = None;
+        let clamped_speed = (action == OnExplosion::Clamp).then(|| {
+            let mut params = sim.params().clone();
+            let speed = (2.0 * baseline).sqrt() * CLAMP_MARGIN;
+            params.max_speed = if params.max_speed > 0.0 {
+                params.max_speed.min(speed)
+            } else {
+                speed
+            };
+            let speed = params.max_speed;
+            sim.set_params(params);
+            speed
+        });
+        Some(Explosion {
+            step,
+            energy,
+            baseline,
+            clamped_speed,
+        })
+    }
+}
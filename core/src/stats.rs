@@ -0,0 +1,203 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::{Add, AddAssign},
+    path::Path,
+};
+
+use glam::Vec2;
+use serde::Serialize;
+
+use crate::{Particle, SimParams, grid::Grid};
+
+/// Observables of one culture, or of every particle for [`Stats::total`]. Particles have unit
+/// mass.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct CultureStats {
+    pub count: u32,
+    pub centroid: Vec2,
+    pub mean_speed: f32,
+    /// Mean kinetic energy per particle
+    pub kinetic_energy: f32,
+    pub momentum: Vec2,
+    /// Rms distance from the centroid
+    pub spread: f32,
+    /// Mean distance to the nearest particle of the same culture within the aoe, over the
+    /// particles that have one
+    pub nn_distance: Option<f32>,
+}
+
+/// Sums a [`CultureStats`] is derived from, accumulated the same way on every backend. Positions
+/// are relative to an origin near the particles, the world center, to keep f32 sums precise.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StatSums {
+    pub count: f32,
+    pub pos: Vec2,
+    pub pos2: f32,
+    pub vel: Vec2,
+    pub speed: f32,
+    pub speed2: f32,
+    pub nn: f32,
+    pub nn_count: f32,
+}
+
+impl StatSums {
+    /// Add a particle at `pos` relative to the origin, with the distance to its nearest
+    /// neighbour if it has one
+    pub fn push(&mut self, pos: Vec2, vel: Vec2, nn: Option<f32>) {
+        let speed2 = vel.length_squared();
+        self.count += 1.0;
+        self.pos += pos;
+        self.pos2 += pos.length_squared();
+        self.vel += vel;
+        self.speed += speed2.sqrt();
+        self.speed2 += speed2;
+        if let Some(nn) = nn {
+            self.nn += nn;
+            self.nn_count += 1.0;
+        }
+    }
+
+    pub fn finish(&self, origin: Vec2) -> CultureStats {
+        let n = self.count.max(1.0);
+        let mean = self.pos / n;
+        CultureStats {
+            count: self.count as u32,
+            centroid: origin + mean,
+            mean_speed: self.speed / n,
+            kinetic_energy: 0.5 * self.speed2 / n,
+            momentum: self.vel,
+            spread: (self.pos2 / n - mean.length_squared()).max(0.0).sqrt(),
+            nn_distance: (self.nn_count > 0.0).then(|| self.nn / self.nn_count),
+        }
+    }
+}
+
+impl AddAssign for StatSums {
+    fn add_assign(&mut self, o: Self) {
+        self.count += o.count;
+        self.pos += o.pos;
+        self.pos2 += o.pos2;
+        self.vel += o.vel;
+        self.speed += o.speed;
+        self.speed2 += o.speed2;
+        self.nn += o.nn;
+        self.nn_count += o.nn_count;
+    }
+}
+
+impl Add for StatSums {
+    type Output = Self;
+
+    fn add(mut self, o: Self) -> Self {
+        self += o;
+        self
+    }
+}
+
+/// Observables of the world at one step
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Stats {
+    pub step: u64,
+    pub total: CultureStats,
+    pub cultures: Vec<CultureStats>,
+}
+
+impl Stats {
+    /// Combine per culture sums taken relative to `origin`
+    pub fn from_sums(step: u64, sums: &[StatSums], origin: Vec2) -> Self {
+        let total = sums.iter().fold(StatSums::default(), |acc, &s| acc + s);
+        Self {
+            step,
+            total: total.finish(origin),
+            cultures: sums.iter().map(|s| s.finish(origin)).collect(),
+        }
+    }
+
+    /// Compute the stats of culture-ordered particles on the CPU, finding nearest neighbours
+    /// with a grid of aoe sized cells
+    pub fn compute(params: &SimParams, bound: Vec2, particles: &[Particle], step: u64) -> Self {
+        let cs = params.culture_size as usize;
+        let grid = Grid::new(particles, bound, params.aoe);
+        let nearest = |i: usize| {
+            let pos = particles[i].pos;
+            let mut best = params.aoe * params.aoe;
+            let mut found = false;
+            for j in grid.neighbours(pos) {
+                if j == i || j / cs != i / cs {
+                    continue;
+                }
+                let d2 = pos.distance_squared(particles[j].pos);
+                if d2 <= best {
+                    best = d2;
+                    found = true;
+                }
+            }
+            found.then(|| best.sqrt())
+        };
+
+        let origin = bound / 2.0;
+        let mut sums = vec![StatSums::default(); params.num_cultures as usize];
+        for (i, p) in particles.iter().enumerate() {
+            sums[i / cs].push(p.pos - origin, p.vel, nearest(i));
+        }
+        Self::from_sums(step, &sums, origin)
+    }
+}
+
+/// Streams stats to a file, as csv for a `.csv` path and json lines otherwise
+pub struct StatsWriter {
+    out: BufWriter<File>,
+    csv: bool,
+}
+
+const CSV_HEADER: &str = "step,culture,count,centroid_x,centroid_y,mean_speed,kinetic_energy,\
+momentum_x,momentum_y,spread,nn_distance";
+
+impl StatsWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let csv = path.extension().is_some_and(|e| e == "csv");
+        let mut out = BufWriter::new(File::create(path)?);
+        if csv {
+            writeln!(out, "{CSV_HEADER}")?;
+        }
+        Ok(Self { out, csv })
+    }
+
+    /// Write one line per step in json lines, or one row per culture and a row for the total,
+    /// culture `all`, in csv
+    pub fn write(&mut self, stats: &Stats) -> io::Result<()> {
+        if !self.csv {
+            serde_json::to_writer(&mut self.out, stats)?;
+            return writeln!(self.out);
+        }
+        let rows = stats
+            .cultures
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i.to_string(), c))
+            .chain([("all".to_string(), &stats.total)]);
+        for (culture, c) in rows {
+            writeln!(
+                self.out,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                stats.step,
+                culture,
+                c.count,
+                c.centroid.x,
+                c.centroid.y,
+                c.mean_speed,
+                c.kinetic_energy,
+                c.momentum.x,
+                c.momentum.y,
+                c.spread,
+                c.nn_distance.map_or(String::new(), |d| d.to_string()),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,526 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use glam::Vec2;
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Mesh, Particle, SimParams, Simulator,
+    cluster::{ClusterParams, ClusterTracker},
+    stability::{EnergyMonitor, OnExplosion},
+    stats::Stats,
+};
+
+/// Steps between energy checks during a run
+const CHECK_STEPS: u32 = 100;
+
+/// How the gravity mesh of a run is drawn from its seed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeshGen {
+    /// Every entry uniform in [-1, 1], like [`Mesh::random`]
+    Uniform,
+    /// Uniform with `get(a, b) == get(b, a)`
+    Symmetric,
+    /// Uniform entries kept with probability `density`, the rest 0
+    Sparse(f32),
+}
+
+impl MeshGen {
+    pub fn generate(&self, num_cultures: usize, rng: &mut impl Rng) -> Mesh {
+        let mut mesh = Mesh::random(num_cultures, rng);
+        match *self {
+            Self::Uniform => {}
+            Self::Symmetric => {
+                for a in 0..num_cultures {
+                    for b in 0..a {
+                        mesh.set(a, b, mesh.get(b, a));
+                    }
+                }
+            }
+            Self::Sparse(density) => {
+                for g in mesh.as_flat_mut() {
+                    if !rng.random_bool(density.clamp(0.0, 1.0) as f64) {
+                        *g = 0.0;
+                    }
+                }
+            }
+        }
+        mesh
+    }
+}
+
+impl FromStr for MeshGen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "uniform" => Ok(Self::Uniform),
+            None if s == "symmetric" => Ok(Self::Symmetric),
+            Some(("sparse", density)) => match density.parse::<f32>() {
+                Ok(d) if (0.0..=1.0).contains(&d) => Ok(Self::Sparse(d)),
+                Ok(d) => Err(format!("sparse density {d} isn't within 0..=1")),
+                Err(e) => Err(format!("invalid sparse density {density:?}: {e}")),
+            },
+            _ => Err(format!(
+                "unknown mesh generator {s:?}, expected uniform, symmetric or sparse:<density>"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MeshGen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::Symmetric => write!(f, "symmetric"),
+            Self::Sparse(density) => write!(f, "sparse:{density}"),
+        }
+    }
+}
+
+/// A value a sweep axis can range over
+pub trait AxisValue: Copy + PartialOrd + FromStr + fmt::Display {
+    /// Values of `lo..hi` on a grid of `resolution` points
+    fn grid(lo: Self, hi: Self, resolution: usize) -> Vec<Self>;
+    /// Random value in `lo..hi`
+    fn sample(lo: Self, hi: Self, rng: &mut impl Rng) -> Self;
+}
+
+/// Float ranges include both ends and are split into `resolution` evenly spaced values
+impl AxisValue for f32 {
+    fn grid(lo: Self, hi: Self, resolution: usize) -> Vec<Self> {
+        if resolution <= 1 {
+            return vec![lo];
+        }
+        (0..resolution)
+            .map(|i| lo + (hi - lo) * i as f32 / (resolution - 1) as f32)
+            .collect()
+    }
+
+    fn sample(lo: Self, hi: Self, rng: &mut impl Rng) -> Self {
+        rng.random_range(lo..=hi)
+    }
+}
+
+/// Integer ranges exclude the end like Rust ranges and always cover every value
+macro_rules! int_axis_value {
+    ($($t:ty),*) => {$(
+        impl AxisValue for $t {
+            fn grid(lo: Self, hi: Self, _resolution: usize) -> Vec<Self> {
+                (lo..hi).collect()
+            }
+
+            fn sample(lo: Self, hi: Self, rng: &mut impl Rng) -> Self {
+                rng.random_range(lo..hi)
+            }
+        }
+    )*};
+}
+
+int_axis_value!(u32, u64);
+
+/// Values of one parameter in a sweep, a comma separated list like `10,20,50` or a range like
+/// `10..50`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Axis<T> {
+    List(Vec<T>),
+    Range(T, T),
+}
+
+impl<T: AxisValue> Axis<T> {
+    /// Every value on a grid
+    pub fn values(&self, resolution: usize) -> Vec<T> {
+        match self {
+            Self::List(values) => values.clone(),
+            Self::Range(lo, hi) => T::grid(*lo, *hi, resolution),
+        }
+    }
+
+    /// A random list element or value in the range
+    pub fn sample(&self, rng: &mut impl Rng) -> T {
+        match self {
+            Self::List(values) => *values.choose(rng).expect("Axis lists aren't empty"),
+            Self::Range(lo, hi) => T::sample(*lo, *hi, rng),
+        }
+    }
+}
+
+impl<T: AxisValue> FromStr for Axis<T>
+where
+    T::Err: fmt::Display,
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<T>()
+                .map_err(|e| format!("invalid value {v:?}: {e}"))
+        };
+        if let Some((lo, hi)) = s.split_once("..") {
+            let (lo, hi) = (parse(lo)?, parse(hi)?);
+            if lo.partial_cmp(&hi) != Some(std::cmp::Ordering::Less) {
+                return Err(format!("empty range {s:?}"));
+            }
+            return Ok(Self::Range(lo, hi));
+        }
+        let values = s.split(',').map(parse).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::List(values))
+    }
+}
+
+impl<T: AxisValue> fmt::Display for Axis<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::List(values) => {
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{v}")?;
+                }
+                Ok(())
+            }
+            Self::Range(lo, hi) => write!(f, "{lo}..{hi}"),
+        }
+    }
+}
+
+/// The parameters a sweep covers, either as a grid over every combination or as a random sample
+#[derive(Clone, Debug)]
+pub struct SweepSpec {
+    pub meshes: Vec<MeshGen>,
+    pub cultures: Axis<u32>,
+    pub culture_size: Axis<u32>,
+    pub aoe: Axis<f32>,
+    pub damping: Axis<f32>,
+    pub seeds: Axis<u64>,
+    /// Values a float range is split into on a grid
+    pub resolution: usize,
+    /// Draw this many random runs instead of the grid
+    pub sample: Option<usize>,
+    /// Seed of the random sample
+    pub sample_seed: u64,
+}
+
+impl SweepSpec {
+    /// Every run of the sweep, with ids counting up from 0. The same spec always gives the same
+    /// runs, which is what lets an interrupted sweep resume.
+    pub fn runs(&self) -> Vec<SweepRun> {
+        let runs = match self.sample {
+            Some(n) => self.sample_runs(n),
+            None => self.grid_runs(),
+        };
+        runs.into_iter()
+            .enumerate()
+            .map(|(id, run)| SweepRun { id, ..run })
+            .collect()
+    }
+
+    fn grid_runs(&self) -> Vec<SweepRun> {
+        let res = self.resolution;
+        let mut runs = vec![];
+        for &mesh in &self.meshes {
+            for cultures in self.cultures.values(res) {
+                for culture_size in self.culture_size.values(res) {
+                    for aoe in self.aoe.values(res) {
+                        for damping in self.damping.values(res) {
+                            for seed in self.seeds.values(res) {
+                                runs.push(SweepRun {
+                                    id: 0,
+                                    mesh,
+                                    cultures,
+                                    culture_size,
+                                    aoe,
+                                    damping,
+                                    seed,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        runs
+    }
+
+    fn sample_runs(&self, n: usize) -> Vec<SweepRun> {
+        let mut rng = StdRng::seed_from_u64(self.sample_seed);
+        (0..n)
+            .map(|_| SweepRun {
+                id: 0,
+                mesh: *self.meshes.choose(&mut rng).expect("a mesh generator"),
+                cultures: self.cultures.sample(&mut rng),
+                culture_size: self.culture_size.sample(&mut rng),
+                aoe: self.aoe.sample(&mut rng),
+                damping: self.damping.sample(&mut rng),
+                seed: self.seeds.sample(&mut rng),
+            })
+            .collect()
+    }
+}
+
+/// One simulation of a sweep. Its seed picks the mesh and the spawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepRun {
+    pub id: usize,
+    pub mesh: MeshGen,
+    pub cultures: u32,
+    pub culture_size: u32,
+    pub aoe: f32,
+    pub damping: f32,
+    pub seed: u64,
+}
+
+impl SweepRun {
+    pub fn params(&self) -> SimParams {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut simp = SimParams::random(
+            self.cultures,
+            self.culture_size,
+            self.aoe,
+            self.damping,
+            &mut rng,
+        );
+        simp.mesh = self.mesh.generate(self.cultures as usize, &mut rng);
+        simp
+    }
+
+    /// The run's columns of the results table
+    fn csv_prefix(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}",
+            self.id, self.mesh, self.cultures, self.culture_size, self.aoe, self.damping, self.seed
+        )
+    }
+}
+
+/// What a run ended up as
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Observables {
+    /// Steps run, fewer than asked for if the energy exploded
+    pub steps: u64,
+    pub exploded: bool,
+    /// Mean kinetic energy per particle
+    pub kinetic_energy: f32,
+    pub mean_speed: f32,
+    /// Rms distance from the centroid
+    pub spread: f32,
+    /// Mean distance to the nearest particle of the same culture within the aoe
+    pub nn_distance: Option<f32>,
+    pub clusters: usize,
+    /// Fraction of particles in a cluster
+    pub clustered: f32,
+    pub largest_cluster: usize,
+}
+
+impl Observables {
+    /// Measure culture-ordered `particles` after `steps` steps
+    pub fn measure(
+        simp: &SimParams,
+        bound: Vec2,
+        particles: &[Particle],
+        clusters: ClusterParams,
+        steps: u64,
+        exploded: bool,
+    ) -> Self {
+        if particles
+            .iter()
+            .any(|p| !(p.pos.is_finite() && p.vel.is_finite()))
+        {
+            return Self {
+                steps,
+                exploded: true,
+                ..Default::default()
+            };
+        }
+        let total = Stats::compute(simp, bound, particles, steps).total;
+        let mut tracker = ClusterTracker::new(clusters);
+        let found = tracker.update(simp, bound, particles);
+        let clustered = found.iter().map(|c| c.size).sum::<usize>();
+        Self {
+            steps,
+            exploded,
+            kinetic_energy: total.kinetic_energy,
+            mean_speed: total.mean_speed,
+            spread: total.spread,
+            nn_distance: total.nn_distance,
+            clusters: found.len(),
+            clustered: clustered as f32 / particles.len().max(1) as f32,
+            largest_cluster: found.first().map_or(0, |c| c.size),
+        }
+    }
+}
+
+/// Run `sim` for `steps` steps with `advance`, which steps it a given number of times, and
+/// measure the result. Stops early if the energy explodes.
+pub fn run<S: Simulator>(
+    sim: &mut S,
+    steps: u32,
+    bound: Vec2,
+    clusters: ClusterParams,
+    advance: impl FnMut(&mut S, u32),
+) -> Observables {
+    let exploded = run_observed(sim, steps, steps, CHECK_STEPS, advance, |_| {});
+    let simp = sim.params().clone();
+    let step = sim.step_count();
+    Observables::measure(&simp, bound, sim.particles(), clusters, step, exploded)
+}
+
+/// Run `sim` for `steps` steps with `advance`, checking the energy every `every` steps and
+/// calling `observe` after each check from step `from` on. Returns whether the energy exploded,
+/// which stops the run early.
+pub fn run_observed<S: Simulator>(
+    sim: &mut S,
+    steps: u32,
+    from: u32,
+    every: u32,
+    mut advance: impl FnMut(&mut S, u32),
+    mut observe: impl FnMut(&mut S),
+) -> bool {
+    let every = every.max(1);
+    let mut monitor = EnergyMonitor::default();
+    let mut done = 0;
+    while done < steps {
+        let n = if done < from {
+            (from - done).min(every)
+        } else {
+            every
+        }
+        .min(steps - done);
+        advance(sim, n);
+        done += n;
+        if monitor.check(sim, OnExplosion::Warn).is_some() {
+            return true;
+        }
+        if done >= from {
+            observe(sim);
+        }
+    }
+    false
+}
+
+/// Settings shared by every run of a sweep, saved next to its results so a resumed sweep can't
+/// mix in rows run differently
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SweepSettings {
+    pub steps: u32,
+    pub bound: Vec2,
+    pub backend: String,
+    /// Barnes-Hut opening angle of the CPU backend
+    pub theta: f32,
+    pub clusters: ClusterParams,
+}
+
+const RESULTS_HEADER: &str = "id,mesh,cultures,culture_size,aoe,damping,seed,steps,exploded,\
+    kinetic_energy,mean_speed,spread,nn_distance,clusters,clustered,largest_cluster,ms_per_step";
+
+/// Csv table of sweep results with a row per finished run, flushed as each run finishes so an
+/// interrupted sweep loses at most the runs in flight
+pub struct SweepResults {
+    file: BufWriter<File>,
+}
+
+impl SweepResults {
+    /// Open the table at `path`, creating it if needed, and return the ids of the runs it
+    /// already has. Fails if a row doesn't match the run of its id or the settings in the
+    /// `.json` next to the table differ, i.e. the table belongs to a different sweep. A row cut
+    /// off by an interruption is dropped.
+    pub fn open(
+        path: &Path,
+        runs: &[SweepRun],
+        settings: &SweepSettings,
+    ) -> io::Result<(Self, HashSet<usize>)> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let settings_path = path.with_extension("json");
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        let complete = text.rfind('\n').map_or(0, |i| i + 1);
+        if complete < text.len() {
+            file.set_len(complete as u64)?;
+        }
+
+        if text.is_empty() {
+            let json = serde_json::to_string_pretty(settings).map_err(io::Error::other)?;
+            std::fs::write(&settings_path, json)?;
+        } else {
+            let json = std::fs::read_to_string(&settings_path)?;
+            let saved = serde_json::from_str::<SweepSettings>(&json)
+                .map_err(|e| invalid(format!("{}: {e}", settings_path.display())))?;
+            if saved != *settings {
+                return Err(invalid(format!(
+                    "{} was run with other settings, {saved:?}",
+                    path.display()
+                )));
+            }
+        }
+        let mut done = HashSet::new();
+        let mut lines = text[..complete].lines();
+        match lines.next() {
+            None => writeln!(file, "{RESULTS_HEADER}")?,
+            Some(RESULTS_HEADER) => {}
+            Some(_) => {
+                return Err(invalid(format!(
+                    "{} isn't a sweep results table",
+                    path.display()
+                )));
+            }
+        }
+        for line in lines {
+            let id = line
+                .split(',')
+                .next()
+                .and_then(|id| id.parse::<usize>().ok());
+            let run = id.and_then(|id| runs.get(id));
+            match run {
+                Some(run) if line.starts_with(&format!("{},", run.csv_prefix())) => {
+                    done.insert(run.id);
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "{} has a row from a different sweep: {line}",
+                        path.display()
+                    )));
+                }
+            }
+        }
+        Ok((
+            Self {
+                file: BufWriter::new(file),
+            },
+            done,
+        ))
+    }
+
+    pub fn write(&mut self, run: &SweepRun, obs: &Observables, ms_per_step: f64) -> io::Result<()> {
+        let nn = obs.nn_distance.map(|d| d.to_string()).unwrap_or_default();
+        writeln!(
+            self.file,
+            "{},{},{},{},{},{},{},{},{},{},{:.3}",
+            run.csv_prefix(),
+            obs.steps,
+            obs.exploded,
+            obs.kinetic_energy,
+            obs.mean_speed,
+            obs.spread,
+            nn,
+            obs.clusters,
+            obs.clustered,
+            obs.largest_cluster,
+            ms_per_step
+        )?;
+        self.file.flush()
+    }
+}
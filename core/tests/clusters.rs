@@ -0,0 +1,138 @@
+//! DBSCAN labels, cluster summaries and identity tracking on hand-placed particles.
+
+mod common;
+
+use common::{BOUND, blob, tracker};
+use glam::{Vec2, vec2};
+use particle_life_core::{
+    Particle, SimParams,
+    cluster::{ClusterParams, ClusterTracker, convex_hull, dbscan},
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+fn params(num_cultures: u32, culture_size: u32) -> SimParams {
+    SimParams::random(
+        num_cultures,
+        culture_size,
+        50.0,
+        0.5,
+        &mut StdRng::seed_from_u64(0),
+    )
+}
+
+#[test]
+fn dbscan_separates_blobs_from_noise() {
+    let mut particles = blob(vec2(100.0, 100.0), Vec2::ZERO);
+    particles.extend(blob(vec2(300.0, 300.0), Vec2::ZERO));
+    particles.push(Particle {
+        pos: vec2(200.0, 200.0),
+        vel: Vec2::ZERO,
+    });
+    let labels = dbscan(&particles, BOUND, 3.0, 3);
+    assert!(labels[..25].iter().all(|&l| l == Some(0)));
+    assert!(labels[25..50].iter().all(|&l| l == Some(1)));
+    assert_eq!(labels[50], None);
+}
+
+#[test]
+fn clusters_are_summarized() {
+    // Culture 0 is the first blob, culture 1 the second
+    let mut particles = blob(vec2(100.0, 100.0), vec2(1.0, 0.0));
+    particles.extend(blob(vec2(300.0, 300.0), vec2(0.0, -2.0)));
+    let simp = params(2, 25);
+    let mut tracker = tracker();
+    let clusters = tracker.update(&simp, BOUND, &particles);
+    assert_eq!(clusters.len(), 2);
+    let c = &clusters[0];
+    assert_eq!(c.size, 25);
+    assert_eq!(c.cultures, [25, 0]);
+    assert_eq!(c.dominant(), (0, 1.0));
+    assert!(c.centroid.distance(vec2(104.0, 104.0)) < 1e-4);
+    assert_eq!(c.velocity, vec2(1.0, 0.0));
+    assert_eq!(c.outline.len(), 4);
+    assert_eq!(clusters[1].velocity, vec2(0.0, -2.0));
+    assert_eq!(tracker.labels()[30], Some(1));
+}
+
+#[test]
+fn ids_follow_moving_and_splitting_clusters() {
+    let simp = params(1, 50);
+    let mut particles = blob(vec2(100.0, 100.0), Vec2::ZERO);
+    particles.extend(blob(vec2(300.0, 300.0), Vec2::ZERO));
+    let mut tracker = tracker();
+    let ids = |t: &ClusterTracker| {
+        let mut ids = t
+            .clusters()
+            .iter()
+            .map(|c| (c.centroid.x as u32, c.id))
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.into_iter().map(|(_, id)| id).collect::<Vec<_>>()
+    };
+    tracker.update(&simp, BOUND, &particles);
+    let before = ids(&tracker);
+
+    // Both blobs drift
+    for p in &mut particles {
+        p.pos += vec2(5.0, -3.0);
+    }
+    tracker.update(&simp, BOUND, &particles);
+    assert_eq!(ids(&tracker), before);
+    assert!(tracker.clusters().iter().all(|c| c.age == 1));
+
+    // The second blob loses its last two rows to a new cluster far away, the bigger part keeps
+    // the id
+    for p in &mut particles[40..] {
+        p.pos += vec2(100.0, 0.0);
+    }
+    tracker.update(&simp, BOUND, &particles);
+    let clusters = tracker.clusters();
+    assert_eq!(clusters.len(), 3);
+    let second = clusters.iter().find(|c| c.id == before[1]).unwrap();
+    assert_eq!(second.size, 15);
+    assert_eq!(second.age, 2);
+    let split = clusters.iter().max_by_key(|c| c.id).unwrap();
+    assert_eq!(split.size, 10);
+    assert_eq!(split.age, 0);
+    assert!(!before.contains(&split.id));
+}
+
+#[test]
+fn hull_is_counter_clockwise() {
+    let points = [
+        vec2(0.0, 0.0),
+        vec2(1.0, 1.0),
+        vec2(2.0, 0.0),
+        vec2(2.0, 2.0),
+        vec2(0.0, 2.0),
+        vec2(1.0, 0.0),
+    ];
+    let hull = convex_hull(&points);
+    assert_eq!(
+        hull,
+        [
+            vec2(0.0, 0.0),
+            vec2(2.0, 0.0),
+            vec2(2.0, 2.0),
+            vec2(0.0, 2.0)
+        ]
+    );
+}
+
+#[test]
+fn cluster_params_are_validated() {
+    let params = ClusterParams::default();
+    params.validate().unwrap();
+    for eps in [0.0, -1.0, f32::NAN] {
+        assert!(ClusterParams { eps, ..params }.validate().is_err());
+    }
+    let min_points = 0;
+    assert!(
+        ClusterParams {
+            min_points,
+            ..params
+        }
+        .validate()
+        .is_err()
+    );
+}
@@ -0,0 +1,28 @@
+//! Hand-placed particles and the cluster tracker the cluster and fitness tests share.
+
+use glam::{Vec2, vec2};
+use particle_life_core::{
+    Particle,
+    cluster::{ClusterParams, ClusterTracker},
+};
+
+pub const BOUND: Vec2 = vec2(500.0, 500.0);
+
+/// A 5x5 grid of particles 2 apart starting at `corner`
+pub fn blob(corner: Vec2, vel: Vec2) -> Vec<Particle> {
+    (0..25)
+        .map(|i| Particle {
+            pos: corner + vec2((i % 5) as f32, (i / 5) as f32) * 2.0,
+            vel,
+        })
+        .collect()
+}
+
+/// A tracker that finds each blob as a cluster
+pub fn tracker() -> ClusterTracker {
+    ClusterTracker::new(ClusterParams {
+        eps: 3.0,
+        min_points: 3,
+        min_size: 5,
+    })
+}
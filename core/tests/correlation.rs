@@ -0,0 +1,66 @@
+//! g(r) of uniformly spread particles is flat at 1, and of a lattice peaks at the spacing.
+
+use glam::{Vec2, vec2};
+use particle_life_core::{Particle, SimParams, correlation::PairCorrelation};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+fn params(num_cultures: u32, culture_size: u32) -> SimParams {
+    SimParams::random(
+        num_cultures,
+        culture_size,
+        50.0,
+        0.5,
+        &mut StdRng::seed_from_u64(0),
+    )
+}
+
+#[test]
+fn uniform_particles_are_uncorrelated() {
+    let bound = vec2(1000.0, 1000.0);
+    let mut rng = StdRng::seed_from_u64(1);
+    let particles = (0..6000)
+        .map(|_| Particle {
+            pos: vec2(
+                rng.random_range(0.0..bound.x),
+                rng.random_range(0.0..bound.y),
+            ),
+            vel: Vec2::ZERO,
+        })
+        .collect::<Vec<_>>();
+    let gr = PairCorrelation::compute(&params(2, 3000), bound, &particles, 20.0, 4);
+    assert_eq!(gr.pairs.len(), 3);
+    for pair in &gr.pairs {
+        for &g in &pair.g {
+            assert!((g - 1.0).abs() < 0.15, "{pair:?}");
+        }
+    }
+    assert_eq!(gr.pair(1, 0), gr.pair(0, 1));
+
+    let mut csv = vec![];
+    gr.write_csv(&mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    assert_eq!(csv.lines().count(), 1 + 3 * 4);
+    assert!(csv.starts_with("a,b,r,count,g\n0,0,2.5,"));
+}
+
+#[test]
+fn lattice_peaks_at_its_spacing() {
+    // One culture on a square lattice 10 apart, well inside the bound
+    let particles = (0..400)
+        .map(|i| Particle {
+            pos: vec2(100.0, 100.0) + vec2((i % 20) as f32, (i / 20) as f32) * 10.0,
+            vel: Vec2::ZERO,
+        })
+        .collect::<Vec<_>>();
+    let gr = PairCorrelation::compute(&params(1, 400), vec2(400.0, 400.0), &particles, 12.0, 12);
+    let pair = &gr.pairs[0];
+    // 4 neighbours at distance 10 for the inner particles, 2 or 3 on the edges
+    let expected = 4 * 400 - 4 * 20;
+    assert_eq!(pair.counts[10], expected);
+    assert_eq!(pair.counts.iter().sum::<u64>(), expected);
+    assert!((gr.r(10) - 10.5).abs() < 1e-5);
+
+    // A radius well under the particles' spacing in the bound still finds every pair
+    let gr = PairCorrelation::compute(&params(1, 400), vec2(4000.0, 4000.0), &particles, 10.5, 1);
+    assert_eq!(gr.pairs[0].counts, [expected]);
+}
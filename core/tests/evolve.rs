@@ -0,0 +1,193 @@
+//! Fitness functions on hand-built trajectories, the evolution strategy on a toy score, presets
+//! loading as sim params, and recording a short CPU run.
+
+mod common;
+
+use common::{BOUND, blob, tracker};
+use glam::{Vec2, vec2};
+use particle_life_core::{
+    Mesh, Particle, SimParams, Simulator,
+    cluster::ClusterParams,
+    cpu::{CpuOptions, CpuSim},
+    evolve::{
+        self, Candidate, Clustered, Coherence, Evolution, EvolveParams, Fitness, FitnessTerm,
+        Frame, MovingClusters, Persistence, Preset, Trajectory, score_terms, total_score,
+    },
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+/// Two blobs tracked for `frames` updates, one moving and one still, and 50 scattered
+/// particles moving up and down
+fn trajectory(frames: u32) -> Trajectory {
+    let simp = SimParams::random(1, 100, 50.0, 0.5, &mut StdRng::seed_from_u64(0));
+    let mut tracker = tracker();
+    let mut particles = blob(vec2(100.0, 100.0), vec2(1.0, 0.0));
+    particles.extend(blob(vec2(300.0, 300.0), Vec2::ZERO));
+    particles.extend((0..50).map(|i| Particle {
+        pos: vec2(20.0 + i as f32 * 9.0, 450.0),
+        vel: vec2(0.0, if i % 2 == 0 { 1.0 } else { -1.0 }),
+    }));
+    let mut trajectory = Trajectory::default();
+    for step in 0..frames {
+        tracker.update(&simp, BOUND, &particles);
+        trajectory.frames.push(Frame {
+            step: step as u64,
+            particles: particles.clone(),
+            clusters: tracker.clusters().to_vec(),
+            labels: tracker.labels().to_vec(),
+        });
+        for p in &mut particles[..25] {
+            p.pos += p.vel;
+        }
+    }
+    trajectory
+}
+
+#[test]
+fn fitness_functions() {
+    let t = trajectory(5);
+    assert_eq!(MovingClusters::default().score(&t), 1.0);
+    assert_eq!(Clustered.score(&t), 0.5);
+    // Both blobs lasted the whole run
+    assert_eq!(Persistence.score(&t), 0.5);
+    // The moving blob's 25 of the 75 units of speed
+    assert!((Coherence.score(&t) - 1.0 / 3.0).abs() < 1e-6);
+
+    let terms = ["moving:2", "clustered"]
+        .map(|s| s.parse::<FitnessTerm>().unwrap())
+        .to_vec();
+    let scores = score_terms(&terms, &t);
+    assert_eq!(scores, [1.0, 0.5]);
+    assert_eq!(total_score(&terms, &scores), 2.5);
+    let exploded = Trajectory {
+        exploded: true,
+        ..t
+    };
+    assert_eq!(score_terms(&terms, &exploded), [0.0, 0.0]);
+    assert!("spinning".parse::<FitnessTerm>().is_err());
+    assert_eq!(Persistence.score(&Trajectory::default()), 0.0);
+}
+
+#[test]
+fn evolution_climbs_a_toy_score() {
+    // Score meshes by how close they are to a target
+    let target = Mesh::random(3, &mut StdRng::seed_from_u64(1));
+    let score = |m: &Mesh| {
+        -m.as_flat()
+            .iter()
+            .zip(target.as_flat())
+            .map(|(a, b)| (a - b).powi(2))
+            .sum::<f32>()
+    };
+    let params = EvolveParams {
+        population: 30,
+        elites: 8,
+        min_sigma: 0.01,
+        learning_rate: 0.7,
+    };
+    params.validate().unwrap();
+    for elites in [0, 30] {
+        assert!(EvolveParams { elites, ..params }.validate().is_err());
+    }
+    let mut evolution = Evolution::new(3, params, 7);
+    let mut first = None;
+    for generation in 0..30 {
+        let meshes = evolution.ask();
+        assert_eq!(meshes.len(), 30 - evolution.elites().len());
+        assert!(
+            meshes
+                .iter()
+                .flat_map(|m| m.as_flat())
+                .all(|g| g.abs() <= 1.0)
+        );
+        let candidates = meshes
+            .into_iter()
+            .map(|mesh| Candidate {
+                score: score(&mesh),
+                scores: vec![],
+                mesh,
+                generation,
+            })
+            .collect();
+        evolution.tell(candidates);
+        first.get_or_insert(evolution.elites()[0].score);
+    }
+    let best = &evolution.elites()[0];
+    assert!(best.score > first.unwrap());
+    assert!(best.score > -0.05, "{}", best.score);
+    assert!(evolution.mean_sigma() < 0.2);
+    let elites = evolution.elites();
+    assert!(elites.windows(2).all(|w| w[0].score >= w[1].score));
+}
+
+#[test]
+fn presets_load_as_params() {
+    let params = SimParams::random(3, 10, 40.0, 0.2, &mut StdRng::seed_from_u64(2));
+    let preset = Preset {
+        score: 1.5,
+        scores: [("moving".to_string(), 1.5)].into(),
+        generation: 4,
+        params: params.clone(),
+    };
+    let path =
+        std::env::temp_dir().join(format!("particle-life-preset-{}.json", std::process::id()));
+    preset.save(&path).unwrap();
+    let json = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let loaded = SimParams::from_json(&json).unwrap();
+    assert_eq!(loaded.mesh, params.mesh);
+    let preset: Preset = serde_json::from_str(&json).unwrap();
+    assert_eq!(preset.scores["moving"], 1.5);
+    assert_eq!(preset.generation, 4);
+}
+
+#[test]
+fn record_cpu_run() {
+    let simp = SimParams::random(3, 60, 40.0, 0.5, &mut StdRng::seed_from_u64(3));
+    let bound = Vec2::splat(300.0);
+    let opts = CpuOptions {
+        bound,
+        seed: Some(3),
+        ..Default::default()
+    };
+    let mut sim = CpuSim::new(simp, opts);
+    let t = evolve::record(
+        &mut sim,
+        200,
+        120,
+        40,
+        bound,
+        ClusterParams::default(),
+        |sim, n| {
+            for _ in 0..n {
+                sim.step(1.0);
+            }
+        },
+    );
+    assert!(!t.exploded);
+    let steps = t.frames.iter().map(|f| f.step).collect::<Vec<_>>();
+    assert_eq!(steps, [120, 160, 200]);
+    assert_eq!(sim.step_count(), 200);
+    assert!(t.frames.iter().all(|f| f.labels.len() == 180));
+}
+
+#[test]
+fn warmup_must_end_before_the_run() {
+    evolve::validate_record(200, 199).unwrap();
+    assert!(evolve::validate_record(200, 200).is_err());
+    let bound = Vec2::splat(300.0);
+    let simp = SimParams::random(2, 10, 40.0, 0.5, &mut StdRng::seed_from_u64(3));
+    let mut sim = CpuSim::new(simp, CpuOptions::default());
+    let record = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        evolve::record(
+            &mut sim,
+            100,
+            100,
+            10,
+            bound,
+            ClusterParams::default(),
+            |_, _| {},
+        )
+    }));
+    assert!(record.is_err());
+}
@@ -0,0 +1,70 @@
+//! Speed caps and the energy explosion detector, on the CPU backend.
+
+use glam::vec2;
+use particle_life_core::{
+    Particle, SimParams, Simulator,
+    cpu::{CpuBackend, CpuOptions, CpuSim},
+    stability::{EnergyMonitor, OnExplosion, kinetic_energy},
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+fn sim(simp: &SimParams, speed: f32) -> CpuSim {
+    let opts = CpuOptions {
+        backend: CpuBackend::Naive,
+        bound: vec2(500.0, 500.0),
+        seed: Some(0),
+        ..Default::default()
+    };
+    let n = simp.num_particles() as usize;
+    let particles = (0..n)
+        .map(|i| Particle {
+            pos: vec2(10.0 + (i % 20) as f32 * 20.0, 10.0 + (i / 20) as f32 * 20.0),
+            vel: vec2(speed, 0.0),
+        })
+        .collect();
+    CpuSim::with_particles(simp.clone(), opts, particles)
+}
+
+#[test]
+fn speed_is_capped() {
+    let mut simp = SimParams::random(2, 50, 50.0, 1.0, &mut StdRng::seed_from_u64(1));
+    simp.max_speed = 0.5;
+    let mut sim = sim(&simp, 10.0);
+    sim.step(1.0);
+    for p in sim.particles() {
+        assert!(p.vel.length() <= 0.5 + 1e-5, "{p:?}");
+    }
+}
+
+#[test]
+fn explosion_is_flagged_and_clamped() {
+    let simp = SimParams::random(2, 50, 50.0, 0.25, &mut StdRng::seed_from_u64(2));
+    let mut sim = sim(&simp, 0.0);
+    let mut monitor = EnergyMonitor::new(10.0);
+    for _ in 0..5 {
+        sim.step(1.0);
+        assert!(monitor.check(&mut sim, OnExplosion::Clamp).is_none());
+    }
+
+    let particles = sim
+        .particles()
+        .iter()
+        .map(|p| Particle {
+            vel: p.vel + vec2(1e3, 0.0),
+            ..*p
+        })
+        .collect();
+    let params = sim.params().clone();
+    let mut sim = CpuSim::with_particles(params, *sim.options(), particles);
+    let explosion = monitor
+        .check(&mut sim, OnExplosion::Clamp)
+        .expect("explosion not flagged");
+    assert!(explosion.energy > 10.0 * explosion.baseline);
+    let speed = explosion.clamped_speed.expect("speed not clamped");
+    assert_eq!(sim.params().max_speed, speed);
+
+    sim.step(1.0);
+    assert!(kinetic_energy(sim.particles()) <= 0.5 * speed * speed + 1e-3);
+    // The baseline restarts, so the same blow up isn't flagged twice
+    assert!(monitor.check(&mut sim, OnExplosion::Warn).is_none());
+}
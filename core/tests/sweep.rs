@@ -0,0 +1,136 @@
+//! Sweep grids and samples, resuming a results table, and a short CPU run.
+
+use glam::Vec2;
+use particle_life_core::{
+    Simulator,
+    cluster::ClusterParams,
+    cpu::{CpuOptions, CpuSim},
+    sweep::{self, Axis, MeshGen, SweepResults, SweepSettings, SweepSpec},
+};
+
+fn spec() -> SweepSpec {
+    SweepSpec {
+        meshes: vec![MeshGen::Uniform, "sparse:0.5".parse().unwrap()],
+        cultures: "3".parse().unwrap(),
+        culture_size: "20,40".parse().unwrap(),
+        aoe: "20..80".parse().unwrap(),
+        damping: Axis::List(vec![0.1]),
+        seeds: "0..2".parse().unwrap(),
+        resolution: 3,
+        sample: None,
+        sample_seed: 0,
+    }
+}
+
+#[test]
+fn axes_parse() {
+    assert_eq!("1,2,5".parse(), Ok(Axis::<u32>::List(vec![1, 2, 5])));
+    assert_eq!("0.5..1".parse(), Ok(Axis::<f32>::Range(0.5, 1.0)));
+    assert!("5..5".parse::<Axis<u32>>().is_err());
+    assert!("a,b".parse::<Axis<f32>>().is_err());
+    assert_eq!("sparse:0.25".parse(), Ok(MeshGen::Sparse(0.25)));
+    assert!("dense".parse::<MeshGen>().is_err());
+    assert!("sparse:NaN".parse::<MeshGen>().is_err());
+    assert!("sparse:1.5".parse::<MeshGen>().is_err());
+}
+
+#[test]
+fn grid_and_sample_runs() {
+    let runs = spec().runs();
+    // 2 meshes, 2 sizes, 3 aoes and 2 seeds
+    assert_eq!(runs.len(), 24);
+    assert!(runs.iter().enumerate().all(|(i, r)| r.id == i));
+    let aoes = runs[..6].iter().map(|r| r.aoe).collect::<Vec<_>>();
+    assert_eq!(aoes, [20.0, 20.0, 50.0, 50.0, 80.0, 80.0]);
+
+    let sample = SweepSpec {
+        sample: Some(50),
+        ..spec()
+    };
+    let runs = sample.runs();
+    assert_eq!(runs.len(), 50);
+    assert_eq!(runs, sample.runs());
+    assert!(
+        runs.iter()
+            .all(|r| (20.0..=80.0).contains(&r.aoe) && r.seed < 2)
+    );
+
+    let simp = runs[0].params();
+    simp.validate().unwrap();
+    assert_eq!(simp.mesh, runs[0].params().mesh);
+    let sparse = runs.iter().find(|r| r.mesh != MeshGen::Uniform).unwrap();
+    let zeros = sparse
+        .params()
+        .mesh
+        .as_flat()
+        .iter()
+        .filter(|&&g| g == 0.0)
+        .count();
+    assert!(zeros > 0);
+}
+
+#[test]
+fn results_resume() {
+    let runs = spec().runs();
+    let path =
+        std::env::temp_dir().join(format!("particle-life-resume-{}.csv", std::process::id()));
+    let settings = SweepSettings {
+        steps: 100,
+        bound: Vec2::splat(300.0),
+        backend: "cpu".into(),
+        theta: 0.9,
+        clusters: ClusterParams::default(),
+    };
+    let (mut results, done) = SweepResults::open(&path, &runs, &settings).unwrap();
+    assert!(done.is_empty());
+    results.write(&runs[0], &Default::default(), 1.0).unwrap();
+    results.write(&runs[3], &Default::default(), 1.0).unwrap();
+    drop(results);
+
+    // An interrupted write leaves a partial row, which is dropped
+    let mut text = std::fs::read_to_string(&path).unwrap();
+    text.push_str("5,uni");
+    std::fs::write(&path, &text).unwrap();
+    let (_, done) = SweepResults::open(&path, &runs, &settings).unwrap();
+    let mut done = done.into_iter().collect::<Vec<_>>();
+    done.sort();
+    assert_eq!(done, [0, 3]);
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+    // Another sweep doesn't match the rows
+    let other = SweepSpec {
+        seeds: "5..7".parse().unwrap(),
+        ..spec()
+    };
+    assert!(SweepResults::open(&path, &other.runs(), &settings).is_err());
+    // Nor do the rows of runs with more steps
+    let longer = SweepSettings {
+        steps: 200,
+        ..settings.clone()
+    };
+    assert!(SweepResults::open(&path, &runs, &longer).is_err());
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(path.with_extension("json")).unwrap();
+}
+
+#[test]
+fn cpu_run() {
+    let run = spec().runs()[4];
+    let bound = Vec2::splat(300.0);
+    let opts = CpuOptions {
+        bound,
+        seed: Some(run.seed),
+        ..Default::default()
+    };
+    let mut sim = CpuSim::new(run.params(), opts);
+    let obs = sweep::run(&mut sim, 150, bound, ClusterParams::default(), |sim, n| {
+        for _ in 0..n {
+            sim.step(1.0);
+        }
+    });
+    assert_eq!(obs.steps, 150);
+    assert_eq!(sim.step_count(), 150);
+    assert!(!obs.exploded);
+    assert!(obs.kinetic_energy.is_finite() && obs.spread > 0.0);
+    assert!((0.0..=1.0).contains(&obs.clustered));
+}
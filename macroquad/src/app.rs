@@ -1,17 +1,46 @@
-use std::time::{Duration, Instant};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use egui_macroquad::egui::{self, Widget};
 use glam::{Vec2, vec2};
 use macroquad::{
-    input::{KeyCode, is_key_pressed},
+    input::{
+        KeyCode, MouseButton, is_key_pressed, is_mouse_button_down, is_mouse_button_pressed,
+        is_mouse_button_released, mouse_position,
+    },
     miniquad,
 };
+use particle_life_core::{
+    Mesh, SimParams,
+    cluster::{ClusterParams, ClusterTracker},
+    field::ForceFields,
+    obstacle::{CircleObstacle, Obstacles, OccupancyMask, SegmentObstacle},
+    sim::Repulsion,
+    stability::{EnergyMonitor, OnExplosion},
+    stats::StatsWriter,
+};
 use quadtree::shapes::Rect;
 
-use super::sim::{SimConfig, World};
+use super::sim::{Backend, SimConfig, World};
+
+const MASK_CELL_SIZE: f32 = 8.0;
+/// Physics steps between cluster updates while the overlay is on
+const CLUSTER_STEPS: u64 = 10;
+const MAX_LISTED_CLUSTERS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ObstacleTool {
+    None,
+    Circle,
+    Wall,
+    Paint,
+}
 
 #[derive(Clone, Debug)]
 pub struct Config {
+    pub mesh: Option<Mesh>,
     pub bound: Rect,
     pub num_cultures: usize,
     pub culture_size: usize,
@@ -20,12 +49,29 @@ pub struct Config {
     pub damping: f32,
     pub cursor_aoe: f32,
     pub cursor_force: f32,
+    pub obstacles: Obstacles,
+    pub fields: ForceFields,
+    pub temperature: f32,
+    pub repulsion: Repulsion,
+    pub max_force: f32,
+    pub max_speed: f32,
+    pub on_explosion: OnExplosion,
+    pub explosion_factor: f32,
+    /// Steps between stats
+    pub stats_every: u32,
+    /// Stream stats here, as csv or json lines
+    pub stats_out: Option<PathBuf>,
+    pub clusters: ClusterParams,
+    pub seed: Option<u64>,
+    pub backend: Backend,
 }
 
 impl Default for Config {
     fn default() -> Self {
+        let bound = Rect::new(Vec2::ZERO, vec2(1000.0, 800.0));
         Self {
-            bound: Rect::new(Vec2::ZERO, vec2(1000.0, 800.0)),
+            mesh: None,
+            bound,
             num_cultures: 5,
             culture_size: 5000,
             aoe: 100.0,
@@ -33,6 +79,19 @@ impl Default for Config {
             damping: 0.5,
             cursor_aoe: 200.0,
             cursor_force: 400.0,
+            obstacles: Obstacles::default(),
+            fields: ForceFields::centered(bound.center()),
+            temperature: 0.0,
+            repulsion: Repulsion::default(),
+            max_force: 0.0,
+            max_speed: 0.0,
+            on_explosion: OnExplosion::Warn,
+            explosion_factor: 100.0,
+            stats_every: 10,
+            stats_out: None,
+            clusters: ClusterParams::default(),
+            seed: None,
+            backend: Backend::BarnesHut,
         }
     }
 }
@@ -40,6 +99,7 @@ impl Default for Config {
 impl Config {
     fn freeze(&self) -> SimConfig {
         SimConfig {
+            mesh: self.mesh.clone(),
             bound: self.bound,
             num_cultures: self.num_cultures,
             culture_size: self.culture_size,
@@ -48,6 +108,14 @@ impl Config {
             damping: self.damping,
             cursor_aoe2: self.cursor_aoe * self.cursor_aoe,
             cursor_force: self.cursor_force,
+            obstacles: self.obstacles.clone(),
+            fields: self.fields,
+            temperature: self.temperature,
+            repulsion: self.repulsion,
+            max_force: self.max_force,
+            max_speed: self.max_speed,
+            seed: self.seed,
+            backend: self.backend,
             ..Default::default()
         }
     }
@@ -56,6 +124,18 @@ impl Config {
 pub struct App {
     conf: Config,
     world: World,
+    monitor: EnergyMonitor,
+    paused: bool,
+    stats_writer: Option<StatsWriter>,
+    clusters: ClusterTracker,
+    show_clusters: bool,
+
+    // Obstacle editing
+    tool: ObstacleTool,
+    brush_radius: f32,
+    wall_thickness: f32,
+    drag_start: Option<Vec2>,
+    egui_wants_pointer: bool,
 
     // Debug
     show_fps: bool,
@@ -67,12 +147,25 @@ pub struct App {
 }
 
 impl App {
-    pub fn new() -> Self {
-        let conf = Config::default();
+    pub fn new(conf: Config) -> Self {
         let world = World::new(conf.freeze());
+        let stats_writer = conf.stats_out.as_deref().map(|path| {
+            StatsWriter::create(path)
+                .unwrap_or_else(|e| panic!("failed to create {}: {e}", path.display()))
+        });
         Self {
+            monitor: EnergyMonitor::new(conf.explosion_factor),
+            paused: false,
+            stats_writer,
+            clusters: ClusterTracker::new(conf.clusters),
+            show_clusters: false,
             conf,
             world,
+            tool: ObstacleTool::None,
+            brush_radius: 20.0,
+            wall_thickness: 6.0,
+            drag_start: None,
+            egui_wants_pointer: false,
             show_fps: true,
             fps: 0,
             frames: 0,
@@ -81,9 +174,28 @@ impl App {
     }
 
     pub fn physics_step(&mut self, tau: f32) {
+        if self.paused {
+            return;
+        }
         self.world.step(tau);
         self.frames += 1;
 
+        if let Some(explosion) = self
+            .world
+            .check_energy(&mut self.monitor, self.conf.on_explosion)
+        {
+            println!("Warning: {explosion}");
+            if let Some(speed) = explosion.clamped_speed {
+                self.conf.max_speed = speed;
+            }
+            self.paused = self.conf.on_explosion == OnExplosion::Pause;
+        }
+        self.write_stats();
+        if self.show_clusters && self.world.step_count().is_multiple_of(CLUSTER_STEPS) {
+            self.clusters.params = self.conf.clusters;
+            self.world.update_clusters(&mut self.clusters);
+        }
+
         if self.last_tick.elapsed() >= Duration::from_secs(1) {
             self.fps = self.frames;
             self.frames = 0;
@@ -91,8 +203,33 @@ impl App {
         }
     }
 
+    fn write_stats(&mut self) {
+        let Some(writer) = &mut self.stats_writer else {
+            return;
+        };
+        if !self
+            .world
+            .step_count()
+            .is_multiple_of(self.conf.stats_every.max(1) as u64)
+        {
+            return;
+        }
+        let stats = self.world.stats();
+        if let Err(e) = writer.write(&stats).and_then(|()| writer.flush()) {
+            println!("Failed to write stats: {e}");
+            self.stats_writer = None;
+        }
+    }
+
+    /// Params of the running world in the format shared with the wgpu binary
+    fn sim_params(&self) -> SimParams {
+        self.world.params().clone()
+    }
+
     fn reset_world(&mut self) {
         self.world = World::new(self.conf.freeze());
+        self.monitor.reset();
+        self.clusters.reset();
     }
 
     fn handle_input(&mut self) {
@@ -103,18 +240,108 @@ impl App {
         if is_key_pressed(KeyCode::R) {
             self.reset_world();
         }
+
+        if is_key_pressed(KeyCode::P) {
+            self.paused = !self.paused;
+        }
+
+        if is_key_pressed(KeyCode::C) {
+            self.show_clusters = !self.show_clusters;
+        }
+
+        if !self.egui_wants_pointer {
+            self.edit_obstacles();
+        }
+    }
+
+    /// Apply the selected obstacle tool. Left click draws, right click erases.
+    fn edit_obstacles(&mut self) {
+        let (mx, my) = mouse_position();
+        let mouse = vec2(mx, my);
+        let obstacles = &mut self.conf.obstacles;
+
+        let changed = match self.tool {
+            ObstacleTool::None => false,
+            ObstacleTool::Circle | ObstacleTool::Wall => {
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    self.drag_start = Some(mouse);
+                }
+                if is_mouse_button_pressed(MouseButton::Right) {
+                    obstacles.remove_at(mouse);
+                    true
+                } else if is_mouse_button_released(MouseButton::Left)
+                    && let Some(start) = self.drag_start.take()
+                {
+                    if self.tool == ObstacleTool::Circle {
+                        obstacles.circles.push(CircleObstacle {
+                            center: start,
+                            radius: start.distance(mouse),
+                        });
+                    } else {
+                        obstacles.segments.push(SegmentObstacle {
+                            a: start,
+                            b: mouse,
+                            radius: self.wall_thickness / 2.0,
+                        });
+                    }
+                    true
+                } else {
+                    false
+                }
+            }
+            ObstacleTool::Paint => {
+                let paint = is_mouse_button_down(MouseButton::Left);
+                let erase = is_mouse_button_down(MouseButton::Right);
+                if paint || erase {
+                    let bound = self.conf.bound;
+                    obstacles
+                        .mask
+                        .get_or_insert_with(|| OccupancyMask::new(bound.bb(), MASK_CELL_SIZE))
+                        .paint(mouse, self.brush_radius, paint);
+                }
+                paint || erase
+            }
+        };
+
+        if changed {
+            self.world.set_obstacles(self.conf.obstacles.clone());
+        }
+    }
+
+    fn render_tool_preview(&self) {
+        use macroquad::prelude::*;
+
+        let (mx, my) = mouse_position();
+        match (self.tool, self.drag_start) {
+            (ObstacleTool::Circle, Some(start)) => {
+                let radius = start.distance(::glam::vec2(mx, my));
+                draw_circle_lines(start.x, start.y, radius, 1.0, GRAY);
+            }
+            (ObstacleTool::Wall, Some(start)) => {
+                draw_line(start.x, start.y, mx, my, self.wall_thickness, GRAY);
+            }
+            (ObstacleTool::Paint, _) => {
+                draw_circle_lines(mx, my, self.brush_radius, 1.0, GRAY);
+            }
+            _ => (),
+        }
     }
 
     pub fn render(&mut self) {
         use macroquad::prelude::*;
 
         self.world.render();
+        if self.show_clusters {
+            self.world.render_clusters(&self.clusters);
+        }
+        self.render_tool_preview();
 
         self.handle_input();
+        self.world.set_interactive(self.tool == ObstacleTool::None);
 
         if self.show_fps {
             draw_text(
-                &format!("{} FPS", self.fps),
+                format!("{} FPS", self.fps),
                 screen_width() - 40.0,
                 10.0,
                 12.0,
@@ -122,7 +349,10 @@ impl App {
             );
         }
 
+        // Live params are applied only when a slider moves, each apply clones the params
+        let mut changed = false;
         egui_macroquad::ui(|ctx| {
+            self.egui_wants_pointer = ctx.wants_pointer_input() || ctx.is_pointer_over_area();
             egui::Window::new("Simulation Config")
                 .default_open(false)
                 .show(ctx, |ui| {
@@ -132,22 +362,122 @@ impl App {
                     egui::Slider::new(&mut self.conf.culture_size, 1..=10000)
                         .text("Culture Size")
                         .ui(ui);
-                    egui::Slider::new(&mut self.conf.aoe, 0.0..=300.0)
+                    changed |= egui::Slider::new(&mut self.conf.aoe, 1.0..=300.0)
                         .text("Particle AOE")
-                        .ui(ui);
+                        .ui(ui)
+                        .changed();
                     egui::Slider::new(&mut self.conf.cursor_aoe, 0.0..=300.0)
                         .text("Cursor AOE")
                         .ui(ui);
                     egui::Slider::new(&mut self.conf.cursor_force, 0.0..=500.0)
                         .text("Cursor Force")
                         .ui(ui);
+                    changed |= egui::Slider::new(&mut self.conf.temperature, 0.0..=10.0)
+                        .text("Temperature")
+                        .ui(ui)
+                        .changed();
+                    let aoe = self.world.params().aoe;
+                    changed |= egui::Slider::new(&mut self.conf.repulsion.radius, 0.0..=aoe)
+                        .text("Repulsion Radius")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut self.conf.repulsion.strength, 0.0..=10.0)
+                        .text("Repulsion Strength")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut self.conf.max_force, 0.0..=100.0)
+                        .text("Max Force (0 = off)")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut self.conf.max_speed, 0.0..=100.0)
+                        .text("Max Speed (0 = off)")
+                        .ui(ui)
+                        .changed();
+                    ui.separator();
+                    ui.label("Force Fields");
+                    let fields = &mut self.conf.fields;
+                    changed |= egui::Slider::new(&mut fields.gravity.x, -1.0..=1.0)
+                        .text("Gravity X")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut fields.gravity.y, -1.0..=1.0)
+                        .text("Gravity Y")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut fields.attractor.strength, -2.0..=2.0)
+                        .text("Attractor")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut fields.vortex.strength, -2.0..=2.0)
+                        .text("Vortex")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut fields.flow.strength, 0.0..=2.0)
+                        .text("Flow Strength")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut fields.flow.scale, 0.0..=0.05)
+                        .text("Flow Scale")
+                        .ui(ui)
+                        .changed();
+                    changed |= egui::Slider::new(&mut fields.flow.speed, 0.0..=0.1)
+                        .text("Flow Speed")
+                        .ui(ui)
+                        .changed();
+                    ui.separator();
+                    ui.label("Obstacle Tool");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.tool, ObstacleTool::None, "None");
+                        ui.radio_value(&mut self.tool, ObstacleTool::Circle, "Circle");
+                        ui.radio_value(&mut self.tool, ObstacleTool::Wall, "Wall");
+                        ui.radio_value(&mut self.tool, ObstacleTool::Paint, "Paint");
+                    });
+                    egui::Slider::new(&mut self.wall_thickness, 1.0..=50.0)
+                        .text("Wall Thickness")
+                        .ui(ui);
+                    egui::Slider::new(&mut self.brush_radius, 1.0..=100.0)
+                        .text("Brush Radius")
+                        .ui(ui);
+                    if ui.button("Clear obstacles").clicked() {
+                        self.conf.obstacles = Obstacles::default();
+                        self.world.set_obstacles(Obstacles::default());
+                    }
+                    if ui.button("Print obstacles").clicked() {
+                        println!("Obstacles: {}", self.world.export_obstacles_json());
+                    }
                     ui.separator();
                     ui.checkbox(&mut self.show_fps, "Show FPS");
+                    ui.checkbox(&mut self.paused, "Paused");
+                    ui.checkbox(&mut self.show_clusters, "Show clusters");
+                    if self.show_clusters {
+                        let mut params = self.conf.clusters;
+                        egui::Slider::new(&mut params.eps, 1.0..=50.0)
+                            .text("Cluster Radius")
+                            .ui(ui);
+                        egui::Slider::new(&mut params.min_points, 1..=20)
+                            .text("Min Neighbours")
+                            .ui(ui);
+                        egui::Slider::new(&mut params.min_size, 1..=500)
+                            .text("Min Cluster Size")
+                            .ui(ui);
+                        if params.validate().is_ok() {
+                            self.conf.clusters = params;
+                        }
+                        ui.label(format!("Clusters: {}", self.clusters.clusters().len()));
+                        for cluster in self.clusters.clusters().iter().take(MAX_LISTED_CLUSTERS) {
+                            ui.monospace(cluster.to_string());
+                        }
+                    }
                     // ui.checkbox(&mut self.conf.gpu, "GPU");
                     ui.separator();
                     if ui.button("Run").clicked() {
+                        // New rules
+                        self.conf.mesh = None;
                         self.reset_world();
                     }
+                    if ui.button("Print SimParams").clicked() {
+                        println!("SimParams\n{}", self.sim_params().to_json());
+                    }
                     if ui.button("Print gravity mesh").clicked() {
                         let mesh = self.world.export_gravity_mesh_json();
                         println!("Gravity mesh: {:?}", &mesh);
@@ -159,12 +489,21 @@ impl App {
                     // if ui.button("Paste gravity mesh").clicked() {
                     //     if let Some(mesh) = miniquad::window::clipboard_get() {
                     //         let mut sim_config = self.conf.freeze();
-                    //         sim_config.mesh_json = Some(mesh);
+                    //         sim_config.mesh = serde_json::from_str(&mesh).ok();
                     //         self.world = World::new(sim_config);
                     //     }
                     // }
                 });
         });
+        if changed {
+            self.conf.repulsion.radius = self.conf.repulsion.radius.min(self.conf.aoe);
+            self.world.set_aoe(self.conf.aoe);
+            self.world.set_fields(self.conf.fields);
+            self.world.set_temperature(self.conf.temperature);
+            self.world.set_repulsion(self.conf.repulsion);
+            self.world
+                .set_limits(self.conf.max_force, self.conf.max_speed);
+        }
         egui_macroquad::draw();
     }
 }
@@ -2,19 +2,90 @@ mod app;
 mod sim;
 mod util;
 
+use std::path::PathBuf;
+
 use ::glam::{Vec2, vec2};
-use app::App;
+use ::rand::{SeedableRng, rngs::StdRng};
+use app::{App, Config};
+use clap::{CommandFactory, Parser, error::ErrorKind};
 use macroquad::prelude::*;
+use particle_life_core::{
+    SimParams, cluster::ClusterParams, field::ForceFields, sim::Repulsion, stability::OnExplosion,
+};
 use quadtree::shapes::Rect;
+use sim::Backend;
 
-const VEL_RATIO: f64 = 15.0;
 const SIM_TIMESTEP: f64 = 1.0 / 60.0; // secs
 const MAX_ACCUMULATOR: f64 = 1.0;
 
-const BOUND: Rect = Rect::new(Vec2::ZERO, vec2(1000.0, 800.0));
+#[derive(Parser)]
+struct Args {
+    /// Sim Params json string
+    simp: Option<String>,
+    #[arg(short, long, default_value_t = 5)]
+    cultures: u32,
+    #[arg(short, long, default_value_t = 5000)]
+    particles: u32,
+    #[arg(short, long, default_value_t = 100.0)]
+    aoe: f32,
+    #[arg(short, long, default_value_t = 0.5)]
+    damping: f32,
+    #[arg(short, long, default_value_t = 0.0)]
+    temperature: f32,
+    /// Hard-core radius particles repel each other within, 0 to disable
+    #[arg(long, default_value_t = 0.0)]
+    repulsion_radius: f32,
+    /// Repulsion at contact, falling to 0 at the radius
+    #[arg(long, default_value_t = 1.0)]
+    repulsion_strength: f32,
+    /// Cap on the force applied to a particle each step, 0 for no cap
+    #[arg(long, default_value_t = 0.0)]
+    max_force: f32,
+    /// Cap on particle speed, 0 for no cap
+    #[arg(long, default_value_t = 0.0)]
+    max_speed: f32,
+    /// What to do when the kinetic energy explodes: off, warn, pause or clamp (caps the speed)
+    #[arg(long, default_value_t = OnExplosion::Warn)]
+    on_explosion: OnExplosion,
+    /// Energy over its running baseline that counts as an explosion
+    #[arg(long, default_value_t = 100.0)]
+    explosion_factor: f32,
+    /// Stream per culture stats here, csv for a .csv path and json lines otherwise
+    #[arg(long)]
+    stats_out: Option<PathBuf>,
+    /// Steps between stats
+    #[arg(long, default_value_t = 10)]
+    stats_every: u32,
+    /// Radius within which particles are neighbours for cluster detection
+    #[arg(long, default_value_t = 10.0)]
+    cluster_eps: f32,
+    /// Neighbours, counting the particle itself, a particle needs to seed or grow a cluster
+    #[arg(long, default_value_t = 4)]
+    cluster_min_points: usize,
+    /// Smallest cluster shown
+    #[arg(long, default_value_t = 10)]
+    cluster_min_size: usize,
+    /// Barnes-Hut opening angle
+    #[arg(long, default_value_t = 0.9)]
+    theta: f32,
+    /// World width, unless the SimParams json sets a bound
+    #[arg(long, default_value_t = 1000.0)]
+    width: f32,
+    /// World height, unless the SimParams json sets a bound
+    #[arg(long, default_value_t = 800.0)]
+    height: f32,
+    /// Seed for the gravity mesh, colors, spawn positions and noise
+    #[arg(short, long)]
+    seed: Option<u64>,
+    #[arg(short, long, value_enum, default_value_t = Backend::BarnesHut)]
+    backend: Backend,
+    /// Sim distance covered per second of velocity
+    #[arg(long, default_value_t = 15.0)]
+    vel_ratio: f64,
+}
 
-fn window_conf() -> Conf {
-    let window_bound = BOUND.bb() * 1.5;
+fn window_conf(bound: Rect) -> Conf {
+    let window_bound = bound.bb() * 1.5;
     Conf {
         window_title: "Particle Life".to_string(),
         window_height: window_bound.y as i32,
@@ -23,11 +94,74 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let mut app = App::new();
+fn main() {
+    let args = Args::parse();
+    let clusters = ClusterParams {
+        eps: args.cluster_eps,
+        min_points: args.cluster_min_points,
+        min_size: args.cluster_min_size,
+    };
+    if let Err(e) = clusters.validate() {
+        Args::command().error(ErrorKind::ValueValidation, e).exit();
+    }
+    let mut simp = match args.simp {
+        Some(s) => SimParams::from_json(&s).unwrap_or_else(|e| panic!("{e}")),
+        None => {
+            let mut rng = match args.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_rng(&mut ::rand::rng()),
+            };
+            let mut simp = SimParams::random(
+                args.cultures,
+                args.particles,
+                args.aoe,
+                args.damping,
+                &mut rng,
+            );
+            simp.fields = ForceFields::centered(vec2(args.width, args.height) / 2.0);
+            simp.temperature = args.temperature;
+            simp.repulsion = Repulsion {
+                radius: args.repulsion_radius,
+                strength: args.repulsion_strength,
+            };
+            simp.max_force = args.max_force;
+            simp.max_speed = args.max_speed;
+            simp
+        }
+    };
+    let bound = *simp.bound.get_or_insert(vec2(args.width, args.height));
+    println!("SimParams\n{}", simp.to_json());
+
+    let conf = Config {
+        mesh: Some(simp.mesh),
+        bound: Rect::new(Vec2::ZERO, bound),
+        num_cultures: simp.num_cultures as usize,
+        culture_size: simp.culture_size as usize,
+        aoe: simp.aoe,
+        damping: simp.damping,
+        theta: args.theta,
+        obstacles: simp.obstacles,
+        fields: simp.fields,
+        temperature: simp.temperature,
+        repulsion: simp.repulsion,
+        max_force: simp.max_force,
+        max_speed: simp.max_speed,
+        on_explosion: args.on_explosion,
+        explosion_factor: args.explosion_factor,
+        stats_every: args.stats_every,
+        stats_out: args.stats_out,
+        clusters,
+        seed: args.seed,
+        backend: args.backend,
+        ..Default::default()
+    };
+
+    let tau = (args.vel_ratio * SIM_TIMESTEP) as f32;
+    macroquad::Window::from_config(window_conf(conf.bound), run(conf, tau));
+}
 
-    let tau = (VEL_RATIO * SIM_TIMESTEP) as f32;
+async fn run(conf: Config, tau: f32) {
+    let mut app = App::new(conf);
 
     let mut acc = 0.0;
     let mut last_tick = get_time();
@@ -1,21 +1,36 @@
-#![allow(unused)]
-
 use glam::{Vec2, vec2};
-use macroquad::{color::Color, input::MouseButton};
-use quadtree::{
-    BHQuadtree, Point, Quadtree, WeightedPoint,
-    shapes::{Rect, Shape},
+use macroquad::{
+    color::Color,
+    input::{MouseButton, is_mouse_button_down, mouse_position},
+};
+use quadtree::shapes::Rect;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use particle_life_core::{
+    Mesh, SimParams, Simulator,
+    cluster::ClusterTracker,
+    cpu::{CpuBackend, CpuOptions, CpuSim},
+    field::ForceFields,
+    obstacle::Obstacles,
+    params::SIM_PARAMS_VERSION,
+    sim::{Cursor, Repulsion},
+    stability::{EnergyMonitor, Explosion, OnExplosion},
+    stats::Stats,
 };
-use rand::Rng;
-use rand_distr::{Distribution, Uniform};
 
-use crate::util::{random_color, random_gravity_mesh};
+use crate::util::random_color;
 
-const DAMPING: f32 = 0.5;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// Exact O(n^2) force computation
+    Naive,
+    /// Barnes-Hut quadtree approximation
+    BarnesHut,
+}
 
 #[derive(Clone, Debug)]
 pub struct SimConfig {
-    pub mesh_json: Option<String>,
+    pub mesh: Option<Mesh>,
     pub bound: Rect,
     pub num_cultures: usize,
     pub culture_size: usize,
@@ -25,12 +40,22 @@ pub struct SimConfig {
     pub cursor_aoe2: f32,
     pub cursor_force: f32,
     pub is_interactive: bool,
+    pub obstacles: Obstacles,
+    pub fields: ForceFields,
+    /// Variance of the gaussian velocity noise added to each particle every step
+    pub temperature: f32,
+    pub repulsion: Repulsion,
+    /// Caps on the force per step and the speed, 0 for no cap
+    pub max_force: f32,
+    pub max_speed: f32,
+    pub seed: Option<u64>,
+    pub backend: Backend,
 }
 
 impl Default for SimConfig {
     fn default() -> Self {
         Self {
-            mesh_json: None,
+            mesh: None,
             bound: Rect::new(Vec2::ZERO, vec2(1000.0, 800.0)),
             num_cultures: 5,
             culture_size: 5000,
@@ -40,293 +65,268 @@ impl Default for SimConfig {
             cursor_aoe2: 200.0 * 200.0,
             cursor_force: 400.0,
             is_interactive: true,
+            obstacles: Obstacles::default(),
+            fields: ForceFields::default(),
+            temperature: 0.0,
+            repulsion: Repulsion::default(),
+            max_force: 0.0,
+            max_speed: 0.0,
+            seed: None,
+            backend: Backend::BarnesHut,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct Particle {
-    // pub last_pos: Vec2,
-    pub pos: Vec2,
-    pub vel: Vec2,
-}
-
-impl Particle {
-    fn new(bound: Rect) -> Self {
-        let mut rng = rand::rng();
-        Self {
-            pos: vec2(
-                rng.random_range(0..bound.bb().x as u32) as f32,
-                rng.random_range(0..bound.bb().y as u32) as f32,
-            ),
-            vel: Vec2::ZERO,
+impl From<Backend> for CpuBackend {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Naive => CpuBackend::Naive,
+            Backend::BarnesHut => CpuBackend::BarnesHut,
         }
     }
+}
 
-    /// Get the force another particle exerts on this particle given the gravitational constant g.
-    fn _naive_force(&self, other: &Particle, g: f32, aoe2: f32) -> Vec2 {
-        let d2 = Vec2::distance_squared(self.pos, other.pos);
-        if d2 > 0.0 && d2 <= aoe2 {
-            let dir = (other.pos - self.pos).normalize();
-            dir * g
-        } else {
-            Vec2::ZERO
+pub struct World {
+    conf: SimConfig,
+    sim: CpuSim,
+    colors: Vec<Color>,
+}
+
+impl World {
+    pub fn new(mut conf: SimConfig) -> Self {
+        let mut rng = match conf.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(&mut rand::rng()),
+        };
+
+        // Generate random gravity mesh
+        let gravity_mesh = match &conf.mesh {
+            Some(mesh) => {
+                conf.num_cultures = mesh.num_cultures();
+                mesh.clone()
+            }
+            None => Mesh::random(conf.num_cultures, &mut rng),
+        };
+
+        let colors = (0..conf.num_cultures)
+            .map(|_| random_color(&mut rng))
+            .collect();
+
+        let params = SimParams {
+            version: SIM_PARAMS_VERSION,
+            num_cultures: conf.num_cultures as u32,
+            culture_size: conf.culture_size as u32,
+            aoe: conf.aoe2.sqrt(),
+            damping: conf.damping,
+            mesh: gravity_mesh,
+            obstacles: conf.obstacles.clone(),
+            fields: conf.fields,
+            temperature: conf.temperature,
+            repulsion: conf.repulsion,
+            max_force: conf.max_force,
+            max_speed: conf.max_speed,
+            bound: Some(conf.bound.bb()),
+        };
+        let opts = CpuOptions {
+            backend: conf.backend.into(),
+            theta: conf.theta,
+            bound: conf.bound.bb(),
+            seed: conf.seed.map(|_| rng.random()),
+        };
+
+        Self {
+            sim: CpuSim::new(params, opts),
+            colors,
+            conf,
         }
     }
 
-    /// Get the force a weighted approximated point exerts on this particle given the gravitational constant g.
-    fn force(&self, point: &WeightedPoint, g: f32, aoe2: f32) -> Vec2 {
-        let d2 = Vec2::distance_squared(self.pos, point.pos);
-        if d2 > 0.0 && d2 <= aoe2 {
-            let dir = (point.pos - self.pos).normalize();
-            dir * g * (point.mass as f32)
+    /// Cursor force from the mouse. Left click repels, right click attracts.
+    fn cursor(&self) -> Option<Cursor> {
+        let strength = if is_mouse_button_down(MouseButton::Left) {
+            -self.conf.cursor_force
+        } else if is_mouse_button_down(MouseButton::Right) {
+            self.conf.cursor_force
         } else {
-            Vec2::ZERO
-        }
+            return None;
+        };
+        let (mx, my) = mouse_position();
+        Some(Cursor {
+            pos: vec2(mx, my),
+            radius: self.conf.cursor_aoe2.sqrt(),
+            strength,
+        })
     }
 
-    fn cursor_force(&self, caoe2: f32, cforce: f32) -> Vec2 {
-        if macroquad::input::is_mouse_button_down(MouseButton::Left) {
-            // Repel on left click
-            let (mx, my) = macroquad::input::mouse_position();
-            let mouse = vec2(mx, my);
-            let d2 = Vec2::distance_squared(mouse, self.pos);
-            if d2 > 0.0 && d2 <= caoe2 {
-                let dir = (mouse - self.pos).normalize();
-                dir * -cforce
-            } else {
-                Vec2::ZERO
-            }
-        } else if macroquad::input::is_mouse_button_down(MouseButton::Right) {
-            // Attract on right click
-            let (mx, my) = macroquad::input::mouse_position();
-            let mouse = vec2(mx, my);
-            let d2 = Vec2::distance_squared(mouse, self.pos);
-            if d2 > 0.0 && d2 <= caoe2 {
-                let dir = (mouse - self.pos).normalize();
-                dir * cforce
-            } else {
-                Vec2::ZERO
-            }
+    pub fn step(&mut self, tau: f32) {
+        let cursor = if self.conf.is_interactive {
+            self.cursor()
         } else {
-            Vec2::ZERO
-        }
+            None
+        };
+        self.sim.set_cursor(cursor);
+        self.sim.step(tau);
     }
-}
 
-impl Point for Particle {
-    fn point(&self) -> Vec2 {
-        self.pos
-    }
-}
+    pub fn render(&mut self) {
+        use macroquad::prelude::*;
 
-#[derive(Debug)]
-struct Culture {
-    color: Color,
-    particles: Vec<Particle>,
-    qt: BHQuadtree,
-}
+        clear_background(BLACK);
 
-impl Culture {
-    fn new(color: Color, size: usize, bound: Rect, bh_theta: f32) -> Self {
-        let particles = std::iter::repeat_with(|| Particle::new(bound))
-            .take(size)
-            .collect::<Vec<_>>();
+        self.render_obstacles();
 
-        Self {
-            color,
-            particles,
-            qt: BHQuadtree::new(10, 8, bh_theta),
+        let culture_size = self.sim.params().culture_size as usize;
+        let particles = self.sim.particles();
+        for (culture, &color) in particles.chunks(culture_size).zip(&self.colors) {
+            for p in culture {
+                draw_rectangle(p.pos.x, p.pos.y, 2.0, 2.0, color);
+            }
         }
-    }
 
-    /// Reconstruct this culture's quadtree
-    fn quadtree(&mut self) {
-        let items = self
-            .particles
-            .iter()
-            .map(|p| WeightedPoint::new(p.pos, 1.0))
-            .collect::<Vec<_>>();
-        self.qt.build(items);
+        // if self.config.show_qts {
+        //     for culture in &self.cultures {
+        //         let color = culture.color;
+        //         let qt = culture.qt;
+        //         qt.query_ref_filter(&self.config.bound, |_| draw_rectangle_lines())
+        //     }
+        // }
     }
 
-    fn _naive_force(&self, other: &Culture, g: f32, aoe: f32) -> Vec<Vec2> {
-        self.particles
-            .iter()
-            .map(|p1| {
-                // Accumulate force on p1
-                other
-                    .particles
-                    .iter()
-                    .fold(Vec2::ZERO, |acc, p2| acc + p1._naive_force(p2, g, aoe))
-            })
-            .collect()
+    /// Recluster the current particles with `tracker`
+    pub fn update_clusters(&mut self, tracker: &mut ClusterTracker) {
+        let params = self.sim.params().clone();
+        tracker.update(&params, self.conf.bound.bb(), self.sim.particles());
     }
 
-    fn force(&self, other: &Culture, g: f32, aoe2: f32) -> Vec<Vec2> {
-        self.particles
-            .iter()
-            .map(|p1| {
-                // Accumulate force on p1
-                other.qt.accumulate(p1.pos, |wp| p1.force(&wp, g, aoe2))
-            })
-            .collect()
+    /// Outline each cluster in the color of its dominant culture and label it with its id
+    pub fn render_clusters(&self, tracker: &ClusterTracker) {
+        use macroquad::prelude::*;
+
+        for cluster in tracker.clusters() {
+            let color = self
+                .colors
+                .get(cluster.dominant().0)
+                .copied()
+                .unwrap_or(WHITE);
+            let outline = &cluster.outline;
+            for (i, a) in outline.iter().enumerate() {
+                let b = outline[(i + 1) % outline.len()];
+                draw_line(a.x, a.y, b.x, b.y, 1.5, color);
+            }
+            let id = cluster.id.to_string();
+            let dims = measure_text(&id, None, 14, 1.0);
+            draw_text(
+                &id,
+                cluster.centroid.x - dims.width / 2.0,
+                cluster.centroid.y + dims.height / 2.0,
+                14.0,
+                WHITE,
+            );
+        }
     }
-}
 
-pub struct World {
-    conf: SimConfig,
-    cultures: Vec<Culture>,
-    gravity_mesh: Vec<Vec<f32>>,
-    force_tensor: Vec<Vec<Vec2>>,
-    cursor_force_tensor: Vec<Vec<Vec2>>,
-    i: u64,
-}
+    fn render_obstacles(&self) {
+        use macroquad::prelude::*;
 
-impl World {
-    pub fn new(mut conf: SimConfig) -> Self {
-        // Generate random gravity mesh
-        let gravity_mesh = match &conf.mesh_json {
-            Some(mesh) => {
-                let mesh: Vec<Vec<f32>> = serde_json::from_str(mesh).unwrap();
-                conf.num_cultures = mesh.len();
-                mesh
+        let obstacles = &self.conf.obstacles;
+        if let Some(mask) = &obstacles.mask {
+            for cell in mask.occupied_cells() {
+                draw_rectangle(cell.x, cell.y, mask.cell_size, mask.cell_size, DARKGRAY);
             }
-            None => random_gravity_mesh(conf.num_cultures),
-        };
-
-        // Spawn cultures
-        let cultures = (0..conf.num_cultures)
-            .map(|_| Culture::new(random_color(), conf.culture_size, conf.bound, conf.theta))
-            .collect::<Vec<_>>();
+        }
+        for c in &obstacles.circles {
+            draw_circle(c.center.x, c.center.y, c.radius, DARKGRAY);
+        }
+        for s in &obstacles.segments {
+            let thickness = s.radius * 2.0;
+            draw_line(s.a.x, s.a.y, s.b.x, s.b.y, thickness, DARKGRAY);
+            draw_circle(s.a.x, s.a.y, s.radius, DARKGRAY);
+            draw_circle(s.b.x, s.b.y, s.radius, DARKGRAY);
+        }
+    }
 
-        // println!(
-        //     "Cultures: {}\nCulture size: {}\nGravity Mesh: {:?}",
-        //     conf.num_cultures, conf.culture_size, &gravity_mesh
-        // );
+    fn update_params(&mut self, f: impl FnOnce(&mut SimParams)) {
+        let mut params = self.sim.params().clone();
+        f(&mut params);
+        self.sim.set_params(params);
+    }
 
-        let force_tensor = vec![vec![Vec2::ZERO; conf.culture_size]; conf.num_cultures];
-        let cursor_force_tensor = vec![vec![Vec2::ZERO; conf.culture_size]; conf.num_cultures];
+    pub fn set_obstacles(&mut self, obstacles: Obstacles) {
+        self.conf.obstacles = obstacles.clone();
+        self.update_params(|p| p.obstacles = obstacles);
+    }
 
-        Self {
-            cultures,
-            gravity_mesh,
-            force_tensor,
-            cursor_force_tensor,
-            i: 0,
-            conf,
-        }
+    pub fn set_fields(&mut self, fields: ForceFields) {
+        self.conf.fields = fields;
+        self.update_params(|p| p.fields = fields);
     }
 
-    pub fn compute_force_naive(&mut self) {
-        for c1 in 0..self.cultures.len() {
-            self.force_tensor[c1].fill(Vec2::ZERO);
-            for c2 in 0..self.cultures.len() {
-                let g = self.gravity_mesh[c1][c2];
-                let forces = self.cultures[c1]._naive_force(&self.cultures[c2], g, self.conf.aoe2);
-                for p in 0..forces.len() {
-                    self.force_tensor[c1][p] += forces[p];
-                }
-            }
-            for f in &mut self.force_tensor[c1] {
-                *f /= self.cultures.len() as f32;
-            }
-        }
+    pub fn set_aoe(&mut self, aoe: f32) {
+        self.conf.aoe2 = aoe * aoe;
+        self.update_params(|p| p.aoe = aoe);
     }
 
-    pub fn compute_force(&mut self) {
-        // Regenerate quadtrees
-        for culture in &mut self.cultures {
-            culture.quadtree();
-        }
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.conf.temperature = temperature;
+        self.update_params(|p| p.temperature = temperature);
+    }
 
-        // Compute rolling slice of force tensor
-        // let c1 = (self.i % self.cultures.len() as u64) as usize;
-        for c1 in 0..self.cultures.len() {
-            self.force_tensor[c1].fill(Vec2::ZERO);
-
-            for c2 in 0..self.cultures.len() {
-                let forces = self.cultures[c1].force(
-                    &self.cultures[c2],
-                    self.gravity_mesh[c1][c2],
-                    self.conf.aoe2,
-                );
-                for p in 0..forces.len() {
-                    self.force_tensor[c1][p] += forces[p];
-                }
-            }
+    pub fn set_repulsion(&mut self, repulsion: Repulsion) {
+        self.conf.repulsion = repulsion;
+        self.update_params(|p| p.repulsion = repulsion);
+    }
 
-            for f in &mut self.force_tensor[c1] {
-                *f /= self.cultures.len() as f32;
-            }
-        }
+    pub fn set_limits(&mut self, max_force: f32, max_speed: f32) {
+        self.conf.max_force = max_force;
+        self.conf.max_speed = max_speed;
+        self.update_params(|p| {
+            p.max_force = max_force;
+            p.max_speed = max_speed;
+        });
     }
 
-    pub fn step(&mut self, tau: f32) {
-        self.compute_force();
-
-        // Compute cursor force tensor
-        if self.conf.is_interactive {
-            for (c, culture) in self.cultures.iter().enumerate() {
-                for (p, particle) in culture.particles.iter().enumerate() {
-                    self.cursor_force_tensor[c][p] =
-                        particle.cursor_force(self.conf.cursor_aoe2, self.conf.cursor_force);
-                }
-            }
+    /// Check the energy with `monitor` and apply `action`, see [`EnergyMonitor::check`]
+    pub fn check_energy(
+        &mut self,
+        monitor: &mut EnergyMonitor,
+        action: OnExplosion,
+    ) -> Option<Explosion> {
+        let explosion = monitor.check(&mut self.sim, action)?;
+        if let Some(speed) = explosion.clamped_speed {
+            self.conf.max_speed = speed;
         }
+        Some(explosion)
+    }
 
-        self.apply_force_tensor(tau);
-
-        self.i += 1;
+    pub fn step_count(&self) -> u64 {
+        self.sim.step_count()
     }
 
-    fn apply_force_tensor(&mut self, tau: f32) {
-        let bound = self.conf.bound;
-        for (c, culture) in self.cultures.iter_mut().enumerate() {
-            for (p, particle) in culture.particles.iter_mut().enumerate() {
-                let force = self.force_tensor[c][p] + self.cursor_force_tensor[c][p];
-                particle.vel = (particle.vel + force) * self.conf.damping;
-                if particle.pos.x <= 0. {
-                    particle.vel.x = (particle.vel.x as f32).abs();
-                    particle.pos.x = 0.;
-                } else if particle.pos.x >= bound.bb().x {
-                    particle.vel.x = -(particle.vel.x as f32).abs();
-                    particle.pos.x = bound.bb().x;
-                }
-                if particle.pos.y <= 0. {
-                    particle.vel.y = (particle.vel.y as f32).abs();
-                    particle.pos.y = 0.;
-                } else if particle.pos.y >= bound.bb().y {
-                    particle.vel.y = -(particle.vel.y as f32).abs();
-                    particle.pos.y = bound.bb().y;
-                }
-                particle.pos += particle.vel * tau;
-            }
-        }
+    /// Per culture stats of the current particles
+    pub fn stats(&mut self) -> Stats {
+        let step = self.sim.step_count();
+        let params = self.sim.params().clone();
+        Stats::compute(&params, self.conf.bound.bb(), self.sim.particles(), step)
     }
 
-    pub fn render(&self) {
-        use macroquad::prelude::*;
+    pub fn set_interactive(&mut self, is_interactive: bool) {
+        self.conf.is_interactive = is_interactive;
+    }
 
-        clear_background(BLACK);
+    pub fn export_obstacles_json(&self) -> String {
+        serde_json::to_string(&self.conf.obstacles).expect("Obstacles are serializable")
+    }
 
-        for culture in &self.cultures {
-            let color = culture.color;
-            for p in &culture.particles {
-                draw_rectangle(p.pos.x, p.pos.y, 2.0, 2.0, color);
-            }
-        }
+    /// Params the world is running with
+    pub fn params(&self) -> &SimParams {
+        self.sim.params()
+    }
 
-        // if self.config.show_qts {
-        //     for culture in &self.cultures {
-        //         let color = culture.color;
-        //         let qt = culture.qt;
-        //         qt.query_ref_filter(&self.config.bound, |_| draw_rectangle_lines())
-        //     }
-        // }
+    pub fn gravity_mesh(&self) -> &Mesh {
+        &self.sim.params().mesh
     }
 
     pub fn export_gravity_mesh_json(&self) -> String {
-        serde_json::to_string(&self.gravity_mesh).expect("Gravity mesh is serializable")
+        serde_json::to_string(self.gravity_mesh()).expect("Gravity mesh is serializable")
     }
 }
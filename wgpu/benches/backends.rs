@@ -0,0 +1,308 @@
+//! Per-step cost of each backend at a range of particle counts, the README benchmark table.
+//! Every backend starts from the same seeded world.
+//!
+//! `cargo bench -p particle-life --bench backends -- --particles 1000,10000 --format csv`
+
+use std::{
+    fmt::Write as _,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use particle_life::sim::{GpuSim, HeadlessOptions, headless_device};
+use particle_life_core::{
+    SimParams, Simulator,
+    cpu::{CpuBackend, CpuOptions, CpuSim},
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Markdown,
+    Csv,
+}
+
+#[derive(Parser)]
+struct Args {
+    /// Particle counts to run, split evenly over the cultures
+    #[arg(long, value_delimiter = ',', default_values_t = [1_000, 10_000, 50_000, 200_000])]
+    particles: Vec<u32>,
+    /// Steps per backend, after a short warmup
+    #[arg(long, default_value_t = 100)]
+    steps: u32,
+    #[arg(long, default_value_t = 10)]
+    cultures: u32,
+    #[arg(long, default_value_t = 50.0)]
+    aoe: f32,
+    #[arg(long, default_value_t = 0.1)]
+    damping: f32,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Barnes-Hut opening angle
+    #[arg(long, default_value_t = 0.9)]
+    theta: f32,
+    /// Skip the naive CPU backend above this many particles
+    #[arg(long, default_value_t = 50_000)]
+    naive_cpu_max: u32,
+    /// Stop a backend early once it has run this long, reporting the steps it got through
+    #[arg(long, default_value_t = 60.0)]
+    max_seconds: f64,
+    /// Skip the GPU backend
+    #[arg(long)]
+    no_gpu: bool,
+    #[arg(long, value_enum, default_value_t = Format::Markdown)]
+    format: Format,
+    /// Also write the report here
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// Passed by `cargo bench`
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+const WARMUP: u32 = 2;
+
+/// Average time per step, and the number of steps it was measured over
+#[derive(Clone, Copy)]
+struct Timing {
+    ms: f64,
+    steps: u32,
+}
+
+/// Run up to `steps` steps, one at a time, until the time budget runs out
+fn time_steps(steps: u32, budget: Duration, mut step: impl FnMut()) -> Timing {
+    for _ in 0..WARMUP {
+        step();
+    }
+    let start = Instant::now();
+    let mut done = 0;
+    while done < steps {
+        step();
+        done += 1;
+        if start.elapsed() > budget {
+            break;
+        }
+    }
+    Timing {
+        ms: start.elapsed().as_secs_f64() * 1000.0 / done.max(1) as f64,
+        steps: done,
+    }
+}
+
+struct Row {
+    particles: u32,
+    naive_cpu: Option<Timing>,
+    barnes_hut_cpu: Timing,
+    gpu: Option<Timing>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let budget = Duration::from_secs_f64(args.max_seconds);
+
+    let gpu = if args.no_gpu {
+        None
+    } else {
+        match headless_device(&HeadlessOptions::default()) {
+            Ok(gpu) => Some(gpu),
+            Err(e) => {
+                eprintln!("no adapter, skipping the GPU backend: {e}");
+                None
+            }
+        }
+    };
+
+    let mut rows = vec![];
+    for &n in &args.particles {
+        let culture_size = n.div_ceil(args.cultures);
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let simp = SimParams::random(
+            args.cultures,
+            culture_size,
+            args.aoe,
+            args.damping,
+            &mut rng,
+        );
+        let opts = CpuOptions {
+            backend: CpuBackend::BarnesHut,
+            theta: args.theta,
+            bound: simp.bound.unwrap_or(particle_life::sim::DEFAULT_BOUND),
+            seed: Some(args.seed),
+        };
+        let mut barnes_hut = CpuSim::new(simp.clone(), opts);
+        let spawn = barnes_hut.particles().to_vec();
+        let count = simp.num_particles();
+        eprintln!("{count} particles");
+
+        let naive_cpu = (count <= args.naive_cpu_max).then(|| {
+            let opts = CpuOptions {
+                backend: CpuBackend::Naive,
+                ..opts
+            };
+            let mut sim = CpuSim::with_particles(simp.clone(), opts, spawn.clone());
+            time_steps(args.steps, budget, || sim.step(1.0))
+        });
+        let gpu = gpu.as_ref().map(|(device, queue, _)| {
+            let mut sim =
+                GpuSim::with_particles(device.clone(), queue.clone(), simp.clone(), spawn.clone());
+            time_steps(args.steps, budget, || {
+                sim.step_n(1, 1.0);
+                sim.wait();
+            })
+        });
+        let barnes_hut_cpu = time_steps(args.steps, budget, || barnes_hut.step(1.0));
+
+        rows.push(Row {
+            particles: count,
+            naive_cpu,
+            barnes_hut_cpu,
+            gpu,
+        });
+    }
+
+    let adapter = gpu.as_ref().map(|(_, _, info)| info);
+    let report = match args.format {
+        Format::Markdown => markdown(&args, adapter, &rows),
+        Format::Csv => csv(&args, adapter, &rows),
+    };
+    print!("{report}");
+    if let Some(path) = &args.out {
+        std::fs::write(path, &report).expect("failed to write the report");
+    }
+}
+
+/// `1234567` as `1 234 567`, like the README table
+fn thousands(n: u32) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(' ');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Machine, adapter and run settings, so tables from different machines can be compared
+fn environment(args: &Args, adapter: Option<&wgpu::AdapterInfo>) -> Vec<(&'static str, String)> {
+    let cpu = std::fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|info| {
+            info.lines()
+                .find(|l| l.starts_with("model name"))
+                .and_then(|l| l.split(':').nth(1))
+                .map(|name| name.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    let threads = std::thread::available_parallelism().map_or(0, |n| n.get());
+    let adapter = adapter.map_or_else(
+        || "none".to_string(),
+        |info| {
+            let driver = format!("{} {}", info.driver, info.driver_info);
+            format!(
+                "{} ({:?}, {:?}, driver {})",
+                info.name,
+                info.backend,
+                info.device_type,
+                driver.trim()
+            )
+        },
+    );
+    vec![
+        (
+            "os",
+            format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        ),
+        ("cpu", format!("{cpu}, {threads} threads")),
+        ("adapter", adapter),
+        (
+            "settings",
+            format!(
+                "{} cultures, aoe {}, damping {}, theta {}, seed {}",
+                args.cultures, args.aoe, args.damping, args.theta, args.seed
+            ),
+        ),
+    ]
+}
+
+fn markdown(args: &Args, adapter: Option<&wgpu::AdapterInfo>, rows: &[Row]) -> String {
+    let mut out = String::new();
+    for (key, value) in environment(args, adapter) {
+        writeln!(out, "- {key}: {value}").unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "| Particles | Steps      | Naive CPU (ms) | Barnes–Hut CPU (ms) | Naive GPU (ms) |"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "| --------- | ---------- | -------------- | ------------------- | -------------- |"
+    )
+    .unwrap();
+
+    let mut short = false;
+    for row in rows {
+        let timings = [Some(row.barnes_hut_cpu), row.naive_cpu, row.gpu];
+        let mut steps: Vec<u32> = timings.iter().flatten().map(|t| t.steps).collect();
+        steps.sort_unstable();
+        steps.dedup();
+        let steps = if steps.len() > 1 {
+            short = true;
+            let steps: Vec<_> = steps.iter().map(u32::to_string).collect();
+            format!("{} *", steps.join(" / "))
+        } else {
+            steps[0].to_string()
+        };
+        let ms = |t: Option<Timing>| t.map_or_else(|| "—".to_string(), |t| format!("{:.3}", t.ms));
+        writeln!(
+            out,
+            "| {:<9} | {:<10} | {:<14} | {:<19} | {:<14} |",
+            thousands(row.particles),
+            steps,
+            ms(row.naive_cpu),
+            ms(Some(row.barnes_hut_cpu)),
+            ms(row.gpu),
+        )
+        .unwrap();
+    }
+    if short {
+        writeln!(
+            out,
+            "\n* Some backends hit the {}s budget and ran fewer steps; times are per step.",
+            args.max_seconds
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn csv(args: &Args, adapter: Option<&wgpu::AdapterInfo>, rows: &[Row]) -> String {
+    let mut out = String::new();
+    for (key, value) in environment(args, adapter) {
+        writeln!(out, "# {key}: {value}").unwrap();
+    }
+    writeln!(
+        out,
+        "particles,naive_cpu_steps,naive_cpu_ms,barnes_hut_cpu_steps,barnes_hut_cpu_ms,gpu_steps,gpu_ms"
+    )
+    .unwrap();
+    let cells = |t: Option<Timing>| {
+        t.map_or_else(|| ",".to_string(), |t| format!("{},{:.3}", t.steps, t.ms))
+    };
+    for row in rows {
+        writeln!(
+            out,
+            "{},{},{},{}",
+            row.particles,
+            cells(row.naive_cpu),
+            cells(Some(row.barnes_hut_cpu)),
+            cells(row.gpu),
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,57 @@
+//! Per-step cost of the GPU pipeline as the number of spatial bins grows. The particle count is
+//! fixed, so the difference between rows is mostly the bin offsets scan.
+//!
+//! `cargo bench -p particle-life --bench bin_offsets`
+
+use std::time::Instant;
+
+use particle_life::sim::{GpuSim, HeadlessOptions, headless_device};
+use particle_life_core::SimParams;
+use rand::{SeedableRng, rngs::StdRng};
+
+const NUM_CULTURES: u32 = 4;
+const CULTURE_SIZE: u32 = 250;
+const WARMUP: u32 = 10;
+const STEPS: u32 = 100;
+
+fn main() {
+    let (device, queue, info) = match headless_device(&HeadlessOptions::default()) {
+        Ok(gpu) => gpu,
+        Err(e) => {
+            eprintln!("no adapter: {e}");
+            return;
+        }
+    };
+    println!("Adapter: {} ({:?})", info.name, info.backend);
+    println!(
+        "{} particles, {} steps\n",
+        NUM_CULTURES * CULTURE_SIZE,
+        STEPS
+    );
+    println!("| Bins    | grid_w | Step (ms) |");
+    println!("| ------- | ------ | --------- |");
+
+    for target_bins in [1_000u32, 10_000, 100_000] {
+        // grid_w = ceil(bound / (2 * aoe))
+        let grid_w = (target_bins as f32).sqrt().round();
+        let aoe = 1000.0 / (2.0 * grid_w) + 1e-3;
+        let simp = SimParams::random(
+            NUM_CULTURES,
+            CULTURE_SIZE,
+            aoe,
+            0.5,
+            &mut StdRng::seed_from_u64(0),
+        );
+        let mut sim = GpuSim::new(device.clone(), queue.clone(), simp);
+
+        sim.step_n(WARMUP, 1.0);
+        sim.wait();
+        let start = Instant::now();
+        sim.step_n(STEPS, 1.0);
+        sim.wait();
+        let ms = start.elapsed().as_secs_f64() * 1000.0 / STEPS as f64;
+
+        let grid_w = sim.gpu_params().grid_w;
+        println!("| {:<7} | {:<6} | {:<9.3} |", grid_w * grid_w, grid_w, ms);
+    }
+}
@@ -0,0 +1,124 @@
+//! Pair correlation g(r) of every culture pair, for a snapshot from `--headless --out` or a
+//! seeded random world. Writes a csv of the per-pair histograms and prints the peak of each pair.
+//!
+//! `cargo bench -p particle-life --bench pair_correlation -- --snapshot world.json --out gr.csv`
+
+use std::{fmt::Write as _, fs::File, io::BufWriter, path::PathBuf};
+
+use clap::Parser;
+use particle_life::sim::DEFAULT_BOUND;
+use particle_life_core::{
+    SimParams, Simulator,
+    correlation::PairCorrelation,
+    cpu::{CpuOptions, CpuSim},
+    sim::Snapshot,
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+#[derive(Parser)]
+struct Args {
+    /// Snapshot json to analyse, instead of a random world
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+    /// Largest distance binned, the aoe by default
+    #[arg(long)]
+    radius: Option<f32>,
+    #[arg(long, default_value_t = 50)]
+    bins: usize,
+    /// Write the histograms here as csv (`a,b,r,count,g`)
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// Particles per culture of the random world
+    #[arg(long, default_value_t = 1000)]
+    particles: u32,
+    #[arg(long, default_value_t = 5)]
+    cultures: u32,
+    #[arg(long, default_value_t = 50.0)]
+    aoe: f32,
+    #[arg(long, default_value_t = 0.1)]
+    damping: f32,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Steps to run the random world before measuring, so it has formed structure
+    #[arg(long, default_value_t = 300)]
+    settle: u32,
+    /// Passed by `cargo bench`
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    let snapshot = match &args.snapshot {
+        Some(path) => {
+            let json = std::fs::read_to_string(path).expect("failed to read the snapshot");
+            serde_json::from_str::<Snapshot>(&json).expect("invalid snapshot")
+        }
+        None => {
+            let mut rng = StdRng::seed_from_u64(args.seed);
+            let simp = SimParams::random(
+                args.cultures,
+                args.particles,
+                args.aoe,
+                args.damping,
+                &mut rng,
+            );
+            let opts = CpuOptions {
+                bound: DEFAULT_BOUND,
+                seed: Some(args.seed),
+                ..Default::default()
+            };
+            let mut sim = CpuSim::new(simp, opts);
+            for _ in 0..args.settle {
+                sim.step(1.0);
+            }
+            sim.snapshot()
+        }
+    };
+    let params = &snapshot.params;
+    let bound = params.bound.unwrap_or(DEFAULT_BOUND);
+    let radius = args.radius.unwrap_or(params.aoe);
+    let gr = PairCorrelation::compute(params, bound, &snapshot.particles, radius, args.bins);
+
+    if let Some(path) = &args.out {
+        let file = File::create(path).expect("failed to create the output");
+        gr.write_csv(BufWriter::new(file))
+            .expect("failed to write the histograms");
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{} particles, {} cultures, step {}, radius {}, {} bins",
+        params.num_particles(),
+        params.num_cultures,
+        snapshot.step,
+        radius,
+        args.bins
+    )
+    .unwrap();
+    writeln!(out, "| a | b | Peak r | Peak g | Pairs  |").unwrap();
+    writeln!(out, "| - | - | ------ | ------ | ------ |").unwrap();
+    for pair in &gr.pairs {
+        let (bin, peak) = pair
+            .g
+            .iter()
+            .copied()
+            .enumerate()
+            .fold(
+                (0, 0.0),
+                |best, (i, g)| if g > best.1 { (i, g) } else { best },
+            );
+        writeln!(
+            out,
+            "| {} | {} | {:<6.1} | {:<6.2} | {:<6} |",
+            pair.a,
+            pair.b,
+            gr.r(bin),
+            peak,
+            pair.counts.iter().sum::<u64>()
+        )
+        .unwrap();
+    }
+    print!("{out}");
+}
@@ -0,0 +1,52 @@
+//! Per-step cost of the GPU pipeline with and without sorting particles by bin before the force
+//! pass.
+//!
+//! `cargo bench -p particle-life --bench sort`
+
+use std::time::Instant;
+
+use particle_life::sim::{GpuSim, HeadlessOptions, headless_device};
+use particle_life_core::SimParams;
+use rand::{SeedableRng, rngs::StdRng};
+
+const NUM_CULTURES: u32 = 10;
+const WARMUP: u32 = 2;
+const STEPS: u32 = 10;
+
+fn main() {
+    let (device, queue, info) = match headless_device(&HeadlessOptions::default()) {
+        Ok(gpu) => gpu,
+        Err(e) => {
+            eprintln!("no adapter: {e}");
+            return;
+        }
+    };
+    println!("Adapter: {} ({:?})", info.name, info.backend);
+    println!("{STEPS} steps\n");
+    println!("| Particles | Unsorted (ms/step) | Sorted (ms/step) |");
+    println!("| --------- | ------------------ | ---------------- |");
+
+    for particles in [10_000u32, 50_000] {
+        let simp = SimParams::random(
+            NUM_CULTURES,
+            particles / NUM_CULTURES,
+            50.0,
+            0.1,
+            &mut StdRng::seed_from_u64(0),
+        );
+        let times = [false, true].map(|sorted| {
+            let mut sim = GpuSim::new(device.clone(), queue.clone(), simp.clone());
+            sim.set_sorted(sorted);
+            sim.step_n(WARMUP, 1.0);
+            sim.wait();
+            let start = Instant::now();
+            sim.step_n(STEPS, 1.0);
+            sim.wait();
+            start.elapsed().as_secs_f64() * 1000.0 / STEPS as f64
+        });
+        println!(
+            "| {:<9} | {:<18.3} | {:<16.3} |",
+            particles, times[0], times[1]
+        );
+    }
+}
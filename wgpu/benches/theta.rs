@@ -0,0 +1,136 @@
+//! Barnes-Hut force error and cost against the exact forces across a sweep of opening angles,
+//! for a snapshot from `--headless --out` or a seeded random world.
+//!
+//! `cargo bench -p particle-life --bench theta -- --snapshot world.json --thetas 0.3,0.6,0.9`
+
+use std::{fmt::Write as _, path::PathBuf};
+
+use clap::Parser;
+use particle_life::sim::DEFAULT_BOUND;
+use particle_life_core::{
+    SimParams, Simulator,
+    accuracy::theta_sweep,
+    cpu::{CpuOptions, CpuSim},
+    sim::Snapshot,
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+#[derive(Parser)]
+struct Args {
+    /// Snapshot json to measure, instead of a random world
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+    #[arg(long, value_delimiter = ',', default_values_t = [0.1, 0.3, 0.5, 0.7, 0.9, 1.2, 1.5])]
+    thetas: Vec<f32>,
+    /// Particles per culture of the random world
+    #[arg(long, default_value_t = 1000)]
+    particles: u32,
+    #[arg(long, default_value_t = 10)]
+    cultures: u32,
+    #[arg(long, default_value_t = 50.0)]
+    aoe: f32,
+    #[arg(long, default_value_t = 0.1)]
+    damping: f32,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Steps to run the random world before measuring, so it has formed structure
+    #[arg(long, default_value_t = 100)]
+    settle: u32,
+    /// Also list the error of every culture
+    #[arg(long)]
+    per_culture: bool,
+    /// Passed by `cargo bench`
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    let snapshot = match &args.snapshot {
+        Some(path) => {
+            let json = std::fs::read_to_string(path).expect("failed to read the snapshot");
+            serde_json::from_str::<Snapshot>(&json).expect("invalid snapshot")
+        }
+        None => {
+            let mut rng = StdRng::seed_from_u64(args.seed);
+            let simp = SimParams::random(
+                args.cultures,
+                args.particles,
+                args.aoe,
+                args.damping,
+                &mut rng,
+            );
+            let opts = CpuOptions {
+                bound: DEFAULT_BOUND,
+                seed: Some(args.seed),
+                ..Default::default()
+            };
+            let mut sim = CpuSim::new(simp, opts);
+            for _ in 0..args.settle {
+                sim.step(1.0);
+            }
+            sim.snapshot()
+        }
+    };
+    let params = &snapshot.params;
+    let bound = params.bound.unwrap_or(DEFAULT_BOUND);
+    let sweep = theta_sweep(params, &snapshot.particles, bound, &args.thetas);
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{} particles, {} cultures, aoe {}, step {}",
+        params.num_particles(),
+        params.num_cultures,
+        params.aoe,
+        snapshot.step
+    )
+    .unwrap();
+    writeln!(out, "Naive: {:.3} ms\n", sweep.naive_ms).unwrap();
+    writeln!(
+        out,
+        "| Theta | Force (ms) | Speedup | Mean rel. error | Max rel. error |"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "| ----- | ---------- | ------- | --------------- | -------------- |"
+    )
+    .unwrap();
+    for t in &sweep.thetas {
+        writeln!(
+            out,
+            "| {:<5} | {:<10.3} | {:<7.2} | {:<15.5} | {:<14.5} |",
+            t.theta,
+            t.force_ms,
+            sweep.naive_ms / t.force_ms,
+            t.total.mean,
+            t.total.max
+        )
+        .unwrap();
+    }
+    if args.per_culture {
+        for t in &sweep.thetas {
+            writeln!(out, "\nTheta {}", t.theta).unwrap();
+            writeln!(
+                out,
+                "| Culture | Particles | Mean rel. error | Max rel. error |"
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "| ------- | --------- | --------------- | -------------- |"
+            )
+            .unwrap();
+            for (i, c) in t.cultures.iter().enumerate() {
+                writeln!(
+                    out,
+                    "| {:<7} | {:<9} | {:<15.5} | {:<14.5} |",
+                    i, c.samples, c.mean, c.max
+                )
+                .unwrap();
+            }
+        }
+    }
+    print!("{out}");
+}
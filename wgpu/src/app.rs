@@ -1,445 +1,251 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
-use rand::Rng;
 use wgpu::util::DeviceExt;
 use winit::{
     application::ApplicationHandler,
-    dpi::PhysicalSize,
-    event::{KeyEvent, WindowEvent},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, KeyEvent, MouseButton, WindowEvent},
     event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
     keyboard::{KeyCode, PhysicalKey},
     window::{Window, WindowId},
 };
 
-use crate::util::random_color;
+use glam::{Vec2, vec2};
+use particle_life_core::{
+    SimParams, Simulator,
+    cluster::{ClusterParams, ClusterTracker},
+    color::random_colors,
+    correlation::PairCorrelation,
+    sim::Cursor,
+    stability::{EnergyMonitor, OnExplosion},
+    stats::StatsWriter,
+};
+
+use crate::{
+    plots::History,
+    profiler::Stage,
+    sim::{GpuParticle, GpuSim, request_device},
+    ui::{Gui, Panel, PanelResponse, Stats, draw_clusters},
+};
 
 const PHYS_DT: f32 = 1.0 / 60.0;
 const MAX_ACC: f32 = 5.0 / 60.0;
-
-pub fn run(params: GpuParams, mesh: Vec<f32>) {
-    env_logger::init();
-
-    let event_loop = EventLoop::new().unwrap();
-    event_loop.set_control_flow(ControlFlow::Poll);
-    let mut app = App::new(params, mesh);
-    event_loop.run_app(&mut app).unwrap();
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct GpuParams {
-    pub bound: [f32; 2],
-    pub num_cultures: u32,
-    pub culture_size: u32,
-    pub num_particles: u32,
-    pub aoe: f32,
-    pub aoe2: f32,
-    pub damping: f32,
-    pub bin_size: f32,
-    pub grid_w: u32,
+/// Time between cluster updates, each of which reads the particles back
+const CLUSTER_INTERVAL: Duration = Duration::from_millis(200);
+/// Time between updates of the live g(r) plot
+const PAIR_CORRELATION_INTERVAL: Duration = Duration::from_millis(500);
+const PAIR_CORRELATION_BINS: usize = 50;
+
+/// Startup switches from the command line
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Sort particles by bin every step
+    pub sort: bool,
+    /// Run the debug validation pass and print its counts every second
+    pub validate: bool,
+    /// Radius of the cursor force in world units
+    pub cursor_radius: f32,
+    /// Force the cursor applies per step. Left click repels, right click attracts.
+    pub cursor_strength: f32,
+    /// Profile GPU stages over a window of this many samples, printed every second
+    pub profile: Option<usize>,
+    /// Checked every second
+    pub on_explosion: OnExplosion,
+    /// Energy over its running baseline that counts as an explosion
+    pub explosion_factor: f32,
+    /// Steps between stats
+    pub stats_every: u32,
+    /// Stream stats here, as csv or json lines
+    pub stats_out: Option<PathBuf>,
+    /// Initial cluster overlay settings, editable in the panel
+    pub clusters: ClusterParams,
 }
 
-impl GpuParams {
-    pub fn new(num_cultures: u32, culture_size: u32, aoe: f32, damping: f32) -> Self {
-        let bound = [1000.0, 1000.0];
-        let grid_w = f32::ceil(bound[0] / (aoe * 2.0));
-        let bin_size = bound[0] / grid_w;
+impl Default for Options {
+    fn default() -> Self {
         Self {
-            bound,
-            num_cultures,
-            culture_size,
-            num_particles: num_cultures * culture_size,
-            aoe,
-            aoe2: aoe * aoe,
-            damping,
-            bin_size,
-            grid_w: grid_w as u32,
+            sort: false,
+            validate: false,
+            cursor_radius: 100.0,
+            cursor_strength: 2.0,
+            profile: None,
+            on_explosion: OnExplosion::Warn,
+            explosion_factor: 100.0,
+            stats_every: 10,
+            stats_out: None,
+            clusters: ClusterParams::default(),
         }
     }
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct GpuParticle {
-    pos: [f32; 2],
-    vel: [f32; 2],
+/// Mouse state tracked from window events
+#[derive(Default)]
+struct Mouse {
+    pos: Option<PhysicalPosition<f64>>,
+    left: bool,
+    right: bool,
 }
 
-impl GpuParticle {
-    pub fn new(bound: [f32; 2]) -> Self {
-        let mut rng = rand::rng();
-        Self {
-            pos: [
-                rng.random_range(0.0..bound[0]),
-                rng.random_range(0.0..bound[1]),
-            ],
-            vel: [rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0)],
-        }
-    }
-
-    pub fn vertex_layout() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: size_of::<Self>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Instance,
-            // can also use wgpu::vertex_attr_array![] macro
-            attributes: &[wgpu::VertexAttribute {
-                offset: 0,
-                shader_location: 0,
-                format: wgpu::VertexFormat::Float32x2,
-            }],
+impl Mouse {
+    fn set(&mut self, button: MouseButton, pressed: bool) {
+        match button {
+            MouseButton::Left => self.left = pressed,
+            MouseButton::Right => self.right = pressed,
+            _ => (),
         }
     }
 }
 
-struct ComputeState {
-    bin_counts_buffer: wgpu::Buffer,
-    particle_buffer_1: wgpu::Buffer,
-    particle_buffer_2: wgpu::Buffer,
-    count_pipeline: wgpu::ComputePipeline,
-    offsets_pipeline: wgpu::ComputePipeline,
-    build_pipeline: wgpu::ComputePipeline,
-    force_pipeline: wgpu::ComputePipeline,
-    general_bind: wgpu::BindGroup,
-    particle_bind_1: wgpu::BindGroup,
-    particle_bind_2: wgpu::BindGroup,
-    particle_bind_swap: bool,
+pub fn run(simp: SimParams, opts: Options) {
+    env_logger::init();
+
+    let event_loop = EventLoop::new().unwrap();
+    event_loop.set_control_flow(ControlFlow::Poll);
+    let mut app = App::new(simp, opts);
+    event_loop.run_app(&mut app).unwrap();
 }
 
 struct RenderState {
     pipeline: wgpu::RenderPipeline,
-    bind: wgpu::BindGroup,
-    vertex_buffer: wgpu::Buffer,
+    binds: RenderBinds,
+    view_buffer: wgpu::Buffer,
+    obstacle_pipeline: wgpu::RenderPipeline,
+    draw_obstacles: bool,
     surface: wgpu::Surface<'static>,
     surface_format: wgpu::TextureFormat,
     window: Arc<Window>,
     size: PhysicalSize<u32>,
 }
 
-struct State {
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    params: GpuParams,
-    compute_state: ComputeState,
-    render_state: RenderState,
-    time_acc: f32,
-    last_frame_t: Instant,
-    phys_steps: u32,
-    rend_steps: u32,
-    last_sec: Instant,
-    t: u32,
+/// Render bind groups. They reference sim buffers, so they're recreated whenever the sim
+/// rebuilds its buffers.
+struct RenderBinds {
+    colors_buffer: wgpu::Buffer,
+    bind: wgpu::BindGroup,
+    obstacle_bind: wgpu::BindGroup,
 }
 
-impl State {
-    pub async fn new(window: Arc<Window>, params: GpuParams, gravity_mesh: &[f32]) -> Result<Self> {
-        let instance = wgpu::Instance::new(&Default::default());
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: None,
-            })
-            .await?;
-        let (device, queue) = adapter.request_device(&Default::default()).await?;
-
-        let colors = (0..params.num_cultures)
-            .map(|_| random_color())
-            .collect::<Vec<_>>();
-        let particles = (0..params.num_particles)
-            .map(|_| GpuParticle::new(params.bound))
-            .collect::<Vec<_>>();
-        let num_bins = (params.grid_w * params.grid_w) as usize;
-
-        use wgpu::BufferUsages as U;
-        let particle_buffer_1 = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Particles"),
-            contents: bytemuck::cast_slice(&particles),
-            usage: U::STORAGE | U::COPY_SRC,
-        });
-        let particle_buffer_2 = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Particles"),
-            contents: bytemuck::cast_slice(&particles),
-            usage: U::STORAGE | U::COPY_SRC,
-        });
-        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Params"),
-            contents: bytemuck::bytes_of(&params),
-            usage: U::UNIFORM,
-        });
-        let gravity_mesh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Gravity Mesh"),
-            contents: bytemuck::cast_slice(gravity_mesh),
-            usage: U::STORAGE,
-        });
-        let bin_counts_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Bin Counts"),
-            contents: &bytemuck::cast_slice(&vec![0f32; num_bins]),
-            usage: U::STORAGE | U::COPY_DST,
-        });
-        let bin_ixs_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Bin Indices"),
-            contents: &bytemuck::cast_slice(&vec![0f32; params.num_particles as usize]),
-            usage: U::STORAGE,
-        });
-        let bin_offsets_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Bin Offsets"),
-            contents: &bytemuck::cast_slice(&vec![0f32; num_bins + 1]),
-            usage: U::STORAGE,
-        });
-        let bin_current_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Bin Current"),
-            contents: &bytemuck::cast_slice(&vec![0f32; num_bins]),
-            usage: U::STORAGE,
-        });
-        let bins_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Bins"),
-            contents: &bytemuck::cast_slice(&vec![0f32; params.num_particles as usize]),
-            usage: U::STORAGE,
-        });
+impl RenderBinds {
+    fn new(
+        device: &wgpu::Device,
+        sim: &GpuSim,
+        pipeline: &wgpu::RenderPipeline,
+        obstacle_pipeline: &wgpu::RenderPipeline,
+        view_buffer: &wgpu::Buffer,
+        colors: &[[f32; 4]],
+    ) -> Self {
         let colors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Colors"),
-            contents: bytemuck::cast_slice(&colors),
-            usage: U::STORAGE,
-        });
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertices"),
-            contents: bytemuck::cast_slice(&particles),
-            usage: U::VERTEX | U::COPY_DST,
+            contents: bytemuck::cast_slice(colors),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
-        let group0_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Compute Group 0 Layout"),
+        let bind = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
             entries: &[
-                // params
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
+                    resource: sim.params_buffer().as_entire_binding(),
                 },
-                // gravity mesh
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // bin counts
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // bin ixs
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
+                    resource: colors_buffer.as_entire_binding(),
                 },
-                // bin offsets
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 4,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // bin current
-                wgpu::BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // bins
-                wgpu::BindGroupLayoutEntry {
-                    binding: 6,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
+                    resource: view_buffer.as_entire_binding(),
                 },
             ],
         });
 
-        let group1_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Group 1 Layout"),
-            entries: &[
-                // particles
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // particles out
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-        });
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Compute Pipeline Layout"),
-            bind_group_layouts: &[&group0_layout, &group1_layout],
-            push_constant_ranges: &[],
-        });
-
-        let cshader = device.create_shader_module(wgpu::include_wgsl!("shaders/compute.wgsl"));
-
-        let count_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Count Bins"),
-            layout: Some(&pipeline_layout),
-            module: &cshader,
-            entry_point: Some("compute_bin_ixs_and_counts"),
-            compilation_options: Default::default(),
-            cache: None,
-        });
-
-        let offsets_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Compute Offsets"),
-            layout: Some(&pipeline_layout),
-            module: &cshader,
-            entry_point: Some("compute_bin_offsets"),
-            compilation_options: Default::default(),
-            cache: None,
-        });
-
-        let build_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Build Bins"),
-            layout: Some(&pipeline_layout),
-            module: &cshader,
-            entry_point: Some("build_bin"),
-            compilation_options: Default::default(),
-            cache: None,
-        });
-
-        let force_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Compute Forces"),
-            layout: Some(&pipeline_layout),
-            module: &cshader,
-            entry_point: Some("compute_force"),
-            compilation_options: Default::default(),
-            cache: None,
-        });
-
-        let compute_general_bind = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Compute General Bind Group"),
-            layout: &group0_layout,
+        let obstacle_bind = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Obstacle Bind Group"),
+            layout: &obstacle_pipeline.get_bind_group_layout(0),
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: params_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: gravity_mesh_buffer.as_entire_binding(),
+                    resource: sim.params_buffer().as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: bin_counts_buffer.as_entire_binding(),
+                    resource: sim.obstacles_buffer().as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 3,
-                    resource: bin_ixs_buffer.as_entire_binding(),
+                    resource: sim.mask_buffer().as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 4,
-                    resource: bin_offsets_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: bin_current_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: bins_buffer.as_entire_binding(),
+                    resource: view_buffer.as_entire_binding(),
                 },
             ],
         });
 
-        let compute_particle_bind_1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Compute Particle Bind Group 1"),
-            layout: &group1_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: particle_buffer_1.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: particle_buffer_2.as_entire_binding(),
-                },
-            ],
-        });
+        Self {
+            colors_buffer,
+            bind,
+            obstacle_bind,
+        }
+    }
+}
 
-        let compute_particle_bind_2 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Compute Particle Bind Group 2"),
-            layout: &force_pipeline.get_bind_group_layout(1),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: particle_buffer_2.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: particle_buffer_1.as_entire_binding(),
-                },
-            ],
-        });
+struct State {
+    sim: GpuSim,
+    opts: Options,
+    mouse: Mouse,
+    render_state: RenderState,
+    gui: Gui,
+    panel: Panel,
+    monitor: EnergyMonitor,
+    stats_writer: Option<StatsWriter>,
+    clusters: ClusterTracker,
+    last_clusters: Instant,
+    pair_correlation: Option<PairCorrelation>,
+    last_pair_correlation: Instant,
+    history: History,
+    time_acc: f32,
+    last_frame_t: Instant,
+    phys_steps: u32,
+    rend_steps: u32,
+    /// Steps counted over the last full second
+    phys_fps: u32,
+    rend_fps: u32,
+    last_sec: Instant,
+}
 
-        let compute_state = ComputeState {
-            bin_counts_buffer,
-            particle_buffer_1,
-            particle_buffer_2,
-            count_pipeline,
-            offsets_pipeline,
-            build_pipeline,
-            force_pipeline,
-            general_bind: compute_general_bind,
-            particle_bind_1: compute_particle_bind_1,
-            particle_bind_2: compute_particle_bind_2,
-            particle_bind_swap: false,
-        };
+impl State {
+    pub async fn new(window: Arc<Window>, simp: &SimParams, opts: Options) -> Result<Self> {
+        let instance = wgpu::Instance::new(&Default::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            })
+            .await?;
+        let (device, queue) = request_device(&adapter).await?;
+
+        let colors = random_colors(simp.num_cultures as usize, &mut rand::rng());
+
+        let mut sim = GpuSim::new(device.clone(), queue, simp.clone());
+        sim.set_sorted(opts.sort);
+        sim.set_validation(opts.validate);
+        if let Some(window) = opts.profile
+            && !sim.set_profiling(true, window)
+        {
+            println!("Timestamp queries aren't supported by this adapter, not profiling");
+        }
+        let stats_writer = opts
+            .stats_out
+            .as_deref()
+            .map(StatsWriter::create)
+            .transpose()?;
 
         let surface = instance.create_surface(Arc::clone(&window))?;
         let cap = surface.get_capabilities(&adapter);
@@ -485,27 +291,58 @@ impl State {
             cache: None,
         });
 
-        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &render_pipeline.get_bind_group_layout(0),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: params_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: colors_buffer.as_entire_binding(),
-                },
-            ],
+        let view_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("View Scale"),
+            size: size_of::<[f32; 2]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let obstacle_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Obstacles"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &rshader,
+                entry_point: Some("vs_obstacles"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &rshader,
+                entry_point: Some("fs_obstacles"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
         });
 
+        let binds = RenderBinds::new(
+            &device,
+            &sim,
+            &render_pipeline,
+            &obstacle_pipeline,
+            &view_buffer,
+            &colors,
+        );
+        let gui = Gui::new(&window, &device, surface_format.add_srgb_suffix());
+        let panel = Panel::new(simp.clone(), colors, opts.clusters);
+
         let size = window.inner_size();
 
         let render_state = RenderState {
             pipeline: render_pipeline,
-            bind: render_bind_group,
-            vertex_buffer,
+            binds,
+            view_buffer,
+            obstacle_pipeline,
+            draw_obstacles: !simp.obstacles.is_empty(),
             surface,
             surface_format,
             window,
@@ -513,17 +350,26 @@ impl State {
         };
 
         let gc = Self {
-            device,
-            queue,
-            params,
-            compute_state,
+            sim,
+            monitor: EnergyMonitor::new(opts.explosion_factor),
+            stats_writer,
+            clusters: ClusterTracker::new(opts.clusters),
+            last_clusters: Instant::now(),
+            pair_correlation: None,
+            last_pair_correlation: Instant::now(),
+            history: History::default(),
+            opts,
+            mouse: Mouse::default(),
             render_state,
+            gui,
+            panel,
             time_acc: 0.0,
             last_frame_t: Instant::now(),
             phys_steps: 0,
             rend_steps: 0,
+            phys_fps: 0,
+            rend_fps: 0,
             last_sec: Instant::now(),
-            t: 0,
         };
 
         gc.configure_surface();
@@ -537,6 +383,11 @@ impl State {
 
     fn configure_surface(&self) {
         let rs = &self.render_state;
+        let bound = self.sim.gpu_params().bound;
+        let scale = view_scale(bound, rs.size);
+        self.sim
+            .queue()
+            .write_buffer(&rs.view_buffer, 0, bytemuck::cast_slice(&scale));
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: rs.surface_format,
@@ -547,7 +398,36 @@ impl State {
             desired_maximum_frame_latency: 3,
             present_mode: wgpu::PresentMode::AutoVsync,
         };
-        rs.surface.configure(&self.device, &surface_config);
+        rs.surface.configure(self.sim.device(), &surface_config);
+    }
+
+    /// Convert a window position in pixels to world coordinates
+    fn to_world(&self, pos: PhysicalPosition<f64>) -> Vec2 {
+        let size = self.render_state.size;
+        let bound = Vec2::from(self.sim.gpu_params().bound);
+        let scale = Vec2::from(view_scale(bound.to_array(), size));
+        let ndc = vec2(
+            pos.x as f32 / size.width as f32 * 2.0 - 1.0,
+            1.0 - pos.y as f32 / size.height as f32 * 2.0,
+        );
+        (ndc / scale + 1.0) * 0.5 * bound
+    }
+
+    /// Cursor force from the mouse. Left click repels, right click attracts.
+    fn cursor(&self) -> Option<Cursor> {
+        if self.gui.wants_pointer() {
+            return None;
+        }
+        let strength = match (self.mouse.left, self.mouse.right) {
+            (true, false) => -self.opts.cursor_strength,
+            (false, true) => self.opts.cursor_strength,
+            _ => return None,
+        };
+        Some(Cursor {
+            pos: self.to_world(self.mouse.pos?),
+            radius: self.opts.cursor_radius,
+            strength,
+        })
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -557,52 +437,112 @@ impl State {
         self.configure_surface();
     }
 
-    pub fn compute(&mut self) -> wgpu::CommandBuffer {
-        let c = &self.compute_state;
-        let (bind_group, particle_out_buffer) = if c.particle_bind_swap {
-            (&c.particle_bind_2, &c.particle_buffer_1)
+    /// Apply edits from the control panel. Anything that rebuilds the sim buffers also needs new
+    /// render bind groups.
+    fn update_from_panel(&mut self, res: PanelResponse) {
+        let rebuilt = if res.reset || res.randomize {
+            let simp = self.panel.respawn_params(res.randomize);
+            self.sim.set_params(simp);
+            self.sim.respawn();
+            true
+        } else if res.params_changed {
+            let simp = self.panel.simp.clone();
+            let rebuilt = self.sim.resizes(&simp);
+            self.sim.set_params(simp);
+            rebuilt
         } else {
-            (&c.particle_bind_1, &c.particle_buffer_2)
+            false
         };
 
-        let mut encoder = self.device.create_command_encoder(&Default::default());
-
-        encoder.clear_buffer(&c.bin_counts_buffer, 0, None);
-
-        let workgroup_count = self.params.num_particles.div_ceil(64);
-
-        let mut cpass = encoder.begin_compute_pass(&Default::default());
-        cpass.set_bind_group(0, &c.general_bind, &[]);
-        cpass.set_bind_group(1, bind_group, &[]);
-
-        cpass.set_pipeline(&c.count_pipeline);
-        cpass.dispatch_workgroups(workgroup_count, 1, 1);
-
-        cpass.set_pipeline(&c.offsets_pipeline);
-        cpass.dispatch_workgroups(1, 1, 1);
-
-        cpass.set_pipeline(&c.build_pipeline);
-        cpass.dispatch_workgroups(workgroup_count, 1, 1);
-
-        cpass.set_pipeline(&c.force_pipeline);
-        cpass.dispatch_workgroups(workgroup_count, 1, 1);
-
-        drop(cpass);
+        if res.reset || res.randomize {
+            self.monitor.reset();
+            self.clusters.reset();
+            self.history.clear();
+        }
+        if rebuilt {
+            self.rebuild_binds();
+        } else if res.colors_changed {
+            self.sim.queue().write_buffer(
+                &self.render_state.binds.colors_buffer,
+                0,
+                bytemuck::cast_slice(&self.panel.colors),
+            );
+        }
+    }
 
-        encoder.copy_buffer_to_buffer(
-            particle_out_buffer,
-            0,
-            &self.render_state.vertex_buffer,
-            0,
-            particle_out_buffer.size(),
+    /// New render bind groups for sim buffers that were rebuilt
+    fn rebuild_binds(&mut self) {
+        let r = &mut self.render_state;
+        r.binds = RenderBinds::new(
+            self.sim.device(),
+            &self.sim,
+            &r.pipeline,
+            &r.obstacle_pipeline,
+            &r.view_buffer,
+            &self.panel.colors,
         );
+    }
 
-        self.compute_state.particle_bind_swap = !c.particle_bind_swap;
-
-        encoder.finish()
+    /// Check the energy and pause or clamp as configured
+    fn check_energy(&mut self) {
+        let Some(explosion) = self.monitor.check(&mut self.sim, self.opts.on_explosion) else {
+            return;
+        };
+        println!("Warning: {explosion}");
+        match self.opts.on_explosion {
+            OnExplosion::Pause => self.panel.paused = true,
+            OnExplosion::Clamp => {
+                self.panel.simp.max_speed = self.sim.params().max_speed;
+                self.rebuild_binds();
+            }
+            OnExplosion::Off | OnExplosion::Warn => (),
+        }
     }
 
     pub fn render(&mut self) {
+        let mut encoder = self
+            .sim
+            .device()
+            .create_command_encoder(&Default::default());
+
+        let stats = Stats {
+            phys_fps: self.phys_fps,
+            render_fps: self.rend_fps,
+            num_particles: self.sim.gpu_params().num_particles,
+            profile: self.sim.profiler().map(|p| p.summary()),
+            clusters: if self.panel.show_clusters {
+                self.clusters.clusters()
+            } else {
+                &[]
+            },
+            pair_correlation: self
+                .pair_correlation
+                .as_ref()
+                .filter(|_| self.panel.show_pair_correlation),
+        };
+        let (bound, size) = (self.sim.gpu_params().bound, self.render_state.size);
+        let mut res = PanelResponse::default();
+        let frame = self.gui.prepare(
+            &self.render_state.window,
+            self.sim.device(),
+            self.sim.queue(),
+            &mut encoder,
+            |ctx| {
+                res = self.panel.show(ctx, &stats);
+                if self.panel.show_plots {
+                    self.history
+                        .show(ctx, &mut self.panel.show_plots, &self.panel.colors);
+                }
+                let painter = ctx.layer_painter(egui::LayerId::background());
+                let points = ctx.pixels_per_point();
+                draw_clusters(&painter, stats.clusters, &self.panel.colors, |pos| {
+                    let pos = to_window(pos, bound, size) / points;
+                    egui::pos2(pos.x, pos.y)
+                });
+            },
+        );
+        self.update_from_panel(res);
+
         let r = &self.render_state;
         // Create texture view
         let surface_texture = r
@@ -616,7 +556,9 @@ impl State {
                 ..Default::default()
             });
 
-        let mut encoder = self.device.create_command_encoder(&Default::default());
+        let device = self.sim.device().clone();
+        let profile = self.sim.profiler_mut().and_then(|p| p.begin(&device));
+        let profiler = self.sim.profiler().filter(|_| profile.is_some());
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: None,
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -629,20 +571,95 @@ impl State {
                 },
             })],
             depth_stencil_attachment: None,
-            timestamp_writes: None,
+            timestamp_writes: profiler.map(|p| p.render_writes(Stage::Render)),
             occlusion_query_set: None,
         });
 
+        if r.draw_obstacles {
+            rpass.set_pipeline(&r.obstacle_pipeline);
+            rpass.set_bind_group(0, &r.binds.obstacle_bind, &[]);
+            rpass.draw(0..6, 0..1);
+        }
+
         rpass.set_pipeline(&r.pipeline);
-        rpass.set_bind_group(0, &r.bind, &[]);
-        rpass.set_vertex_buffer(0, r.vertex_buffer.slice(..));
-        rpass.draw(0..6, 0..self.params.num_particles);
+        rpass.set_bind_group(0, &r.binds.bind, &[]);
+        rpass.set_vertex_buffer(0, self.sim.particle_buffer().slice(..));
+        rpass.draw(0..6, 0..self.sim.gpu_params().num_particles);
 
+        let mut rpass = rpass.forget_lifetime();
+        self.gui.render(&mut rpass, &frame);
         drop(rpass);
 
-        self.queue.submit([encoder.finish()]);
+        if let (Some(profiler), Some(buffer)) = (self.sim.profiler_mut(), profile) {
+            profiler.resolve(&mut encoder, buffer, Stage::RENDER);
+        }
+
+        self.sim.queue().submit([encoder.finish()]);
         r.window.pre_present_notify();
         surface_texture.present();
+        self.gui.finish(frame);
+    }
+
+    /// Recluster the particles if the overlay is on and it's been long enough since the last time
+    fn update_clusters(&mut self) {
+        if !self.panel.show_clusters || self.last_clusters.elapsed() < CLUSTER_INTERVAL {
+            return;
+        }
+        self.last_clusters = Instant::now();
+        self.clusters.params = self.panel.clusters;
+        let simp = self.sim.params().clone();
+        let bound = Vec2::from(self.sim.gpu_params().bound);
+        let count = self
+            .clusters
+            .update(&simp, bound, self.sim.particles())
+            .len();
+        self.history.push_clusters(self.sim.step_count(), count);
+    }
+
+    /// Recompute the live g(r) if the plot is on and it's been long enough since the last time
+    fn update_pair_correlation(&mut self) {
+        if !self.panel.show_pair_correlation
+            || self.last_pair_correlation.elapsed() < PAIR_CORRELATION_INTERVAL
+        {
+            return;
+        }
+        self.last_pair_correlation = Instant::now();
+        let simp = self.sim.params().clone();
+        let bound = Vec2::from(self.sim.gpu_params().bound);
+        self.pair_correlation = Some(PairCorrelation::compute(
+            &simp,
+            bound,
+            self.sim.particles(),
+            self.panel.pair_radius,
+            PAIR_CORRELATION_BINS,
+        ));
+    }
+
+    /// Reduce stats on the GPU while they're streamed or plotted, and pass on the ones read back
+    /// since the last frame without waiting for the GPU
+    fn collect_stats(&mut self) {
+        let wanted = self.stats_writer.is_some() || self.panel.show_plots;
+        if wanted != self.sim.stats().is_some() {
+            self.sim.set_stats(wanted.then_some(self.opts.stats_every));
+        }
+        for stats in self.sim.collect_stats(false) {
+            self.history.push_stats(&stats);
+            if let Some(writer) = &mut self.stats_writer
+                && let Err(e) = writer.write(&stats)
+            {
+                println!("Failed to write stats: {e}");
+                self.stats_writer = None;
+            }
+        }
+        if let Some(profiler) = self.sim.profiler() {
+            let ms = Stage::ALL[Stage::COMPUTE]
+                .iter()
+                .map(|&stage| profiler.last_ms(stage))
+                .sum::<Option<f32>>();
+            if let Some(ms) = ms {
+                self.history.push_step_ms(self.sim.step_count(), ms);
+            }
+        }
     }
 
     pub fn step(&mut self) {
@@ -651,11 +668,23 @@ impl State {
         self.last_frame_t = now;
 
         if now.duration_since(self.last_sec).as_secs_f32() >= 1.0 {
-            self.t += 1;
-            println!(
-                "t={}\nPhysics FPS: {}\nRender FPS: {}",
-                self.t, self.phys_steps, self.rend_steps
-            );
+            self.phys_fps = self.phys_steps;
+            self.rend_fps = self.rend_steps;
+            if self.opts.validate {
+                println!("Validation: {}", self.sim.read_validation());
+            }
+            if let Some(profiler) = self.sim.profiler() {
+                println!("GPU stages:\n{}", profiler.summary());
+            }
+            if !self.panel.paused {
+                self.check_energy();
+            }
+            if let Some(writer) = &mut self.stats_writer
+                && let Err(e) = writer.flush()
+            {
+                println!("Failed to write stats: {e}");
+                self.stats_writer = None;
+            }
             self.phys_steps = 0;
             self.rend_steps = 0;
             self.last_sec = now;
@@ -663,36 +692,68 @@ impl State {
 
         self.time_acc += dur;
         self.time_acc = f32::min(self.time_acc, MAX_ACC);
+        if self.panel.paused {
+            self.time_acc = 0.0;
+        }
+
+        let cursor = self.cursor();
+        self.sim.set_cursor(cursor);
 
         let mut cmd_bufs = vec![];
         while self.time_acc >= PHYS_DT {
-            let cmd = self.compute();
+            let cmd = self.sim.compute();
             cmd_bufs.push(cmd);
             self.phys_steps += 1;
             self.time_acc -= PHYS_DT;
         }
 
-        if cmd_bufs.len() > 0 {
-            self.queue.submit(cmd_bufs);
+        if !cmd_bufs.is_empty() {
+            self.sim.queue().submit(cmd_bufs);
         }
+        self.sim.collect_profile();
+        self.collect_stats();
+        self.update_clusters();
+        self.update_pair_correlation();
 
         self.render();
         self.rend_steps += 1;
     }
 }
 
+/// Convert world coordinates to a window position in pixels, the inverse of
+/// [`State::to_world`]
+fn to_window(pos: Vec2, bound: [f32; 2], size: PhysicalSize<u32>) -> Vec2 {
+    let scale = Vec2::from(view_scale(bound, size));
+    let ndc = (pos / Vec2::from(bound) * 2.0 - 1.0) * scale;
+    vec2(ndc.x + 1.0, 1.0 - ndc.y) * 0.5 * vec2(size.width as f32, size.height as f32)
+}
+
+/// NDC scale that fits the world into the window without stretching it
+fn view_scale(bound: [f32; 2], size: PhysicalSize<u32>) -> [f32; 2] {
+    if size.width == 0 || size.height == 0 {
+        return [1.0, 1.0];
+    }
+    let world_aspect = bound[0] / bound[1];
+    let window_aspect = size.width as f32 / size.height as f32;
+    if window_aspect > world_aspect {
+        [world_aspect / window_aspect, 1.0]
+    } else {
+        [1.0, window_aspect / world_aspect]
+    }
+}
+
 pub struct App {
-    params: GpuParams,
+    simp: SimParams,
+    opts: Options,
     state: Option<State>,
-    mesh: Vec<f32>,
 }
 
 impl App {
-    pub fn new(params: GpuParams, mesh: Vec<f32>) -> Self {
+    pub fn new(simp: SimParams, opts: Options) -> Self {
         Self {
-            params,
+            simp,
+            opts,
             state: None,
-            mesh,
         }
     }
 }
@@ -705,7 +766,11 @@ impl ApplicationHandler for App {
                 .unwrap(),
         );
 
-        let state = pollster::block_on(State::new(Arc::clone(&window), self.params, &self.mesh));
+        let state = pollster::block_on(State::new(
+            Arc::clone(&window),
+            &self.simp,
+            self.opts.clone(),
+        ));
         self.state = Some(state.unwrap());
 
         window.request_redraw();
@@ -713,6 +778,22 @@ impl ApplicationHandler for App {
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
         let state = self.state.as_mut().unwrap();
+        // Releases always reach the world, so a button let go over the panel doesn't leave the
+        // cursor force on
+        if let WindowEvent::MouseInput {
+            state: ElementState::Released,
+            button,
+            ..
+        } = event
+        {
+            state.mouse.set(button, false);
+        }
+        if state
+            .gui
+            .on_window_event(&state.render_state.window, &event)
+        {
+            return;
+        }
         match event {
             WindowEvent::CloseRequested => {
                 println!("The close button was pressed; stopping");
@@ -736,18 +817,29 @@ impl ApplicationHandler for App {
                         ..
                     },
                 ..
-            } => handle_key(event_loop, code, key_state.is_pressed()),
+            } => handle_key(event_loop, state, code, key_state.is_pressed()),
+            WindowEvent::CursorMoved { position, .. } => state.mouse.pos = Some(position),
+            WindowEvent::CursorLeft { .. } => state.mouse.pos = None,
+            WindowEvent::MouseInput {
+                state: button_state,
+                button,
+                ..
+            } => state
+                .mouse
+                .set(button, button_state == ElementState::Pressed),
             _ => (),
         }
     }
 }
 
-fn handle_key(event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
+fn handle_key(event_loop: &ActiveEventLoop, state: &mut State, code: KeyCode, is_pressed: bool) {
     if !is_pressed {
         return;
     }
     match code {
         KeyCode::KeyQ => event_loop.exit(),
+        KeyCode::KeyP => state.panel.paused = !state.panel.paused,
+        KeyCode::KeyC => state.panel.show_clusters = !state.panel.show_clusters,
         _ => (),
     }
 }
@@ -0,0 +1,230 @@
+//! Options and runners shared by the headless batch bins, `sweep` and `evolve`
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use glam::{Vec2, vec2};
+use particle_life_core::{
+    Particle, SimParams, Simulator,
+    cluster::ClusterParams,
+    cpu::{CpuBackend, CpuOptions, CpuSim},
+};
+
+use crate::sim::{GpuSim, HeadlessOptions, headless_device};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Barnes-Hut on the CPU, runs in parallel
+    Cpu,
+    /// The compute pipeline, one run at a time
+    Gpu,
+}
+
+/// The world every run is in, the backend running them and how clusters are detected
+#[derive(clap::Args)]
+#[command(about = None, long_about = None)]
+pub struct BatchArgs {
+    #[arg(long, value_enum, default_value_t = Backend::Cpu)]
+    pub backend: Backend,
+    #[arg(long, default_value_t = 1000.0)]
+    pub width: f32,
+    #[arg(long, default_value_t = 1000.0)]
+    pub height: f32,
+    /// Barnes-Hut opening angle of the CPU backend
+    #[arg(long, default_value_t = 0.9)]
+    pub theta: f32,
+    /// CPU runs at once, defaults to the number of cores
+    #[arg(long)]
+    pub jobs: Option<usize>,
+    /// Only use a software adapter for the GPU backend
+    #[arg(long)]
+    pub fallback: bool,
+    /// Radius within which particles are neighbours for cluster detection
+    #[arg(long, default_value_t = 10.0)]
+    pub cluster_eps: f32,
+    /// Neighbours, counting the particle itself, a particle needs to seed or grow a cluster
+    #[arg(long, default_value_t = 4)]
+    pub cluster_min_points: usize,
+    /// Smallest cluster counted
+    #[arg(long, default_value_t = 10)]
+    pub cluster_min_size: usize,
+}
+
+impl BatchArgs {
+    pub fn bound(&self) -> Vec2 {
+        vec2(self.width, self.height)
+    }
+
+    pub fn clusters(&self) -> ClusterParams {
+        ClusterParams {
+            eps: self.cluster_eps,
+            min_points: self.cluster_min_points,
+            min_size: self.cluster_min_size,
+        }
+    }
+
+    /// Name of the backend as given on the command line
+    pub fn backend_name(&self) -> &'static str {
+        match self.backend {
+            Backend::Cpu => "cpu",
+            Backend::Gpu => "gpu",
+        }
+    }
+
+    pub fn cpu_options(&self, seed: u64) -> CpuOptions {
+        CpuOptions {
+            backend: CpuBackend::BarnesHut,
+            theta: self.theta,
+            bound: self.bound(),
+            seed: Some(seed),
+        }
+    }
+
+    /// Open the device of the GPU backend, if that's the one picked
+    pub fn runner(&self) -> Result<Runner<'_>> {
+        let gpu = match self.backend {
+            Backend::Cpu => None,
+            Backend::Gpu => {
+                let opts = HeadlessOptions {
+                    force_fallback_adapter: self.fallback,
+                    ..Default::default()
+                };
+                let (device, queue, info) = headless_device(&opts)?;
+                println!("Adapter: {} ({:?})", info.name, info.backend);
+                Some((device, queue))
+            }
+        };
+        Ok(Runner { args: self, gpu })
+    }
+}
+
+/// Spawns and schedules runs on the picked backend
+pub struct Runner<'a> {
+    args: &'a BatchArgs,
+    gpu: Option<(wgpu::Device, wgpu::Queue)>,
+}
+
+impl Runner<'_> {
+    /// A sim of `simp` spawned from `seed`. The GPU backend spawns like the CPU backend so both
+    /// start from the same particles, and seeds its noise from `seed` too.
+    pub fn spawn(&self, simp: SimParams, seed: u64) -> BatchSim {
+        let mut sim = CpuSim::new(simp, self.args.cpu_options(seed));
+        match &self.gpu {
+            None => BatchSim::Cpu(Box::new(sim)),
+            Some((device, queue)) => {
+                let spawn = sim.particles().to_vec();
+                let simp = sim.params().clone();
+                let mut sim = GpuSim::with_particles(device.clone(), queue.clone(), simp, spawn);
+                sim.set_seed((seed ^ seed >> 32) as u32);
+                BatchSim::Gpu(Box::new(sim))
+            }
+        }
+    }
+
+    /// Call `run` on every item and return the results in order. The CPU backend runs `--jobs`
+    /// items at once, the GPU backend one at a time. No more items are started after an error.
+    pub fn try_map<T: Sync, R: Send>(
+        &self,
+        items: &[T],
+        run: impl Fn(&T) -> Result<R> + Sync,
+    ) -> Result<Vec<R>> {
+        let jobs = match self.gpu {
+            Some(_) => 1,
+            None => self
+                .args
+                .jobs
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        };
+        let next = AtomicUsize::new(0);
+        let mut results = items.iter().map(|_| None).collect::<Vec<_>>();
+        std::thread::scope(|s| {
+            let workers = (0..jobs.clamp(1, items.len().max(1)))
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut done = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(item) = items.get(i) else {
+                                break;
+                            };
+                            match run(item) {
+                                Ok(r) => done.push((i, r)),
+                                Err(e) => {
+                                    next.store(items.len(), Ordering::Relaxed);
+                                    return Err(e);
+                                }
+                            }
+                        }
+                        Ok(done)
+                    })
+                })
+                .collect::<Vec<_>>();
+            for worker in workers {
+                for (i, r) in worker.join().expect("batch worker panicked")? {
+                    results[i] = Some(r);
+                }
+            }
+            anyhow::Ok(())
+        })?;
+        Ok(results.into_iter().flatten().collect())
+    }
+}
+
+/// A sim on either backend
+pub enum BatchSim {
+    Cpu(Box<CpuSim>),
+    Gpu(Box<GpuSim>),
+}
+
+impl BatchSim {
+    /// Step `n` times, waiting for the GPU to finish
+    pub fn advance(&mut self, n: u32) {
+        match self {
+            Self::Cpu(sim) => {
+                for _ in 0..n {
+                    sim.step(1.0);
+                }
+            }
+            Self::Gpu(sim) => {
+                sim.step_n(n, 1.0);
+                sim.wait();
+            }
+        }
+    }
+
+    fn sim(&mut self) -> &mut dyn Simulator {
+        match self {
+            Self::Cpu(sim) => sim.as_mut(),
+            Self::Gpu(sim) => sim.as_mut(),
+        }
+    }
+}
+
+impl Simulator for BatchSim {
+    fn step(&mut self, dt: f32) {
+        self.sim().step(dt);
+    }
+
+    fn step_count(&self) -> u64 {
+        match self {
+            Self::Cpu(sim) => sim.step_count(),
+            Self::Gpu(sim) => sim.step_count(),
+        }
+    }
+
+    fn particles(&mut self) -> &[Particle] {
+        self.sim().particles()
+    }
+
+    fn params(&self) -> &SimParams {
+        match self {
+            Self::Cpu(sim) => sim.params(),
+            Self::Gpu(sim) => sim.params(),
+        }
+    }
+
+    fn set_params(&mut self, params: SimParams) {
+        self.sim().set_params(params);
+    }
+}
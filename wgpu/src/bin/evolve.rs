@@ -0,0 +1,241 @@
+//! Evolve gravity meshes towards life-like structures: each generation runs the candidates
+//! headlessly, scores them with the `--fitness` terms and saves the best as presets.
+//!
+//! `cargo run --release -p particle-life --bin evolve -- --out presets --generations 20`
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use clap::Parser;
+use particle_life::batch::{BatchArgs, BatchSim};
+use particle_life_core::{
+    Mesh, SimParams,
+    evolve::{
+        self, Candidate, Evolution, EvolveParams, FitnessTerm, Preset, score_terms, total_score,
+    },
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+#[derive(Parser)]
+#[command(
+    mut_arg("width", |a| a.default_value("600")),
+    mut_arg("height", |a| a.default_value("600"))
+)]
+struct Args {
+    /// Directory for the presets and history.csv
+    #[arg(long)]
+    out: PathBuf,
+    /// Weighted fitness terms, name or name:weight, from moving, persistence, coherence and
+    /// clustered
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "moving,persistence,coherence"
+    )]
+    fitness: Vec<FitnessTerm>,
+    #[arg(long, default_value_t = 20)]
+    generations: u32,
+    /// Candidates per generation, elites included
+    #[arg(long, default_value_t = 24)]
+    population: usize,
+    /// Best candidates kept each generation, which the next is drawn around
+    #[arg(long, default_value_t = 6)]
+    elites: usize,
+    /// Floor on the std of each mesh coefficient
+    #[arg(long, default_value_t = 0.05)]
+    min_sigma: f32,
+    /// Weight of the elites when moving the search distribution
+    #[arg(long, default_value_t = 0.7)]
+    learning_rate: f32,
+    /// Seed of the search
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Spawn seeds each candidate is run with, its score is the mean
+    #[arg(long, value_delimiter = ',', default_value = "0,1")]
+    spawn_seeds: Vec<u64>,
+    /// Best candidates saved as presets
+    #[arg(long, default_value_t = 5)]
+    keep: usize,
+    #[arg(short, long, default_value_t = 6)]
+    cultures: u32,
+    /// Particles per culture
+    #[arg(short, long, default_value_t = 150)]
+    particles: u32,
+    #[arg(short, long, default_value_t = 50.0)]
+    aoe: f32,
+    #[arg(short, long, default_value_t = 0.1)]
+    damping: f32,
+    /// Steps per run
+    #[arg(long, default_value_t = 1000)]
+    steps: u32,
+    /// Steps before scoring starts, so the spawn has settled
+    #[arg(long, default_value_t = 500)]
+    warmup: u32,
+    /// Steps between the cluster updates the fitness is scored on
+    #[arg(long, default_value_t = 50)]
+    every: u32,
+    #[command(flatten)]
+    batch: BatchArgs,
+}
+
+impl Args {
+    fn params(&self, mesh: &Mesh, seed: u64) -> SimParams {
+        let mut simp = SimParams::random(
+            self.cultures,
+            self.particles,
+            self.aoe,
+            self.damping,
+            &mut StdRng::seed_from_u64(seed),
+        );
+        simp.mesh = mesh.clone();
+        simp.bound = Some(self.batch.bound());
+        simp
+    }
+}
+
+fn main() {
+    if let Err(e) = search(&Args::parse()) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+fn search(args: &Args) -> Result<()> {
+    let params = EvolveParams {
+        population: args.population,
+        elites: args.elites,
+        min_sigma: args.min_sigma,
+        learning_rate: args.learning_rate,
+    };
+    params.validate().map_err(anyhow::Error::msg)?;
+    evolve::validate_record(args.steps, args.warmup).map_err(anyhow::Error::msg)?;
+    if args.keep > args.elites {
+        anyhow::bail!(
+            "can keep at most the {} elites, got --keep {}",
+            args.elites,
+            args.keep
+        );
+    }
+    args.batch
+        .clusters()
+        .validate()
+        .map_err(anyhow::Error::msg)?;
+    let mut evolution = Evolution::new(args.cultures as usize, params, args.seed);
+    // The params only differ in the mesh between candidates, so one sample checks them all
+    let mesh = Mesh::random(
+        args.cultures as usize,
+        &mut StdRng::seed_from_u64(args.seed),
+    );
+    args.params(&mesh, args.seed)
+        .validate()
+        .map_err(anyhow::Error::msg)?;
+    let runner = args.batch.runner()?;
+
+    std::fs::create_dir_all(&args.out)?;
+    let path = args.out.join("history.csv");
+    let history = File::create_new(&path).map_err(|e| {
+        anyhow::anyhow!(
+            "{}: {e}, pick another --out to keep the earlier search",
+            path.display()
+        )
+    })?;
+    let mut history = BufWriter::new(history);
+    write!(history, "generation,best,mean,sigma")?;
+    for term in &args.fitness {
+        write!(history, ",{}", term.fitness.name())?;
+    }
+    writeln!(history)?;
+
+    for _ in 0..args.generations {
+        let generation = evolution.generation();
+        let meshes = evolution.ask();
+        // Score of each fitness term averaged over the spawn seeds
+        let scores = runner.try_map(&meshes, |mesh| {
+            let mut sums = vec![0.0; args.fitness.len()];
+            for &seed in &args.spawn_seeds {
+                let mut sim = runner.spawn(args.params(mesh, seed), seed);
+                let trajectory = evolve::record(
+                    &mut sim,
+                    args.steps,
+                    args.warmup,
+                    args.every,
+                    args.batch.bound(),
+                    args.batch.clusters(),
+                    BatchSim::advance,
+                );
+                for (sum, s) in sums.iter_mut().zip(score_terms(&args.fitness, &trajectory)) {
+                    *sum += s;
+                }
+            }
+            let n = args.spawn_seeds.len().max(1) as f32;
+            anyhow::Ok(sums.into_iter().map(|s| s / n).collect::<Vec<_>>())
+        })?;
+        let candidates = meshes
+            .into_iter()
+            .zip(scores)
+            .map(|(mesh, scores)| Candidate {
+                mesh,
+                score: total_score(&args.fitness, &scores),
+                scores,
+                generation,
+            })
+            .collect::<Vec<_>>();
+        let mean = candidates.iter().map(|c| c.score).sum::<f32>() / candidates.len().max(1) as f32;
+        evolution.tell(candidates);
+
+        let best = &evolution.elites()[0];
+        let terms = args
+            .fitness
+            .iter()
+            .zip(&best.scores)
+            .map(|(t, s)| format!("{} {s:.3}", t.fitness.name()))
+            .collect::<Vec<_>>();
+        println!(
+            "Generation {generation}: best {:.3} ({}), mean {mean:.3}, sigma {:.3}",
+            best.score,
+            terms.join(", "),
+            evolution.mean_sigma()
+        );
+        write!(
+            history,
+            "{generation},{},{mean},{}",
+            best.score,
+            evolution.mean_sigma()
+        )?;
+        for s in &best.scores {
+            write!(history, ",{s}")?;
+        }
+        writeln!(history)?;
+        history.flush()?;
+        save_presets(args, evolution.elites())?;
+    }
+    println!(
+        "Saved the best {} meshes to {}",
+        args.keep.min(evolution.elites().len()),
+        args.out.display()
+    );
+    Ok(())
+}
+
+/// Overwrite best-<rank>.json with the best candidates so far
+fn save_presets(args: &Args, elites: &[Candidate]) -> Result<()> {
+    for (rank, c) in elites.iter().take(args.keep).enumerate() {
+        let preset = Preset {
+            score: c.score,
+            scores: args
+                .fitness
+                .iter()
+                .zip(&c.scores)
+                .map(|(t, &s)| (t.fitness.name().to_string(), s))
+                .collect(),
+            generation: c.generation,
+            params: args.params(&c.mesh, args.spawn_seeds.first().copied().unwrap_or(0)),
+        };
+        preset.save(&args.out.join(format!("best-{}.json", rank + 1)))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,210 @@
+//! Run a grid or random sample of sim params headlessly and write a table of observables, with
+//! an optional thumbnail of each run's final state. Rerunning the same command resumes an
+//! interrupted sweep.
+//!
+//! `cargo run --release -p particle-life --bin sweep -- --out sweep --aoe 20..80 --seeds 0..4`
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use glam::vec2;
+use particle_life::batch::{BatchArgs, BatchSim};
+use particle_life_core::{
+    Particle, SimParams, Simulator,
+    color::random_colors,
+    sweep::{self, Axis, MeshGen, SweepResults, SweepRun, SweepSettings, SweepSpec},
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+#[derive(Parser)]
+struct Args {
+    /// Directory for results.csv and the thumbnails
+    #[arg(long)]
+    out: PathBuf,
+    /// Steps per run
+    #[arg(long, default_value_t = 1000)]
+    steps: u32,
+    /// Mesh generators: uniform, symmetric or sparse:<density>
+    #[arg(long, value_delimiter = ',', default_value = "uniform")]
+    meshes: Vec<MeshGen>,
+    /// Cultures, a list like 4,8 or a range like 2..10
+    #[arg(long, default_value = "10")]
+    cultures: Axis<u32>,
+    /// Particles per culture, a list or a range
+    #[arg(long, default_value = "200")]
+    culture_size: Axis<u32>,
+    /// Area of effect, a list or a range
+    #[arg(long, default_value = "50")]
+    aoe: Axis<f32>,
+    /// Damping, a list or a range
+    #[arg(long, default_value = "0.1")]
+    damping: Axis<f32>,
+    /// Seeds picking each run's mesh and spawn, a list or a range
+    #[arg(long, default_value = "0")]
+    seeds: Axis<u64>,
+    /// Values a float range is split into on the grid
+    #[arg(long, default_value_t = 5)]
+    resolution: usize,
+    /// Draw this many random runs from the axes instead of running the grid
+    #[arg(long)]
+    sample: Option<usize>,
+    #[arg(long, default_value_t = 0)]
+    sample_seed: u64,
+    #[command(flatten)]
+    batch: BatchArgs,
+    /// Write a png of each run's final state to thumbnails/<id>.png
+    #[arg(long)]
+    thumbnails: bool,
+    /// Width of the thumbnails in pixels
+    #[arg(long, default_value_t = 256)]
+    thumbnail_size: u32,
+}
+
+impl Args {
+    /// The params of `run` in this sweep's world
+    fn params(&self, run: &SweepRun) -> SimParams {
+        let mut simp = run.params();
+        simp.bound = Some(self.batch.bound());
+        simp
+    }
+}
+
+fn main() {
+    if let Err(e) = sweep(&Args::parse()) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+fn sweep(args: &Args) -> Result<()> {
+    let spec = SweepSpec {
+        meshes: args.meshes.clone(),
+        cultures: args.cultures.clone(),
+        culture_size: args.culture_size.clone(),
+        aoe: args.aoe.clone(),
+        damping: args.damping.clone(),
+        seeds: args.seeds.clone(),
+        resolution: args.resolution,
+        sample: args.sample,
+        sample_seed: args.sample_seed,
+    };
+    let runs = spec.runs();
+    let batch = &args.batch;
+    batch.clusters().validate().map_err(anyhow::Error::msg)?;
+    for run in &runs {
+        args.params(run).validate().map_err(|e| {
+            anyhow!(
+                "run {} with cultures {}, culture size {}, aoe {}, damping {}: {e}",
+                run.id,
+                run.cultures,
+                run.culture_size,
+                run.aoe,
+                run.damping
+            )
+        })?;
+    }
+    std::fs::create_dir_all(&args.out)?;
+    if args.thumbnails {
+        std::fs::create_dir_all(args.out.join("thumbnails"))?;
+    }
+    let path = args.out.join("results.csv");
+    let settings = SweepSettings {
+        steps: args.steps,
+        bound: batch.bound(),
+        backend: batch.backend_name().to_string(),
+        theta: batch.theta,
+        clusters: batch.clusters(),
+    };
+    let (results, done) = SweepResults::open(&path, &runs, &settings)?;
+    let todo = runs
+        .iter()
+        .filter(|run| !done.contains(&run.id))
+        .copied()
+        .collect::<Vec<_>>();
+    println!(
+        "{} runs, {} already in {}",
+        runs.len(),
+        done.len(),
+        path.display()
+    );
+    let results = Mutex::new(results);
+    let finished = AtomicUsize::new(0);
+    let runner = batch.runner()?;
+    runner.try_map(&todo, |run| {
+        let mut sim = runner.spawn(args.params(run), run.seed);
+        let start = Instant::now();
+        let obs = sweep::run(
+            &mut sim,
+            args.steps,
+            batch.bound(),
+            batch.clusters(),
+            BatchSim::advance,
+        );
+        let ms = start.elapsed().as_secs_f64() * 1000.0 / obs.steps.max(1) as f64;
+        if args.thumbnails {
+            write_thumbnail(args, run, sim.particles())?;
+        }
+        results.lock().unwrap().write(run, &obs, ms)?;
+        let n = finished.fetch_add(1, Ordering::Relaxed) + 1;
+        println!(
+            "[{n}/{}] run {}: {} clusters, {:.0}% clustered, energy {:.3}{}",
+            todo.len(),
+            run.id,
+            obs.clusters,
+            obs.clustered * 100.0,
+            obs.kinetic_energy,
+            if obs.exploded { ", exploded" } else { "" }
+        );
+        anyhow::Ok(())
+    })?;
+    println!("Wrote results to {}", path.display());
+    Ok(())
+}
+
+/// Draw the particles as 2x2 dots in their culture colors, which are seeded by the run
+fn write_thumbnail(args: &Args, run: &SweepRun, particles: &[Particle]) -> Result<()> {
+    let bound = args.batch.bound();
+    let width = args.thumbnail_size.max(1);
+    let height = ((width as f32 * bound.y / bound.x).round() as u32).max(1);
+    let colors = random_colors(run.cultures as usize, &mut StdRng::seed_from_u64(run.seed));
+    let mut rgba = [0, 0, 0, 255].repeat((width * height) as usize);
+    let scale = vec2(width as f32, height as f32) / bound;
+    for (i, p) in particles.iter().enumerate() {
+        let [r, g, b, _] = colors[i / run.culture_size.max(1) as usize].map(|c| (c * 255.0) as u8);
+        let px = (p.pos * scale).floor();
+        if !px.is_finite() {
+            continue;
+        }
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let (x, y) = (px.x as i64 + dx, px.y as i64 + dy);
+            if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+                let o = (y as usize * width as usize + x as usize) * 4;
+                rgba[o..o + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+    write_png(
+        &args.out.join("thumbnails").join(format!("{}.png", run.id)),
+        width,
+        height,
+        &rgba,
+    )
+}
+
+fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgba)?;
+    Ok(())
+}
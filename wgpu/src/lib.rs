@@ -1,2 +1,8 @@
 pub mod app;
-pub mod util;
+pub mod batch;
+pub mod obstacle;
+pub mod plots;
+pub mod profiler;
+pub mod sim;
+pub mod stats;
+pub mod ui;
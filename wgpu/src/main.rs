@@ -1,9 +1,26 @@
-mod app;
-mod util;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::Instant,
+};
 
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use util::random_gravity_mesh_flat;
+use anyhow::Result;
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use glam::vec2;
+use particle_life::{
+    app,
+    sim::{DEFAULT_BOUND, GpuSim, HeadlessOptions, headless_device},
+};
+use particle_life_core::{
+    SimParams, Simulator,
+    cluster::ClusterParams,
+    correlation::PairCorrelation,
+    sim::Repulsion,
+    stability::{EnergyMonitor, OnExplosion},
+    stats::StatsWriter,
+};
+use rand::{SeedableRng, rngs::StdRng};
 
 #[derive(Parser)]
 struct Args {
@@ -17,30 +34,291 @@ struct Args {
     aoe: f32,
     #[arg(short, long, default_value_t = 0.1)]
     damping: f32,
+    #[arg(short, long, default_value_t = 0.0)]
+    temperature: f32,
+    /// Hard-core radius particles repel each other within, 0 to disable
+    #[arg(long, default_value_t = 0.0)]
+    repulsion_radius: f32,
+    /// Repulsion at contact, falling to 0 at the radius
+    #[arg(long, default_value_t = 1.0)]
+    repulsion_strength: f32,
+    /// Cap on the force applied to a particle each step, 0 for no cap
+    #[arg(long, default_value_t = 0.0)]
+    max_force: f32,
+    /// Cap on particle speed, 0 for no cap
+    #[arg(long, default_value_t = 0.0)]
+    max_speed: f32,
+    /// World width, unless the SimParams json sets a bound
+    #[arg(long, default_value_t = 1000.0)]
+    width: f32,
+    /// World height, unless the SimParams json sets a bound
+    #[arg(long, default_value_t = 1000.0)]
+    height: f32,
+    /// Run the compute pipeline without a window for `--steps` steps
+    #[arg(long)]
+    headless: bool,
+    /// Steps to run in headless mode
+    #[arg(long, default_value_t = 1000)]
+    steps: u32,
+    /// Only use a software adapter in headless mode
+    #[arg(long)]
+    fallback: bool,
+    /// Write the final snapshot json here in headless mode
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// Sort particles by bin every step before computing forces
+    #[arg(long)]
+    sort: bool,
+    /// Count out of range and non-finite particles every step and report them
+    #[arg(long)]
+    validate: bool,
+    /// Radius of the mouse cursor force
+    #[arg(long, default_value_t = 100.0)]
+    cursor_radius: f32,
+    /// Strength of the mouse cursor force, left click repels and right click attracts
+    #[arg(long, default_value_t = 2.0)]
+    cursor_strength: f32,
+    /// Time each GPU stage with timestamp queries, if the adapter supports them
+    #[arg(long)]
+    profile: bool,
+    /// Steps (or frames) the profile is averaged over
+    #[arg(long, default_value_t = 120)]
+    profile_window: usize,
+    /// Write the profile json here in headless mode
+    #[arg(long)]
+    profile_out: Option<PathBuf>,
+    /// What to do when the kinetic energy explodes: off, warn, pause (stops a headless run) or
+    /// clamp (caps the speed)
+    #[arg(long, default_value_t = OnExplosion::Warn)]
+    on_explosion: OnExplosion,
+    /// Energy over its running baseline that counts as an explosion
+    #[arg(long, default_value_t = 100.0)]
+    explosion_factor: f32,
+    /// Stream per culture stats here, csv for a .csv path and json lines otherwise
+    #[arg(long)]
+    stats_out: Option<PathBuf>,
+    /// Steps between stats
+    #[arg(long, default_value_t = 10)]
+    stats_every: u32,
+    /// Write the pair correlation g(r) of the final state here as csv in headless mode
+    #[arg(long)]
+    pair_correlation: Option<PathBuf>,
+    /// Largest distance binned for --pair-correlation, the aoe by default
+    #[arg(long)]
+    pair_radius: Option<f32>,
+    /// Distance bins for --pair-correlation
+    #[arg(long, default_value_t = 50)]
+    pair_bins: usize,
+    /// Radius within which particles are neighbours for cluster detection
+    #[arg(long, default_value_t = 10.0)]
+    cluster_eps: f32,
+    /// Neighbours, counting the particle itself, a particle needs to seed or grow a cluster
+    #[arg(long, default_value_t = 4)]
+    cluster_min_points: usize,
+    /// Smallest cluster shown
+    #[arg(long, default_value_t = 10)]
+    cluster_min_size: usize,
+    /// Seed for the random SimParams, the spawn and noise stay unseeded
+    #[arg(short, long)]
+    seed: Option<u64>,
+    /// Ignored, macroquad's Barnes-Hut opening angle. The GPU computes exact forces.
+    #[arg(long)]
+    theta: Option<f32>,
+    /// Ignored, macroquad's force method. The GPU computes exact forces.
+    #[arg(short, long, value_parser = ["naive", "barnes-hut"])]
+    backend: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SimParams {
-    num_cultures: u32,
-    culture_size: u32,
-    aoe: f32,
-    damping: f32,
-    mesh: Vec<f32>,
-}
+/// Steps submitted at once when profiling headless, so timestamp readback keeps up
+const PROFILE_CHUNK: u32 = 16;
+/// Steps between energy checks in headless mode
+const ENERGY_CHECK_STEPS: u32 = 100;
+/// Stats steps submitted at once in headless mode, well within the staging buffers
+const STATS_CHUNK: u32 = 32;
 
 fn main() {
     let args = Args::parse();
-    let simp = match args.simp {
-        Some(s) => serde_json::from_str(&s).expect("Simp arg should be valid json"),
-        None => SimParams {
-            num_cultures: args.cultures,
-            culture_size: args.particles,
-            aoe: args.aoe,
-            damping: args.damping,
-            mesh: random_gravity_mesh_flat(args.cultures as usize),
-        },
+    let clusters = ClusterParams {
+        eps: args.cluster_eps,
+        min_points: args.cluster_min_points,
+        min_size: args.cluster_min_size,
+    };
+    if let Err(e) = clusters.validate() {
+        Args::command().error(ErrorKind::ValueValidation, e).exit();
+    }
+    if args.theta.is_some() || args.backend.is_some() {
+        eprintln!("--theta and --backend only apply to macroquad's CPU forces, ignoring them");
+    }
+    let mut simp = match &args.simp {
+        Some(s) => SimParams::from_json(s).unwrap_or_else(|e| panic!("{e}")),
+        None => {
+            let mut rng = match args.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_rng(&mut rand::rng()),
+            };
+            let mut simp = SimParams::random(
+                args.cultures,
+                args.particles,
+                args.aoe,
+                args.damping,
+                &mut rng,
+            );
+            simp.temperature = args.temperature;
+            simp.repulsion = Repulsion {
+                radius: args.repulsion_radius,
+                strength: args.repulsion_strength,
+            };
+            simp.max_force = args.max_force;
+            simp.max_speed = args.max_speed;
+            simp
+        }
     };
-    println!("SimParams\n{}", serde_json::to_string(&simp).unwrap());
-    let params = app::GpuParams::new(simp.num_cultures, simp.culture_size, simp.aoe, simp.damping);
-    app::run(params, simp.mesh);
+    simp.bound.get_or_insert(vec2(args.width, args.height));
+    println!("SimParams\n{}", simp.to_json());
+    if args.headless {
+        if let Err(e) = run_headless(simp, &args) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    } else {
+        let opts = app::Options {
+            sort: args.sort,
+            validate: args.validate,
+            cursor_radius: args.cursor_radius,
+            cursor_strength: args.cursor_strength,
+            profile: args.profile.then_some(args.profile_window),
+            on_explosion: args.on_explosion,
+            explosion_factor: args.explosion_factor,
+            stats_every: args.stats_every,
+            stats_out: args.stats_out.clone(),
+            clusters,
+        };
+        app::run(simp, opts);
+    }
+}
+
+fn run_headless(simp: SimParams, args: &Args) -> Result<()> {
+    let steps = args.steps;
+    let pair_radius = args.pair_radius.unwrap_or(simp.aoe);
+    if args.pair_correlation.is_some() && (pair_radius.is_nan() || pair_radius <= 0.0) {
+        anyhow::bail!("--pair-radius must be positive, got {pair_radius}");
+    }
+    if args.pair_correlation.is_some() && args.pair_bins == 0 {
+        anyhow::bail!("--pair-bins must be at least 1");
+    }
+    let opts = HeadlessOptions {
+        force_fallback_adapter: args.fallback,
+        ..Default::default()
+    };
+    let (device, queue, info) = headless_device(&opts)?;
+    println!("Adapter: {} ({:?})", info.name, info.backend);
+
+    let mut sim = GpuSim::new(device, queue, simp);
+    sim.set_sorted(args.sort);
+    sim.set_validation(args.validate);
+    let profile = args.profile && sim.set_profiling(true, args.profile_window);
+    if args.profile && !profile {
+        println!("Timestamp queries aren't supported by this adapter, not profiling");
+    }
+
+    let mut stats_writer = args
+        .stats_out
+        .as_deref()
+        .map(StatsWriter::create)
+        .transpose()?;
+    if stats_writer.is_some() {
+        sim.set_stats(Some(args.stats_every));
+    }
+
+    let guard = args.on_explosion != OnExplosion::Off;
+    let mut chunk = steps.max(1);
+    if guard {
+        chunk = ENERGY_CHECK_STEPS;
+    }
+    if stats_writer.is_some() {
+        chunk = chunk.min(args.stats_every.max(1).saturating_mul(STATS_CHUNK));
+    }
+    if profile {
+        chunk = chunk.min(PROFILE_CHUNK);
+    }
+    let mut monitor = EnergyMonitor::new(args.explosion_factor);
+    let mut done = 0;
+    let mut since_check = 0;
+    let start = Instant::now();
+    while done < steps {
+        let n = (steps - done).min(chunk);
+        sim.step_n(n, 1.0);
+        sim.wait();
+        if profile {
+            sim.collect_profile();
+        }
+        if let Some(writer) = &mut stats_writer {
+            for stats in sim.collect_stats(false) {
+                writer.write(&stats)?;
+            }
+        }
+        done += n;
+        since_check += n;
+        if guard && (since_check >= ENERGY_CHECK_STEPS || done == steps) {
+            since_check = 0;
+            if let Some(explosion) = monitor.check(&mut sim, args.on_explosion) {
+                println!("Warning: {explosion}");
+                if args.on_explosion == OnExplosion::Pause {
+                    println!("Stopping early");
+                    break;
+                }
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{} steps in {:.3} ms ({:.3} ms/step)",
+        done,
+        elapsed.as_secs_f64() * 1000.0,
+        elapsed.as_secs_f64() * 1000.0 / done.max(1) as f64
+    );
+
+    if let Some(writer) = &mut stats_writer {
+        for stats in sim.collect_stats(true) {
+            writer.write(&stats)?;
+        }
+        writer.flush()?;
+        println!(
+            "Wrote stats to {}",
+            args.stats_out.as_ref().unwrap().display()
+        );
+    }
+
+    if args.validate {
+        println!("Validation: {}", sim.read_validation());
+    }
+
+    if let Some(profiler) = sim.profiler() {
+        let summary = profiler.summary();
+        println!(
+            "GPU stages over the last {} steps:\n{summary}",
+            args.profile_window
+        );
+        if let Some(path) = &args.profile_out {
+            std::fs::write(path, summary.to_json().to_string())?;
+            println!("Wrote profile to {}", path.display());
+        }
+    }
+
+    if let Some(path) = &args.pair_correlation {
+        let simp = sim.params().clone();
+        let bound = simp.bound.unwrap_or(DEFAULT_BOUND);
+        let gr =
+            PairCorrelation::compute(&simp, bound, sim.particles(), pair_radius, args.pair_bins);
+        let mut out = BufWriter::new(File::create(path)?);
+        gr.write_csv(&mut out)?;
+        out.flush()?;
+        println!("Wrote pair correlation to {}", path.display());
+    }
+
+    if let Some(path) = &args.out {
+        std::fs::write(path, serde_json::to_string(&sim.snapshot())?)?;
+        println!("Wrote snapshot to {}", path.display());
+    }
+    Ok(())
 }
@@ -0,0 +1,36 @@
+use particle_life_core::obstacle::{MIN_SEGMENT_RADIUS, Obstacles};
+
+/// Capsule from `a` to `b`. Circles are capsules with `a == b`.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuObstacle {
+    pub a: [f32; 2],
+    pub b: [f32; 2],
+    pub radius: f32,
+    pub _pad: f32,
+}
+
+/// Flatten circles and segments into a single list for the obstacle storage buffer
+pub fn gpu_obstacles(obstacles: &Obstacles) -> Vec<GpuObstacle> {
+    let circles = obstacles.circles.iter().map(|c| GpuObstacle {
+        a: c.center.to_array(),
+        b: c.center.to_array(),
+        radius: c.radius,
+        _pad: 0.0,
+    });
+    let segments = obstacles.segments.iter().map(|s| GpuObstacle {
+        a: s.a.to_array(),
+        b: s.b.to_array(),
+        radius: s.radius.max(MIN_SEGMENT_RADIUS),
+        _pad: 0.0,
+    });
+    circles.chain(segments).collect()
+}
+
+/// Occupancy mask cells as one u32 per cell for the mask storage buffer
+pub fn gpu_mask(obstacles: &Obstacles) -> Vec<u32> {
+    match &obstacles.mask {
+        Some(mask) => mask.cells.iter().map(|&c| c as u32).collect(),
+        None => vec![],
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+
+use particle_life_core::stats::Stats;
+
+/// Points kept per series
+const HISTORY_LEN: usize = 2000;
+
+/// Rolling series of (step, value) points
+#[derive(Default)]
+struct Series(VecDeque<[f64; 2]>);
+
+impl Series {
+    fn push(&mut self, step: u64, value: f32) {
+        if self.0.len() == HISTORY_LEN {
+            self.0.pop_front();
+        }
+        self.0.push_back([step as f64, value as f64]);
+    }
+
+    fn line(&self, name: impl Into<String>) -> egui_plot::Line<'_> {
+        let (a, b) = self.0.as_slices();
+        let points = a.iter().chain(b).copied().collect::<Vec<_>>();
+        egui_plot::Line::new(name, points)
+    }
+}
+
+/// Rolling history of the sim's observables against the physics step, plotted in their own
+/// window. Pausing freezes the series so they can be zoomed and panned.
+#[derive(Default)]
+pub struct History {
+    pub paused: bool,
+    energy: Series,
+    speeds: Vec<Series>,
+    clusters: Series,
+    step_ms: Series,
+}
+
+impl History {
+    /// Kinetic energy and per culture mean speed
+    pub fn push_stats(&mut self, stats: &Stats) {
+        if self.paused {
+            return;
+        }
+        self.energy.push(stats.step, stats.total.kinetic_energy);
+        self.speeds
+            .resize_with(stats.cultures.len(), Default::default);
+        for (series, c) in self.speeds.iter_mut().zip(&stats.cultures) {
+            series.push(stats.step, c.mean_speed);
+        }
+    }
+
+    pub fn push_clusters(&mut self, step: u64, count: usize) {
+        if !self.paused {
+            self.clusters.push(step, count as f32);
+        }
+    }
+
+    /// GPU time of the step's compute stages
+    pub fn push_step_ms(&mut self, step: u64, ms: f32) {
+        if !self.paused && self.step_ms.0.back().is_none_or(|p| p[0] < step as f64) {
+            self.step_ms.push(step, ms);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self {
+            paused: self.paused,
+            ..Default::default()
+        };
+    }
+
+    /// Plot every series in a window, speeds in the culture colors
+    pub fn show(&mut self, ctx: &egui::Context, open: &mut bool, colors: &[[f32; 4]]) {
+        egui::Window::new("Plots")
+            .open(open)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.paused, "Paused");
+                    if ui.button("Clear").clicked() {
+                        self.clear();
+                    }
+                });
+                ui.label("Drag to pan, scroll to zoom, double click to reset");
+
+                let plot = |id: &str| {
+                    egui_plot::Plot::new(id)
+                        .height(110.0)
+                        .link_axis("history", [true, false])
+                        .link_cursor("history", [true, false])
+                        .y_axis_label(id)
+                };
+                plot("kinetic energy").show(ui, |plot| {
+                    plot.line(self.energy.line("energy"));
+                });
+                plot("mean speed").show(ui, |plot| {
+                    for (c, series) in self.speeds.iter().enumerate() {
+                        let [r, g, b, _] = colors.get(c).copied().unwrap_or([1.0; 4]);
+                        plot.line(
+                            series
+                                .line(format!("culture {c}"))
+                                .color(egui::Rgba::from_rgb(r, g, b)),
+                        );
+                    }
+                });
+                if self.clusters.0.is_empty() {
+                    ui.label("Show clusters to plot the cluster count");
+                } else {
+                    plot("clusters").show(ui, |plot| {
+                        plot.line(self.clusters.line("clusters"));
+                    });
+                }
+                if self.step_ms.0.is_empty() {
+                    ui.label("Run with --profile to plot the GPU step time");
+                } else {
+                    plot("step ms").show(ui, |plot| {
+                        plot.line(self.step_ms.line("step ms"));
+                    });
+                }
+            });
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    ops::Range,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Profiled GPU stages, each timed from the start to the end of its pass
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Bin indices and counts
+    Count,
+    /// Bin offsets scan
+    Offsets,
+    /// Bin contents
+    Build,
+    /// Forces and integration, including the reorder pass when sorting
+    Force,
+    Render,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Count,
+        Stage::Offsets,
+        Stage::Build,
+        Stage::Force,
+        Stage::Render,
+    ];
+    /// Stages recorded by a compute step
+    pub const COMPUTE: Range<usize> = 0..4;
+    /// Stages recorded by a frame
+    pub const RENDER: Range<usize> = 4..5;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Count => "count",
+            Stage::Offsets => "offsets",
+            Stage::Build => "build",
+            Stage::Force => "force",
+            Stage::Render => "render",
+        }
+    }
+}
+
+/// Staging buffer holding one resolved set of timestamps
+struct Slot {
+    buffer: wgpu::Buffer,
+    stages: Range<usize>,
+    mapped: Arc<AtomicBool>,
+}
+
+/// Timestamp queries around each stage, aggregated over the last `window` samples per stage.
+///
+/// Readback is asynchronous: [`GpuProfiler::collect`] maps slots once their commands have been
+/// submitted and reads back the ones that are ready, without blocking. A step or frame that
+/// finds no free slot simply isn't profiled.
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    free: Vec<wgpu::Buffer>,
+    /// Resolved but not yet submitted
+    encoded: Vec<Slot>,
+    /// Submitted and waiting for the map
+    pending: Vec<Slot>,
+    num_buffers: usize,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    window: usize,
+    samples: [VecDeque<f32>; Stage::ALL.len()],
+}
+
+const QUERY_SIZE: u64 = size_of::<u64>() as u64;
+const MAX_BUFFERS: usize = 64;
+
+impl GpuProfiler {
+    /// None when the device doesn't support `TIMESTAMP_QUERY`
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, window: usize) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let count = 2 * Stage::ALL.len() as u32;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Stage Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve"),
+            size: count as u64 * QUERY_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            free: vec![],
+            encoded: vec![],
+            pending: vec![],
+            num_buffers: 0,
+            period: queue.get_timestamp_period(),
+            window: window.max(1),
+            samples: Default::default(),
+        })
+    }
+
+    fn indices(stage: Stage) -> (u32, u32) {
+        let i = Stage::ALL.iter().position(|&s| s == stage).unwrap() as u32;
+        (2 * i, 2 * i + 1)
+    }
+
+    /// Take a staging buffer for one resolve, or None if all of them are in flight
+    pub fn begin(&mut self, device: &wgpu::Device) -> Option<wgpu::Buffer> {
+        if let Some(buffer) = self.free.pop() {
+            return Some(buffer);
+        }
+        if self.num_buffers == MAX_BUFFERS {
+            return None;
+        }
+        self.num_buffers += 1;
+        Some(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Readback"),
+            size: self.resolve_buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }))
+    }
+
+    pub fn compute_writes(&self, stage: Stage) -> wgpu::ComputePassTimestampWrites<'_> {
+        let (start, end) = Self::indices(stage);
+        wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(start),
+            end_of_pass_write_index: Some(end),
+        }
+    }
+
+    pub fn render_writes(&self, stage: Stage) -> wgpu::RenderPassTimestampWrites<'_> {
+        let (start, end) = Self::indices(stage);
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(start),
+            end_of_pass_write_index: Some(end),
+        }
+    }
+
+    /// Copy the timestamps of `stages` into `buffer` from [`GpuProfiler::begin`], after the
+    /// passes that wrote them
+    pub fn resolve(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        buffer: wgpu::Buffer,
+        stages: Range<usize>,
+    ) {
+        let queries = 2 * stages.start as u32..2 * stages.end as u32;
+        let offset = queries.start as u64 * QUERY_SIZE;
+        let size = queries.len() as u64 * QUERY_SIZE;
+        encoder.resolve_query_set(&self.query_set, queries, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &buffer, offset, size);
+        self.encoded.push(Slot {
+            buffer,
+            stages,
+            mapped: Arc::new(AtomicBool::new(false)),
+        });
+    }
+
+    /// Start reading back everything resolved so far and record the samples that are ready.
+    /// Call after submitting the commands that resolved them.
+    pub fn collect(&mut self, device: &wgpu::Device) {
+        for slot in self.encoded.drain(..) {
+            let mapped = slot.mapped.clone();
+            slot.buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |r| {
+                    if r.is_ok() {
+                        mapped.store(true, Ordering::Release);
+                    }
+                });
+            self.pending.push(slot);
+        }
+        let _ = device.poll(wgpu::PollType::Poll);
+
+        let (ready, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|slot| slot.mapped.load(Ordering::Acquire));
+        self.pending = pending;
+        for slot in ready {
+            {
+                let data = slot.buffer.slice(..).get_mapped_range();
+                let ticks = bytemuck::cast_slice::<_, u64>(&data);
+                for i in slot.stages.clone() {
+                    let elapsed = ticks[2 * i + 1].saturating_sub(ticks[2 * i]);
+                    let samples = &mut self.samples[i];
+                    if samples.len() == self.window {
+                        samples.pop_front();
+                    }
+                    samples.push_back(elapsed as f32 * self.period / 1e6);
+                }
+            }
+            slot.buffer.unmap();
+            self.free.push(slot.buffer);
+        }
+    }
+
+    /// Newest sample of a stage
+    pub fn last_ms(&self, stage: Stage) -> Option<f32> {
+        let i = Stage::ALL.iter().position(|&s| s == stage).unwrap();
+        self.samples[i].back().copied()
+    }
+
+    /// Per stage timings over the window, skipping stages with no samples
+    pub fn summary(&self) -> ProfileSummary {
+        let stages = Stage::ALL
+            .iter()
+            .zip(&self.samples)
+            .filter(|(_, s)| !s.is_empty())
+            .map(|(&stage, s)| StageTiming {
+                stage,
+                samples: s.len(),
+                mean_ms: s.iter().sum::<f32>() / s.len() as f32,
+                min_ms: s.iter().copied().fold(f32::INFINITY, f32::min),
+                max_ms: s.iter().copied().fold(0.0, f32::max),
+            })
+            .collect();
+        ProfileSummary { stages }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StageTiming {
+    pub stage: Stage,
+    pub samples: usize,
+    pub mean_ms: f32,
+    pub min_ms: f32,
+    pub max_ms: f32,
+}
+
+/// Stage timings over the profiler's rolling window
+#[derive(Clone, Debug, Default)]
+pub struct ProfileSummary {
+    pub stages: Vec<StageTiming>,
+}
+
+impl ProfileSummary {
+    pub fn to_json(&self) -> serde_json::Value {
+        self.stages
+            .iter()
+            .map(|t| {
+                let timing = serde_json::json!({
+                    "samples": t.samples,
+                    "mean_ms": t.mean_ms,
+                    "min_ms": t.min_ms,
+                    "max_ms": t.max_ms,
+                });
+                (t.stage.name().to_string(), timing)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+impl fmt::Display for ProfileSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.stages.is_empty() {
+            return write!(f, "no samples yet");
+        }
+        for (i, t) in self.stages.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{:<8} {:>8.3} ms (min {:.3}, max {:.3}, n={})",
+                t.stage.name(),
+                t.mean_ms,
+                t.min_ms,
+                t.max_ms,
+                t.samples
+            )?;
+        }
+        Ok(())
+    }
+}
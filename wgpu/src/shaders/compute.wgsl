@@ -6,15 +6,53 @@ struct Params {
     aoe: f32,
     aoe2: f32,
     damping: f32,
-    bin_size: f32,
+    bin_size: vec2f,
     grid_w: u32,
+    grid_h: u32,
+    num_obstacles: u32,
+    mask_w: u32,
+    mask_h: u32,
+    mask_cell_size: f32,
+    attractor_strength: f32,
+    dt: f32,
+    gravity: vec2f,
+    attractor: vec2f,
+    vortex: vec2f,
+    vortex_strength: f32,
+    flow_strength: f32,
+    flow_scale: f32,
+    flow_speed: f32,
+    temperature: f32,
+    seed: u32,
+    repulsion_radius: f32,
+    repulsion_strength: f32,
+    // 0 for no limit
+    max_force: f32,
+    max_speed: f32,
 }
 
 struct Particle {
     pos: vec2f,
     vel: vec2f,
+    culture: u32,
+    // Index in the initial spawn order, kept when particles are reordered
+    id: u32,
 };
 
+struct Cursor {
+    pos: vec2f,
+    radius: f32,
+    // Positive attracts, negative repels, 0 when the cursor is inactive
+    strength: f32,
+}
+
+struct Obstacle {
+    a: vec2f,
+    b: vec2f,
+    radius: f32,
+    _pad: f32,
+}
+
 @group(0) @binding(0)
 var<uniform> params: Params;
 @group(0) @binding(1)
@@ -29,10 +67,25 @@ var<storage, read_write> bin_offsets: array<u32>;
 var<storage, read_write> bin_current: array<atomic<u32>>;
 @group(0) @binding(6)
 var<storage, read_write> bins: array<u32>;
+@group(0) @binding(7)
+var<storage, read> obstacles: array<Obstacle>;
+@group(0) @binding(8)
+var<storage, read> mask: array<u32>;
+@group(0) @binding(9)
+var<storage, read_write> step_count: u32;
+@group(0) @binding(10)
+var<storage, read_write> block_sums: array<u32>;
+// Debug counters of out of range and non-finite particles, accumulated until the host reads them
+@group(0) @binding(11)
+var<storage, read_write> validation: array<atomic<u32>, 2>;
+@group(0) @binding(12)
+var<uniform> cursor: Cursor;
 @group(1) @binding(0)
 var<storage, read> particles: array<Particle>;
 @group(1) @binding(1)
 var<storage, read_write> particles_out: array<Particle>;
+@group(1) @binding(2)
+var<storage, read_write> sorted: array<Particle>;
 
 @compute @workgroup_size(64)
 fn compute_bin_ixs_and_counts(@builtin(global_invocation_id) gid: vec3u) {
@@ -41,25 +94,135 @@ fn compute_bin_ixs_and_counts(@builtin(global_invocation_id) gid: vec3u) {
 
     // Compute ix
     let p = particles[i];
-    let bx = u32(p.pos.x / params.bin_size);
-    let by = u32(p.pos.y / params.bin_size);
-    let bi = by * params.grid_w + bx;
+    // Non-finite positions go in the first bin, and positions outside the grid (including
+    // exactly on the far bound) in the nearest edge bin
+    let pos = select(vec2f(0.0), p.pos, is_finite2(p.pos));
+    let last = vec2f(f32(params.grid_w - 1u), f32(params.grid_h - 1u));
+    let cell = clamp(floor(pos / params.bin_size), vec2f(0.0), last);
+    let bi = u32(cell.y) * params.grid_w + u32(cell.x);
     bin_ixs[i] = bi;
 
     // Inc count
     atomicAdd(&bin_counts[bi], 1u);
 }
 
-@compute @workgroup_size(1)
-fn compute_bin_offsets() {
-    let n = params.grid_w * params.grid_w;
+// Bins scanned per workgroup. Each of the SCAN_WG invocations serially scans SCAN_ITEMS
+// consecutive bins, and the per-invocation sums are combined with a Blelloch scan. Keeping the
+// workgroup scan small keeps the barrier count low.
+const SCAN_WG: u32 = 64u;
+const SCAN_ITEMS: u32 = 64u;
+const SCAN_BLOCK: u32 = 4096u;
+
+var<workgroup> scan_tmp: array<u32, SCAN_WG>;
+var<workgroup> scan_total: u32;
+
+fn num_bins() -> u32 {
+    return params.grid_w * params.grid_h;
+}
+
+fn num_scan_blocks() -> u32 {
+    return (num_bins() + SCAN_BLOCK - 1u) / SCAN_BLOCK;
+}
+
+// Work-efficient (Blelloch) exclusive scan of scan_tmp in place, leaving the total in
+// scan_total. Called by all SCAN_WG invocations of a workgroup.
+fn blelloch_scan(lid: u32) {
+    var offset = 1u;
+    for (var d = SCAN_WG >> 1u; d > 0u; d >>= 1u) {
+        workgroupBarrier();
+        if lid < d {
+            let ai = offset * (2u * lid + 1u) - 1u;
+            let bi = offset * (2u * lid + 2u) - 1u;
+            scan_tmp[bi] += scan_tmp[ai];
+        }
+        offset <<= 1u;
+    }
+
+    workgroupBarrier();
+    if lid == 0u {
+        scan_total = scan_tmp[SCAN_WG - 1u];
+        scan_tmp[SCAN_WG - 1u] = 0u;
+    }
+
+    for (var d = 1u; d < SCAN_WG; d <<= 1u) {
+        offset >>= 1u;
+        workgroupBarrier();
+        if lid < d {
+            let ai = offset * (2u * lid + 1u) - 1u;
+            let bi = offset * (2u * lid + 2u) - 1u;
+            let t = scan_tmp[ai];
+            scan_tmp[ai] = scan_tmp[bi];
+            scan_tmp[bi] += t;
+        }
+    }
+    workgroupBarrier();
+}
+
+// Pass 1: exclusive scan of the bin counts within each block of SCAN_BLOCK bins
+@compute @workgroup_size(64)
+fn scan_bin_blocks(
+    @builtin(local_invocation_id) lid3: vec3u,
+    @builtin(workgroup_id) wid: vec3u,
+) {
+    let lid = lid3.x;
+    let n = num_bins();
+    let start = wid.x * SCAN_BLOCK + lid * SCAN_ITEMS;
+    let end = min(start + SCAN_ITEMS, n);
+
     var sum = 0u;
-    for (var i = 0u; i < n; i++) {
+    for (var i = start; i < end; i++) {
+        sum += atomicLoad(&bin_counts[i]);
+    }
+    scan_tmp[lid] = sum;
+
+    blelloch_scan(lid);
+
+    sum = scan_tmp[lid];
+    for (var i = start; i < end; i++) {
         bin_offsets[i] = sum;
-        atomicStore(&bin_current[i], sum);
         sum += atomicLoad(&bin_counts[i]);
     }
-    bin_offsets[n] = sum;
+    if lid == 0u {
+        block_sums[wid.x] = scan_total;
+    }
+}
+
+// Pass 2: exclusive scan of the block totals in a single workgroup, each invocation covering a
+// chunk of blocks so any number of bins fits. The grand total goes in the last entry.
+@compute @workgroup_size(64)
+fn scan_block_sums(@builtin(local_invocation_id) lid3: vec3u) {
+    let lid = lid3.x;
+    let nb = num_scan_blocks();
+    let chunk = (nb + SCAN_WG - 1u) / SCAN_WG;
+    let start = lid * chunk;
+    let end = min(start + chunk, nb);
+
+    var sum = 0u;
+    for (var b = start; b < end; b++) {
+        sum += block_sums[b];
+    }
+    scan_tmp[lid] = sum;
+
+    blelloch_scan(lid);
+
+    sum = scan_tmp[lid];
+    for (var b = start; b < end; b++) {
+        let v = block_sums[b];
+        block_sums[b] = sum;
+        sum += v;
+    }
+    if lid == 0u {
+        block_sums[nb] = scan_total;
+    }
+}
+
+// Start of bin b in bins. The scan leaves offsets relative to their block, so the block's offset
+// is added here rather than in a separate pass. Bin num_bins() starts at the total.
+fn bin_start(b: u32) -> u32 {
+    if b >= num_bins() {
+        return block_sums[num_scan_blocks()];
+    }
+    return bin_offsets[b] + block_sums[b / SCAN_BLOCK];
 }
 
 @compute @workgroup_size(64)
@@ -67,52 +230,105 @@ fn build_bin(@builtin(global_invocation_id) gid: vec3u) {
     let i = gid.x;
     if i >= params.num_particles { return; }
     let bi = bin_ixs[i];
-    let o = atomicAdd(&bin_current[bi], 1u);
+    let o = bin_start(bi) + atomicAdd(&bin_current[bi], 1u);
     bins[o] = i;
 }
 
+// Copy particles into bin order so the sorted force pass reads neighbours from contiguous memory
 @compute @workgroup_size(64)
-fn compute_force(@builtin(global_invocation_id) gid: vec3u) {
+fn reorder_particles(@builtin(global_invocation_id) gid: vec3u) {
     let i = gid.x;
     if i >= params.num_particles { return; }
+    sorted[i] = particles[bins[i]];
+}
 
-    let p1 = particles[i];
-    let c = (i / params.culture_size) * params.num_cultures;
+// Force the mesh and the hard-core repulsion exert on p1 from p2, the same law as
+// particle_life_core::sim::pair_force
+fn pair_force(p1: Particle, p2: Particle) -> vec2f {
+    let d = p2.pos - p1.pos;
+    let d2 = dot(d, d);
+    // Coincident particles have no direction to push along
+    if d2 > 0.0 && d2 <= params.aoe2 {
+        let g = gravity_mesh[p1.culture * params.num_cultures + p2.culture];
+        var force = normalize(d) * g;
+        let r = params.repulsion_radius;
+        if d2 < r * r {
+            let dist = sqrt(d2);
+            force -= d / dist * params.repulsion_strength * (1.0 - dist / r);
+        }
+        return force;
+    }
+    return vec2f(0.0);
+}
 
+// Range of bins to visit around bin bi, clamped to the grid
+fn neighbour_bins(bi: u32) -> vec4i {
     let gw = i32(params.grid_w);
-    let bi = i32(bin_ixs[i]);
-    let bx = bi % gw;
-    let by = bi / gw;
+    let gh = i32(params.grid_h);
+    let bx = i32(bi) % gw;
+    let by = i32(bi) / gw;
+    return vec4i(max(bx - 1, 0), max(by - 1, 0), min(bx + 1, gw - 1), min(by + 1, gh - 1));
+}
 
-    var force = vec2f(0.0);
+@compute @workgroup_size(64)
+fn compute_force(@builtin(global_invocation_id) gid: vec3u) {
+    let i = gid.x;
+    if i >= params.num_particles { return; }
 
-    for (var dy = -1i; dy <= 1; dy++) {
-        for (var dx = -1i; dx <= 1; dx++) {
-            let lbx = bx + dx;
-            let lby = by + dy;
-            if lbx < 0 || lby < 0 || lbx >= gw || lby >= gw {
-                continue;
-            }
-            let lbi = u32(bi + dy * gw + dx);
-            let bs = bin_offsets[lbi];
-            let be = bin_offsets[lbi+1];
+    let p1 = particles[i];
+    let r = neighbour_bins(bin_ixs[i]);
+    var force = vec2f(0.0);
 
-            for (var b = bs; b < be; b++) {
+    for (var by = r.y; by <= r.w; by++) {
+        for (var bx = r.x; bx <= r.z; bx++) {
+            let lbi = u32(by) * params.grid_w + u32(bx);
+            let be = bin_start(lbi + 1u);
+            for (var b = bin_start(lbi); b < be; b++) {
                 let j = bins[b];
                 if i == j { continue; }
-                let p2 = particles[j];
-                let d = p2.pos - p1.pos;
-                let d2 = dot(d, d);
-                if d2 < params.aoe2 {
-                    let g = gravity_mesh[c + j / params.culture_size];
-                    force += normalize(d) * g;
-                }
+                force += pair_force(p1, particles[j]);
+            }
+        }
+    }
+
+    particles_out[i] = integrate(p1, force);
+}
+
+// Same as compute_force on particles reordered by reorder_particles. The output stays in bin
+// order, so the next step's input is already mostly sorted.
+@compute @workgroup_size(64)
+fn compute_force_sorted(@builtin(global_invocation_id) gid: vec3u) {
+    let i = gid.x;
+    if i >= params.num_particles { return; }
+
+    let p1 = sorted[i];
+    let r = neighbour_bins(bin_ixs[bins[i]]);
+    var force = vec2f(0.0);
+
+    for (var by = r.y; by <= r.w; by++) {
+        for (var bx = r.x; bx <= r.z; bx++) {
+            let lbi = u32(by) * params.grid_w + u32(bx);
+            let be = bin_start(lbi + 1u);
+            for (var b = bin_start(lbi); b < be; b++) {
+                if i == b { continue; }
+                force += pair_force(p1, sorted[b]);
             }
         }
     }
 
-    var pos = p1.pos;
-    var vel = (p1.vel + force) * params.damping;
+    particles_out[i] = integrate(p1, force);
+}
+
+// Apply the mesh force and external forces to a particle and move it one step
+fn integrate(p: Particle, mesh_force: vec2f) -> Particle {
+    let force = mesh_force + cursor_force(p.pos) + field_force(p.pos, f32(step_count));
+
+    var pos = p.pos;
+    var vel = (p.vel + limit(force, params.max_force)) * params.damping;
+    if params.temperature > 0.0 {
+        vel += gaussian2(p.id, step_count) * sqrt(params.temperature);
+    }
+    vel = limit(vel, params.max_speed);
     var bound = params.bound;
 
     if pos.x <= 0.0 {
@@ -131,8 +347,180 @@ fn compute_force(@builtin(global_invocation_id) gid: vec3u) {
         pos.y = bound.y;
     }
 
-    pos += vel;
+    let prev = pos;
+    pos += vel * params.dt;
+    resolve_obstacles(prev, &pos, &vel);
+
+    return Particle(pos, vel, p.culture, p.id);
+}
 
-    particles_out[i].pos = pos;
-    particles_out[i].vel = vel;
+// Matches `Cursor::force` in core sim.rs
+fn cursor_force(pos: vec2f) -> vec2f {
+    let d = cursor.pos - pos;
+    let d2 = dot(d, d);
+    if d2 > 0.0 && d2 <= cursor.radius * cursor.radius {
+        return normalize(d) * cursor.strength;
+    }
+    return vec2f(0.0);
+}
+
+// Count particles that are non-finite or outside the bound
+@compute @workgroup_size(64)
+fn validate_particles(@builtin(global_invocation_id) gid: vec3u) {
+    let i = gid.x;
+    if i >= params.num_particles { return; }
+
+    let p = particles[i];
+    if !(is_finite2(p.pos) && is_finite2(p.vel)) {
+        atomicAdd(&validation[1], 1u);
+    } else if any(p.pos < vec2f(0.0)) || any(p.pos > params.bound) {
+        atomicAdd(&validation[0], 1u);
+    }
+}
+
+// Checks the exponent bits, since `x != x` may be optimized out
+fn is_finite2(v: vec2f) -> bool {
+    let e = bitcast<vec2u>(v) & vec2u(0x7f800000u);
+    return all(e != vec2u(0x7f800000u));
+}
+
+@compute @workgroup_size(1)
+fn advance_step() {
+    step_count += 1u;
+}
+
+// PCG hash, matches `pcg` in core field.rs
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Pair of standard normal samples from a per particle PCG stream keyed by id and step
+fn gaussian2(i: u32, step: u32) -> vec2f {
+    let s1 = pcg(params.seed ^ pcg(i ^ pcg(step)));
+    let s2 = pcg(s1);
+    let u1 = max(f32(s1) / 4294967295.0, 1e-7);
+    let u2 = f32(s2) / 4294967295.0;
+    let r = sqrt(-2.0 * log(u1));
+    let theta = 6.2831853 * u2;
+    return r * vec2f(cos(theta), sin(theta));
+}
+
+fn hash3(c: vec3i) -> f32 {
+    let h = pcg(u32(c.x) ^ pcg(u32(c.y) ^ pcg(u32(c.z))));
+    return f32(h) / 4294967295.0 * 2.0 - 1.0;
+}
+
+// Smoothly interpolated lattice noise in [-1, 1]
+fn value_noise(p: vec3f) -> f32 {
+    let i = vec3i(floor(p));
+    let f = fract(p);
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    let x00 = mix(hash3(i), hash3(i + vec3i(1, 0, 0)), u.x);
+    let x10 = mix(hash3(i + vec3i(0, 1, 0)), hash3(i + vec3i(1, 1, 0)), u.x);
+    let x01 = mix(hash3(i + vec3i(0, 0, 1)), hash3(i + vec3i(1, 0, 1)), u.x);
+    let x11 = mix(hash3(i + vec3i(0, 1, 1)), hash3(i + vec3i(1, 1, 1)), u.x);
+    return mix(mix(x00, x10, u.y), mix(x01, x11, u.y), u.z);
+}
+
+// Clamp the length of v to max, 0 meaning no limit
+fn limit(v: vec2f, max_len: f32) -> vec2f {
+    let len2 = dot(v, v);
+    if max_len > 0.0 && len2 > max_len * max_len {
+        return v * (max_len / sqrt(len2));
+    }
+    return v;
+}
+
+fn safe_normalize(v: vec2f) -> vec2f {
+    let l2 = dot(v, v);
+    if l2 > 0.0 {
+        return v / sqrt(l2);
+    }
+    return vec2f(0.0);
+}
+
+// External force on a particle at pos at step t
+fn field_force(pos: vec2f, t: f32) -> vec2f {
+    var force = params.gravity;
+
+    if params.attractor_strength != 0.0 {
+        force += safe_normalize(params.attractor - pos) * params.attractor_strength;
+    }
+
+    if params.vortex_strength != 0.0 {
+        let dir = safe_normalize(params.vortex - pos);
+        force += vec2f(-dir.y, dir.x) * params.vortex_strength;
+    }
+
+    if params.flow_strength != 0.0 {
+        let eps = 0.01;
+        let p = vec3f(pos * params.flow_scale, t * params.flow_speed);
+        let dx = value_noise(p + vec3f(eps, 0.0, 0.0)) - value_noise(p - vec3f(eps, 0.0, 0.0));
+        let dy = value_noise(p + vec3f(0.0, eps, 0.0)) - value_noise(p - vec3f(0.0, eps, 0.0));
+        force += vec2f(dy, -dx) / (2.0 * eps) * params.flow_strength;
+    }
+
+    return force;
+}
+
+fn closest_point(o: Obstacle, p: vec2f) -> vec2f {
+    let ab = o.b - o.a;
+    let len2 = dot(ab, ab);
+    if len2 == 0.0 { return o.a; }
+    let t = clamp(dot(p - o.a, ab) / len2, 0.0, 1.0);
+    return o.a + ab * t;
+}
+
+fn push_out(center: vec2f, radius: f32, prev: vec2f, pos: ptr<function, vec2f>, vel: ptr<function, vec2f>) {
+    let d = *pos - center;
+    let d2 = dot(d, d);
+    if d2 >= radius * radius { return; }
+
+    var n = vec2f(1.0, 0.0);
+    let pd = prev - center;
+    if d2 > 0.0 {
+        n = d / sqrt(d2);
+    } else if dot(pd, pd) > 0.0 {
+        n = normalize(pd);
+    }
+
+    *pos = center + n * radius;
+    let vn = dot(*vel, n);
+    if vn < 0.0 {
+        *vel -= 2.0 * vn * n;
+    }
+}
+
+fn mask_occupied(p: vec2f) -> bool {
+    let c = floor(p / params.mask_cell_size);
+    if c.x < 0.0 || c.y < 0.0 || c.x >= f32(params.mask_w) || c.y >= f32(params.mask_h) {
+        return false;
+    }
+    return mask[u32(c.y) * params.mask_w + u32(c.x)] != 0u;
+}
+
+// Push a particle that moved from prev into pos back out of any obstacle and reflect the normal
+// component of its velocity
+fn resolve_obstacles(prev: vec2f, pos: ptr<function, vec2f>, vel: ptr<function, vec2f>) {
+    for (var o = 0u; o < params.num_obstacles; o++) {
+        let obstacle = obstacles[o];
+        push_out(closest_point(obstacle, *pos), obstacle.radius, prev, pos, vel);
+    }
+
+    if params.mask_w == 0u || !mask_occupied(*pos) || mask_occupied(prev) {
+        return;
+    }
+    let blocked_x = mask_occupied(vec2f((*pos).x, prev.y));
+    let blocked_y = mask_occupied(vec2f(prev.x, (*pos).y));
+    if blocked_x || !blocked_y {
+        (*vel).x = -(*vel).x;
+        (*pos).x = prev.x;
+    }
+    if blocked_y || !blocked_x {
+        (*vel).y = -(*vel).y;
+        (*pos).y = prev.y;
+    }
 }
@@ -2,13 +2,45 @@ struct Params {
     bound: vec2f,
     num_cultures: u32,
     culture_size: u32,
+    num_particles: u32,
     aoe: f32,
     aoe2: f32,
     damping: f32,
+    bin_size: vec2f,
+    grid_w: u32,
+    grid_h: u32,
+    num_obstacles: u32,
+    mask_w: u32,
+    mask_h: u32,
+    mask_cell_size: f32,
+    attractor_strength: f32,
+    dt: f32,
+    gravity: vec2f,
+    attractor: vec2f,
+    vortex: vec2f,
+    vortex_strength: f32,
+    flow_strength: f32,
+    flow_scale: f32,
+    flow_speed: f32,
+    temperature: f32,
+    seed: u32,
+    repulsion_radius: f32,
+    repulsion_strength: f32,
+    // 0 for no limit
+    max_force: f32,
+    max_speed: f32,
+}
+
+struct Obstacle {
+    a: vec2f,
+    b: vec2f,
+    radius: f32,
+    _pad: f32,
 }
 
 struct VInput {
     @location(0) pos: vec2f,
+    @location(1) culture: u32,
 }
 
 struct VOutput {
@@ -17,10 +49,22 @@ struct VOutput {
     @location(1) local_pos: vec2f,
 }
 
+struct ObstacleOutput {
+    @builtin(position) clip_position: vec4f,
+    @location(0) world_pos: vec2f,
+}
+
 @group(0) @binding(0)
 var<uniform> params: Params;
 @group(0) @binding(1)
 var<storage, read> colors: array<vec4f>;
+@group(0) @binding(2)
+var<storage, read> obstacles: array<Obstacle>;
+@group(0) @binding(3)
+var<storage, read> mask: array<u32>;
+// Scales the world in NDC so it keeps its aspect ratio in the window
+@group(0) @binding(4)
+var<uniform> view_scale: vec2f;
 
 const QUAD = array(
     vec2f(-1, -1),
@@ -34,16 +78,15 @@ const QUAD = array(
 @vertex
 fn vs_main(
     vert: VInput,
-    @builtin(instance_index) i: u32,
     @builtin(vertex_index) vi: u32,
 ) -> VOutput {
     let ndc = vec2f(
         vert.pos.x / params.bound.x * 2.0 - 1.0,
         vert.pos.y / params.bound.y * 2.0 - 1.0
     );
-    let pos = ndc + QUAD[vi] * 0.002;
+    let pos = (ndc + QUAD[vi] * 0.002) * view_scale;
     var out: VOutput;
-    out.color = colors[i / params.culture_size];
+    out.color = colors[vert.culture];
     out.clip_position = vec4(pos, 0.0, 1.0);
     out.local_pos = QUAD[vi];
     return out;
@@ -60,3 +103,42 @@ fn fs_main(in: VOutput) -> @location(0) vec4f {
     let total = core + glow;
     return vec4f(in.color.rgb, total);
 }
+
+@vertex
+fn vs_obstacles(@builtin(vertex_index) vi: u32) -> ObstacleOutput {
+    var out: ObstacleOutput;
+    out.clip_position = vec4(QUAD[vi] * view_scale, 0.0, 1.0);
+    out.world_pos = (QUAD[vi] + 1.0) * 0.5 * params.bound;
+    return out;
+}
+
+@fragment
+fn fs_obstacles(in: ObstacleOutput) -> @location(0) vec4f {
+    let p = in.world_pos;
+    var hit = false;
+
+    for (var o = 0u; o < params.num_obstacles; o++) {
+        let obstacle = obstacles[o];
+        let ab = obstacle.b - obstacle.a;
+        let len2 = dot(ab, ab);
+        var t = 0.0;
+        if len2 > 0.0 {
+            t = clamp(dot(p - obstacle.a, ab) / len2, 0.0, 1.0);
+        }
+        if distance(p, obstacle.a + ab * t) <= obstacle.radius {
+            hit = true;
+        }
+    }
+
+    if params.mask_w > 0u {
+        let c = vec2u(floor(p / params.mask_cell_size));
+        if c.x < params.mask_w && c.y < params.mask_h && mask[c.y * params.mask_w + c.x] != 0u {
+            hit = true;
+        }
+    }
+
+    if !hit {
+        discard;
+    }
+    return vec4f(0.3, 0.3, 0.3, 1.0);
+}
@@ -0,0 +1,239 @@
+// Per culture observables: each particle's nearest neighbour of its culture is found with the
+// bins built by compute.wgsl, then each chunk of particles is summed per culture and the chunk
+// sums are combined per culture.
+
+struct Params {
+    bound: vec2f,
+    num_cultures: u32,
+    culture_size: u32,
+    num_particles: u32,
+    aoe: f32,
+    aoe2: f32,
+    damping: f32,
+    bin_size: vec2f,
+    grid_w: u32,
+    grid_h: u32,
+    num_obstacles: u32,
+    mask_w: u32,
+    mask_h: u32,
+    mask_cell_size: f32,
+    attractor_strength: f32,
+    dt: f32,
+    gravity: vec2f,
+    attractor: vec2f,
+    vortex: vec2f,
+    vortex_strength: f32,
+    flow_strength: f32,
+    flow_scale: f32,
+    flow_speed: f32,
+    temperature: f32,
+    seed: u32,
+    repulsion_radius: f32,
+    repulsion_strength: f32,
+    // 0 for no limit
+    max_force: f32,
+    max_speed: f32,
+}
+
+struct Particle {
+    pos: vec2f,
+    vel: vec2f,
+    culture: u32,
+    // Index in the initial spawn order, kept when particles are reordered
+    id: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> particles: array<Particle>;
+@group(0) @binding(2)
+var<storage, read> bin_ixs: array<u32>;
+@group(0) @binding(3)
+var<storage, read> bins: array<u32>;
+@group(0) @binding(4)
+var<storage, read> bin_offsets: array<u32>;
+@group(0) @binding(5)
+var<storage, read> block_sums: array<u32>;
+// Distance to the nearest neighbour within the aoe, -1 when there is none
+@group(0) @binding(6)
+var<storage, read_write> nn: array<f32>;
+// STAT_FIELDS sums per culture, in StatSums field order
+@group(0) @binding(7)
+var<storage, read_write> sums: array<f32>;
+// The same per chunk of REDUCE_WG particles, written by reduce_chunks
+@group(0) @binding(8)
+var<storage, read_write> chunk_sums: array<f32>;
+
+// Same as compute.wgsl
+const SCAN_BLOCK: u32 = 4096u;
+
+fn num_bins() -> u32 {
+    return params.grid_w * params.grid_h;
+}
+
+fn num_scan_blocks() -> u32 {
+    return (num_bins() + SCAN_BLOCK - 1u) / SCAN_BLOCK;
+}
+
+fn bin_start(b: u32) -> u32 {
+    if b >= num_bins() {
+        return block_sums[num_scan_blocks()];
+    }
+    return bin_offsets[b] + block_sums[b / SCAN_BLOCK];
+}
+
+fn neighbour_bins(bi: u32) -> vec4i {
+    let gw = i32(params.grid_w);
+    let gh = i32(params.grid_h);
+    let bx = i32(bi) % gw;
+    let by = i32(bi) / gw;
+    return vec4i(max(bx - 1, 0), max(by - 1, 0), min(bx + 1, gw - 1), min(by + 1, gh - 1));
+}
+
+// Bins are at least aoe wide, so the neighbouring bins cover the aoe
+@compute @workgroup_size(64)
+fn nearest_neighbours(@builtin(global_invocation_id) gid: vec3u) {
+    let i = gid.x;
+    if i >= params.num_particles { return; }
+
+    let p1 = particles[i];
+    let r = neighbour_bins(bin_ixs[i]);
+    var best = params.aoe2;
+    var found = false;
+    for (var by = r.y; by <= r.w; by++) {
+        for (var bx = r.x; bx <= r.z; bx++) {
+            let lbi = u32(by) * params.grid_w + u32(bx);
+            let be = bin_start(lbi + 1u);
+            for (var b = bin_start(lbi); b < be; b++) {
+                let j = bins[b];
+                let p2 = particles[j];
+                if i == j || p2.culture != p1.culture { continue; }
+                let d = p2.pos - p1.pos;
+                let d2 = dot(d, d);
+                if d2 <= best {
+                    best = d2;
+                    found = true;
+                }
+            }
+        }
+    }
+    nn[i] = select(-1.0, sqrt(best), found);
+}
+
+const STAT_FIELDS: u32 = 10u;
+const REDUCE_WG: u32 = 256u;
+const NO_CULTURE: u32 = 0xffffffffu;
+
+var<workgroup> partial: array<array<f32, STAT_FIELDS>, REDUCE_WG>;
+var<workgroup> lowest: atomic<u32>;
+var<workgroup> current: u32;
+
+// Sum partial into partial[0]
+fn reduce_partial(lid: u32) {
+    for (var stride = REDUCE_WG / 2u; stride > 0u; stride >>= 1u) {
+        workgroupBarrier();
+        if lid < stride {
+            for (var k = 0u; k < STAT_FIELDS; k++) {
+                partial[lid][k] += partial[lid + stride][k];
+            }
+        }
+    }
+}
+
+// Dispatched with one workgroup per chunk of REDUCE_WG particles, zeroing the chunk's sums of
+// cultures it doesn't have. The cultures in a chunk are reduced one at a time, so the work grows
+// with the cultures each chunk has rather than all of them. Positions are summed relative to the
+// world center.
+@compute @workgroup_size(256)
+fn reduce_chunks(
+    @builtin(local_invocation_id) lid3: vec3u,
+    @builtin(workgroup_id) wid: vec3u,
+) {
+    let lid = lid3.x;
+    let i = wid.x * REDUCE_WG + lid;
+    let base = wid.x * params.num_cultures * STAT_FIELDS;
+    for (var k = lid; k < params.num_cultures * STAT_FIELDS; k += REDUCE_WG) {
+        chunk_sums[base + k] = 0.0;
+    }
+    storageBarrier();
+
+    var s: array<f32, STAT_FIELDS>;
+    var culture = NO_CULTURE;
+    if i < params.num_particles {
+        let p = particles[i];
+        let pos = p.pos - params.bound * 0.5;
+        let speed2 = dot(p.vel, p.vel);
+        s[0] = 1.0;
+        s[1] = pos.x;
+        s[2] = pos.y;
+        s[3] = dot(pos, pos);
+        s[4] = p.vel.x;
+        s[5] = p.vel.y;
+        s[6] = sqrt(speed2);
+        s[7] = speed2;
+        let d = nn[i];
+        if d >= 0.0 {
+            s[8] = d;
+            s[9] = 1.0;
+        }
+        culture = p.culture;
+    }
+
+    loop {
+        // The smallest culture in the chunk not reduced yet
+        if lid == 0u {
+            atomicStore(&lowest, NO_CULTURE);
+        }
+        workgroupBarrier();
+        atomicMin(&lowest, culture);
+        workgroupBarrier();
+        if lid == 0u {
+            current = atomicLoad(&lowest);
+        }
+        let c = workgroupUniformLoad(&current);
+        if c == NO_CULTURE {
+            break;
+        }
+
+        if culture == c {
+            partial[lid] = s;
+            culture = NO_CULTURE;
+        } else {
+            partial[lid] = array<f32, STAT_FIELDS>();
+        }
+        reduce_partial(lid);
+        if lid == 0u {
+            for (var k = 0u; k < STAT_FIELDS; k++) {
+                chunk_sums[base + c * STAT_FIELDS + k] = partial[0][k];
+            }
+        }
+    }
+}
+
+// Dispatched with one workgroup per culture after reduce_chunks
+@compute @workgroup_size(256)
+fn combine_chunks(
+    @builtin(local_invocation_id) lid3: vec3u,
+    @builtin(workgroup_id) wid: vec3u,
+) {
+    let lid = lid3.x;
+    let c = wid.x;
+    let num_chunks = (params.num_particles + REDUCE_WG - 1u) / REDUCE_WG;
+
+    var s: array<f32, STAT_FIELDS>;
+    for (var chunk = lid; chunk < num_chunks; chunk += REDUCE_WG) {
+        let o = (chunk * params.num_cultures + c) * STAT_FIELDS;
+        for (var k = 0u; k < STAT_FIELDS; k++) {
+            s[k] += chunk_sums[o + k];
+        }
+    }
+    partial[lid] = s;
+    reduce_partial(lid);
+
+    if lid == 0u {
+        for (var k = 0u; k < STAT_FIELDS; k++) {
+            sums[c * STAT_FIELDS + k] = partial[0][k];
+        }
+    }
+}
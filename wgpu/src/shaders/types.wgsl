@@ -6,11 +6,49 @@ struct Params {
     aoe: f32,
     aoe2: f32,
     damping: f32,
-    bin_size: f32,
+    bin_size: vec2f,
     grid_w: u32,
+    grid_h: u32,
+    num_obstacles: u32,
+    mask_w: u32,
+    mask_h: u32,
+    mask_cell_size: f32,
+    attractor_strength: f32,
+    dt: f32,
+    gravity: vec2f,
+    attractor: vec2f,
+    vortex: vec2f,
+    vortex_strength: f32,
+    flow_strength: f32,
+    flow_scale: f32,
+    flow_speed: f32,
+    temperature: f32,
+    seed: u32,
+    repulsion_radius: f32,
+    repulsion_strength: f32,
+    // 0 for no limit
+    max_force: f32,
+    max_speed: f32,
 }
 
 struct Particle {
     pos: vec2f,
     vel: vec2f,
+    culture: u32,
+    // Index in the initial spawn order, kept when particles are reordered
+    id: u32,
+}
+
+struct Cursor {
+    pos: vec2f,
+    radius: f32,
+    // Positive attracts, negative repels, 0 when the cursor is inactive
+    strength: f32,
+}
+
+struct Obstacle {
+    a: vec2f,
+    b: vec2f,
+    radius: f32,
+    _pad: f32,
 }
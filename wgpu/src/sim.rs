@@ -0,0 +1,1044 @@
+use std::fmt;
+
+use anyhow::Result;
+use rand::Rng;
+use wgpu::util::DeviceExt;
+
+use glam::{Vec2, vec2};
+use particle_life_core::{Particle, SimParams, Simulator, sim::Cursor, stats::Stats};
+
+use crate::{
+    obstacle::{gpu_mask, gpu_obstacles},
+    profiler::{GpuProfiler, Stage},
+    stats::{GpuStats, StatsInputs},
+};
+
+/// Request a device with the adapter's limits. The compute pass binds more storage buffers than
+/// the default limits allow. Timestamp queries are enabled when supported, for profiling.
+pub async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+    let desc = wgpu::DeviceDescriptor {
+        required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+        required_limits: adapter.limits(),
+        ..Default::default()
+    };
+    Ok(adapter.request_device(&desc).await?)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeadlessOptions {
+    pub power_preference: wgpu::PowerPreference,
+    /// Only use a software adapter such as lavapipe or llvmpipe. Otherwise a software adapter is
+    /// still used when no hardware adapter is available.
+    pub force_fallback_adapter: bool,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+        }
+    }
+}
+
+/// Request a device with no surface, for running the compute pipeline without a window
+pub fn headless_device(
+    opts: &HeadlessOptions,
+) -> Result<(wgpu::Device, wgpu::Queue, wgpu::AdapterInfo)> {
+    let instance = wgpu::Instance::new(&Default::default());
+    let request = |force_fallback_adapter| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: opts.power_preference,
+            force_fallback_adapter,
+            compatible_surface: None,
+        }))
+    };
+    let adapter = match request(opts.force_fallback_adapter) {
+        Ok(adapter) => adapter,
+        Err(_) if !opts.force_fallback_adapter => request(true)?,
+        Err(e) => return Err(e.into()),
+    };
+    let (device, queue) = pollster::block_on(request_device(&adapter))?;
+    Ok((device, queue, adapter.get_info()))
+}
+
+/// World size used when the params don't set a bound
+pub const DEFAULT_BOUND: Vec2 = vec2(1000.0, 1000.0);
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuParams {
+    pub bound: [f32; 2],
+    pub num_cultures: u32,
+    pub culture_size: u32,
+    pub num_particles: u32,
+    pub aoe: f32,
+    pub aoe2: f32,
+    pub damping: f32,
+    pub bin_size: [f32; 2],
+    pub grid_w: u32,
+    pub grid_h: u32,
+    pub num_obstacles: u32,
+    pub mask_w: u32,
+    pub mask_h: u32,
+    pub mask_cell_size: f32,
+    pub attractor_strength: f32,
+    /// Time step positions are integrated over
+    pub dt: f32,
+    pub gravity: [f32; 2],
+    pub attractor: [f32; 2],
+    pub vortex: [f32; 2],
+    pub vortex_strength: f32,
+    pub flow_strength: f32,
+    pub flow_scale: f32,
+    pub flow_speed: f32,
+    /// Variance of the gaussian velocity noise added to each particle every step
+    pub temperature: f32,
+    pub seed: u32,
+    pub repulsion_radius: f32,
+    pub repulsion_strength: f32,
+    pub max_force: f32,
+    pub max_speed: f32,
+}
+
+impl GpuParams {
+    pub fn new(simp: &SimParams) -> Self {
+        let SimParams {
+            num_cultures,
+            culture_size,
+            aoe,
+            damping,
+            ref obstacles,
+            ref fields,
+            temperature,
+            repulsion,
+            max_force,
+            max_speed,
+            ..
+        } = *simp;
+        let bound = simp.bound.unwrap_or(DEFAULT_BOUND);
+        let grid = (bound / (aoe * 2.0)).ceil();
+        let bin_size = bound / grid;
+        let (mask_w, mask_h, mask_cell_size) = match &obstacles.mask {
+            Some(mask) => (mask.width as u32, mask.height as u32, mask.cell_size),
+            None => (0, 0, 0.0),
+        };
+        Self {
+            bound: bound.to_array(),
+            num_cultures,
+            culture_size,
+            num_particles: num_cultures * culture_size,
+            aoe,
+            aoe2: aoe * aoe,
+            damping,
+            bin_size: bin_size.to_array(),
+            grid_w: grid.x as u32,
+            grid_h: grid.y as u32,
+            num_obstacles: (obstacles.circles.len() + obstacles.segments.len()) as u32,
+            mask_w,
+            mask_h,
+            mask_cell_size,
+            attractor_strength: fields.attractor.strength,
+            dt: 1.0,
+            gravity: fields.gravity.to_array(),
+            attractor: fields.attractor.center.to_array(),
+            vortex: fields.vortex.center.to_array(),
+            vortex_strength: fields.vortex.strength,
+            flow_strength: fields.flow.strength,
+            flow_scale: fields.flow.scale,
+            flow_speed: fields.flow.speed,
+            temperature,
+            seed: rand::rng().random(),
+            repulsion_radius: repulsion.radius,
+            repulsion_strength: repulsion.strength,
+            max_force,
+            max_speed,
+        }
+    }
+
+    pub fn num_bins(&self) -> u32 {
+        self.grid_w * self.grid_h
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuParticle {
+    pos: [f32; 2],
+    vel: [f32; 2],
+    culture: u32,
+    /// Index in the initial spawn order, kept when particles are reordered
+    id: u32,
+}
+
+impl GpuParticle {
+    pub fn vertex_layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::offset_of!(Self, culture) as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuCursor {
+    pos: [f32; 2],
+    radius: f32,
+    strength: f32,
+}
+
+impl From<Option<Cursor>> for GpuCursor {
+    fn from(cursor: Option<Cursor>) -> Self {
+        cursor.map_or_else(Self::default, |c| Self {
+            pos: c.pos.to_array(),
+            radius: c.radius,
+            strength: c.strength,
+        })
+    }
+}
+
+impl From<GpuParticle> for Particle {
+    fn from(p: GpuParticle) -> Self {
+        Self {
+            pos: p.pos.into(),
+            vel: p.vel.into(),
+        }
+    }
+}
+
+struct Pipelines {
+    group0_layout: wgpu::BindGroupLayout,
+    group1_layout: wgpu::BindGroupLayout,
+    count: wgpu::ComputePipeline,
+    scan_blocks: wgpu::ComputePipeline,
+    scan_block_sums: wgpu::ComputePipeline,
+    build: wgpu::ComputePipeline,
+    force: wgpu::ComputePipeline,
+    reorder: wgpu::ComputePipeline,
+    force_sorted: wgpu::ComputePipeline,
+    validate: wgpu::ComputePipeline,
+    advance: wgpu::ComputePipeline,
+}
+
+impl Pipelines {
+    fn new(device: &wgpu::Device) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let group0_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Compute Group 0 Layout"),
+            entries: &[
+                // params
+                uniform(0),
+                // gravity mesh
+                storage(1, true),
+                // bin counts
+                storage(2, false),
+                // bin ixs
+                storage(3, false),
+                // bin offsets
+                storage(4, false),
+                // bin current
+                storage(5, false),
+                // bins
+                storage(6, false),
+                // obstacles
+                storage(7, true),
+                // obstacle mask
+                storage(8, true),
+                // step count
+                storage(9, false),
+                // scan block sums
+                storage(10, false),
+                // validation counters
+                storage(11, false),
+                // cursor
+                uniform(12),
+            ],
+        });
+
+        let group1_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Group 1 Layout"),
+            entries: &[
+                // particles
+                storage(0, true),
+                // particles out
+                storage(1, false),
+                // particles sorted by bin
+                storage(2, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Compute Pipeline Layout"),
+            bind_group_layouts: &[&group0_layout, &group1_layout],
+            push_constant_ranges: &[],
+        });
+
+        let cshader = device.create_shader_module(wgpu::include_wgsl!("shaders/compute.wgsl"));
+
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &cshader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            count: pipeline("Count Bins", "compute_bin_ixs_and_counts"),
+            scan_blocks: pipeline("Scan Bin Blocks", "scan_bin_blocks"),
+            scan_block_sums: pipeline("Scan Block Sums", "scan_block_sums"),
+            build: pipeline("Build Bins", "build_bin"),
+            force: pipeline("Compute Forces", "compute_force"),
+            reorder: pipeline("Reorder Particles", "reorder_particles"),
+            force_sorted: pipeline("Compute Forces Sorted", "compute_force_sorted"),
+            validate: pipeline("Validate Particles", "validate_particles"),
+            advance: pipeline("Advance Step", "advance_step"),
+            group0_layout,
+            group1_layout,
+        }
+    }
+}
+
+/// Buffers and bind groups sized for one set of params
+struct Resources {
+    params_buffer: wgpu::Buffer,
+    gravity_mesh_buffer: wgpu::Buffer,
+    bin_counts_buffer: wgpu::Buffer,
+    bin_current_buffer: wgpu::Buffer,
+    obstacles_buffer: wgpu::Buffer,
+    mask_buffer: wgpu::Buffer,
+    validation_buffer: wgpu::Buffer,
+    cursor_buffer: wgpu::Buffer,
+    bin_ixs_buffer: wgpu::Buffer,
+    bins_buffer: wgpu::Buffer,
+    bin_offsets_buffer: wgpu::Buffer,
+    block_sums_buffer: wgpu::Buffer,
+    particle_buffers: [wgpu::Buffer; 2],
+    general_bind: wgpu::BindGroup,
+    particle_binds: [wgpu::BindGroup; 2],
+}
+
+impl Resources {
+    fn new(
+        device: &wgpu::Device,
+        pipelines: &Pipelines,
+        simp: &SimParams,
+        params: &GpuParams,
+        particles: &[GpuParticle],
+        cursor: GpuCursor,
+        step: u32,
+    ) -> Self {
+        let num_bins = params.num_bins() as usize;
+
+        use wgpu::BufferUsages as U;
+        let particle_buffer = || {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Particles"),
+                contents: bytemuck::cast_slice(particles),
+                usage: U::STORAGE | U::VERTEX | U::COPY_SRC,
+            })
+        };
+        let particle_buffers = [particle_buffer(), particle_buffer()];
+        let sorted_buffer = particle_buffer();
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Params"),
+            contents: bytemuck::bytes_of(params),
+            usage: U::UNIFORM | U::COPY_DST,
+        });
+        let gravity_mesh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Gravity Mesh"),
+            contents: bytemuck::cast_slice(simp.mesh.as_flat()),
+            usage: U::STORAGE | U::COPY_DST,
+        });
+        let bin_counts_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bin Counts"),
+            contents: bytemuck::cast_slice(&vec![0u32; num_bins]),
+            usage: U::STORAGE | U::COPY_DST,
+        });
+        let bin_ixs_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bin Indices"),
+            contents: bytemuck::cast_slice(&vec![0u32; particles.len().max(1)]),
+            usage: U::STORAGE,
+        });
+        let bin_offsets_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bin Offsets"),
+            contents: bytemuck::cast_slice(&vec![0u32; num_bins]),
+            usage: U::STORAGE,
+        });
+        let bin_current_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bin Current"),
+            contents: bytemuck::cast_slice(&vec![0u32; num_bins]),
+            usage: U::STORAGE | U::COPY_DST,
+        });
+        let bins_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bins"),
+            contents: bytemuck::cast_slice(&vec![0u32; particles.len().max(1)]),
+            usage: U::STORAGE,
+        });
+        // Storage buffers can't be empty, so pad with a zeroed entry when there are no obstacles
+        let mut gpu_obstacles = gpu_obstacles(&simp.obstacles);
+        if gpu_obstacles.is_empty() {
+            gpu_obstacles.push(bytemuck::Zeroable::zeroed());
+        }
+        let obstacles_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Obstacles"),
+            contents: bytemuck::cast_slice(&gpu_obstacles),
+            usage: U::STORAGE,
+        });
+        let mut gpu_mask = gpu_mask(&simp.obstacles);
+        if gpu_mask.is_empty() {
+            gpu_mask.push(0);
+        }
+        let mask_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Obstacle Mask"),
+            contents: bytemuck::cast_slice(&gpu_mask),
+            usage: U::STORAGE,
+        });
+        let block_sums_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Scan Block Sums"),
+            contents: bytemuck::cast_slice(&vec![0u32; num_scan_blocks(num_bins) + 1]),
+            usage: U::STORAGE,
+        });
+        let step_count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Step Count"),
+            contents: bytemuck::bytes_of(&step),
+            usage: U::STORAGE,
+        });
+
+        let validation_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Validation Counters"),
+            contents: bytemuck::bytes_of(&[0u32; 2]),
+            usage: U::STORAGE | U::COPY_SRC | U::COPY_DST,
+        });
+
+        let cursor_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cursor"),
+            contents: bytemuck::bytes_of(&cursor),
+            usage: U::UNIFORM | U::COPY_DST,
+        });
+
+        let general_bind = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Compute General Bind Group"),
+            layout: &pipelines.group0_layout,
+            entries: &[
+                params_buffer.as_entire_binding(),
+                gravity_mesh_buffer.as_entire_binding(),
+                bin_counts_buffer.as_entire_binding(),
+                bin_ixs_buffer.as_entire_binding(),
+                bin_offsets_buffer.as_entire_binding(),
+                bin_current_buffer.as_entire_binding(),
+                bins_buffer.as_entire_binding(),
+                obstacles_buffer.as_entire_binding(),
+                mask_buffer.as_entire_binding(),
+                step_count_buffer.as_entire_binding(),
+                block_sums_buffer.as_entire_binding(),
+                validation_buffer.as_entire_binding(),
+                cursor_buffer.as_entire_binding(),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect::<Vec<_>>(),
+        });
+
+        let particle_bind = |label, src: &wgpu::Buffer, dst: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &pipelines.group1_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: src.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: dst.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: sorted_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let particle_binds = [
+            particle_bind(
+                "Compute Particle Bind Group 1",
+                &particle_buffers[0],
+                &particle_buffers[1],
+            ),
+            particle_bind(
+                "Compute Particle Bind Group 2",
+                &particle_buffers[1],
+                &particle_buffers[0],
+            ),
+        ];
+
+        Self {
+            params_buffer,
+            gravity_mesh_buffer,
+            bin_counts_buffer,
+            bin_current_buffer,
+            obstacles_buffer,
+            mask_buffer,
+            validation_buffer,
+            cursor_buffer,
+            bin_ixs_buffer,
+            bins_buffer,
+            bin_offsets_buffer,
+            block_sums_buffer,
+            particle_buffers,
+            general_bind,
+            particle_binds,
+        }
+    }
+}
+
+/// Particle counts from the debug validation pass, summed over the steps since the last read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Finite particles outside the bound. A few are expected, since particles are only clamped
+    /// back the step after they cross it.
+    pub out_of_range: u32,
+    /// Particles with a NaN or infinite position or velocity
+    pub non_finite: u32,
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} out of range, {} non-finite",
+            self.out_of_range, self.non_finite
+        )
+    }
+}
+
+/// Spatially binned simulation running in compute shaders
+pub struct GpuSim {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    simp: SimParams,
+    params: GpuParams,
+    pipelines: Pipelines,
+    res: Resources,
+    /// Index of the particle buffer holding the current state
+    current: usize,
+    /// Physically reorder particles by bin before computing forces
+    sorted: bool,
+    /// Count bad particles every step, see [`GpuSim::read_validation`]
+    validate: bool,
+    cursor: GpuCursor,
+    profiler: Option<GpuProfiler>,
+    stats: Option<GpuStats>,
+    step: u64,
+    readback: Vec<Particle>,
+    readback_step: Option<u64>,
+}
+
+impl GpuSim {
+    pub fn new(device: wgpu::Device, queue: wgpu::Queue, simp: SimParams) -> Self {
+        let params = GpuParams::new(&simp);
+        let particles = spawn_particles(params.num_particles, params.bound);
+        Self::with_particles(device, queue, simp, particles)
+    }
+
+    /// Create a simulation starting from the given particles instead of a random spawn
+    pub fn with_particles(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        simp: SimParams,
+        particles: Vec<Particle>,
+    ) -> Self {
+        assert_eq!(particles.len(), simp.num_particles() as usize);
+        let params = GpuParams::new(&simp);
+        let pipelines = Pipelines::new(&device);
+        let gpu_particles = gpu_particles(&particles, simp.culture_size);
+        let cursor = GpuCursor::default();
+        let res = Resources::new(
+            &device,
+            &pipelines,
+            &simp,
+            &params,
+            &gpu_particles,
+            cursor,
+            0,
+        );
+        Self {
+            device,
+            queue,
+            simp,
+            params,
+            pipelines,
+            res,
+            current: 0,
+            sorted: false,
+            validate: false,
+            cursor,
+            profiler: None,
+            stats: None,
+            step: 0,
+            readback: particles,
+            readback_step: Some(0),
+        }
+    }
+
+    /// Create a simulation on its own device without a window
+    pub fn headless(simp: SimParams, opts: &HeadlessOptions) -> Result<Self> {
+        let (device, queue, _) = headless_device(opts)?;
+        Ok(Self::new(device, queue, simp))
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn gpu_params(&self) -> &GpuParams {
+        &self.params
+    }
+
+    pub fn params_buffer(&self) -> &wgpu::Buffer {
+        &self.res.params_buffer
+    }
+
+    pub fn obstacles_buffer(&self) -> &wgpu::Buffer {
+        &self.res.obstacles_buffer
+    }
+
+    pub fn mask_buffer(&self) -> &wgpu::Buffer {
+        &self.res.mask_buffer
+    }
+
+    /// Buffer holding the particles as of the last encoded step
+    pub fn particle_buffer(&self) -> &wgpu::Buffer {
+        &self.res.particle_buffers[self.current]
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    /// Toggle the reorder pass. Sorting particles by bin makes neighbour reads contiguous at the
+    /// cost of an extra copy per step.
+    pub fn set_sorted(&mut self, sorted: bool) {
+        self.sorted = sorted;
+    }
+
+    /// Toggle the debug validation pass, which counts out of range and non-finite particles at
+    /// the start of every step
+    pub fn set_validation(&mut self, validate: bool) {
+        self.validate = validate;
+    }
+
+    /// Set the cursor force applied from the next submitted step on
+    pub fn set_cursor(&mut self, cursor: Option<Cursor>) {
+        let cursor = GpuCursor::from(cursor);
+        if self.cursor != cursor {
+            self.cursor = cursor;
+            self.queue
+                .write_buffer(&self.res.cursor_buffer, 0, bytemuck::bytes_of(&cursor));
+        }
+    }
+
+    /// Toggle per stage GPU timestamps, averaged over the last `window` samples. Returns false,
+    /// leaving profiling off, when the device doesn't support timestamp queries.
+    pub fn set_profiling(&mut self, enabled: bool, window: usize) -> bool {
+        self.profiler = enabled
+            .then(|| GpuProfiler::new(&self.device, &self.queue, window))
+            .flatten();
+        self.profiler.is_some()
+    }
+
+    pub fn profiler(&self) -> Option<&GpuProfiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut GpuProfiler> {
+        self.profiler.as_mut()
+    }
+
+    /// Read back timestamps from submitted steps, see [`GpuProfiler::collect`]
+    pub fn collect_profile(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.collect(&self.device);
+        }
+    }
+
+    /// Reduce per culture stats on the GPU every `every` steps, or stop with None. Read them with
+    /// [`GpuSim::collect_stats`].
+    pub fn set_stats(&mut self, every: Option<u32>) {
+        self.stats = every.map(|every| GpuStats::new(&self.device, every));
+        self.bind_stats();
+    }
+
+    pub fn stats(&self) -> Option<&GpuStats> {
+        self.stats.as_ref()
+    }
+
+    /// Stats of the submitted stats steps that have been read back, see [`GpuStats::collect`]
+    pub fn collect_stats(&mut self, wait: bool) -> Vec<Stats> {
+        self.stats
+            .as_mut()
+            .map_or_else(Vec::new, |stats| stats.collect(&self.device, wait))
+    }
+
+    fn bind_stats(&mut self) {
+        let Some(stats) = &mut self.stats else {
+            return;
+        };
+        let res = &self.res;
+        let inputs = StatsInputs {
+            params: &res.params_buffer,
+            particles: [&res.particle_buffers[0], &res.particle_buffers[1]],
+            bin_ixs: &res.bin_ixs_buffer,
+            bins: &res.bins_buffer,
+            bin_offsets: &res.bin_offsets_buffer,
+            block_sums: &res.block_sums_buffer,
+        };
+        stats.bind(
+            &self.device,
+            inputs,
+            self.params.num_particles,
+            self.params.num_cultures,
+            Vec2::from(self.params.bound),
+        );
+    }
+
+    pub fn set_dt(&mut self, dt: f32) {
+        if self.params.dt != dt {
+            self.params.dt = dt;
+            self.queue
+                .write_buffer(&self.res.params_buffer, 0, bytemuck::bytes_of(&self.params));
+        }
+    }
+
+    /// Whether `set_params` with `simp` needs new buffers, which reads the particles back and
+    /// recreates every buffer and bind group. Other edits only rewrite the params and mesh.
+    pub fn resizes(&self, simp: &SimParams) -> bool {
+        let params = GpuParams::new(simp);
+        params.num_particles != self.params.num_particles
+            || params.num_cultures != self.params.num_cultures
+            || params.bound != self.params.bound
+            || params.grid_w != self.params.grid_w
+            || params.grid_h != self.params.grid_h
+            || simp.obstacles != self.simp.obstacles
+    }
+
+    /// Seed the velocity noise, which is seeded randomly otherwise
+    pub fn set_seed(&mut self, seed: u32) {
+        if self.params.seed != seed {
+            self.params.seed = seed;
+            self.queue
+                .write_buffer(&self.res.params_buffer, 0, bytemuck::bytes_of(&self.params));
+        }
+    }
+
+    /// Advance `n` steps in a single submission
+    pub fn step_n(&mut self, n: u32, dt: f32) {
+        self.set_dt(dt);
+        let cmds = (0..n).map(|_| self.compute()).collect::<Vec<_>>();
+        self.queue.submit(cmds);
+        self.collect_profile();
+    }
+
+    /// Block until all submitted steps have finished
+    pub fn wait(&self) {
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .expect("failed to poll device");
+    }
+
+    /// Encode one step. Several steps can be submitted together.
+    pub fn compute(&mut self) -> wgpu::CommandBuffer {
+        let c = &self.res;
+        let p = &self.pipelines;
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+
+        encoder.clear_buffer(&c.bin_counts_buffer, 0, None);
+        encoder.clear_buffer(&c.bin_current_buffer, 0, None);
+
+        let workgroup_count = self.params.num_particles.div_ceil(64);
+        let num_bins = self.params.num_bins() as usize;
+
+        // One pass per profiled stage
+        let profile = self
+            .profiler
+            .as_mut()
+            .and_then(|profiler| profiler.begin(&self.device));
+        let profiler = self.profiler.as_ref().filter(|_| profile.is_some());
+        let pass = |encoder: &mut wgpu::CommandEncoder, stage: Stage| {
+            let mut cpass = encoder
+                .begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some(stage.name()),
+                    timestamp_writes: profiler.map(|p| p.compute_writes(stage)),
+                })
+                .forget_lifetime();
+            cpass.set_bind_group(0, &c.general_bind, &[]);
+            cpass.set_bind_group(1, &c.particle_binds[self.current], &[]);
+            cpass
+        };
+
+        let mut cpass = pass(&mut encoder, Stage::Count);
+        if self.validate {
+            cpass.set_pipeline(&p.validate);
+            cpass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+        cpass.set_pipeline(&p.count);
+        cpass.dispatch_workgroups(workgroup_count, 1, 1);
+        drop(cpass);
+
+        let mut cpass = pass(&mut encoder, Stage::Offsets);
+        cpass.set_pipeline(&p.scan_blocks);
+        cpass.dispatch_workgroups(num_scan_blocks(num_bins) as u32, 1, 1);
+        cpass.set_pipeline(&p.scan_block_sums);
+        cpass.dispatch_workgroups(1, 1, 1);
+        drop(cpass);
+
+        let mut cpass = pass(&mut encoder, Stage::Build);
+        cpass.set_pipeline(&p.build);
+        cpass.dispatch_workgroups(workgroup_count, 1, 1);
+        drop(cpass);
+
+        // The bins refer to the particles this step starts from
+        if let Some(stats) = &mut self.stats {
+            stats.encode(&self.device, &mut encoder, self.current, self.step);
+        }
+
+        let mut cpass = pass(&mut encoder, Stage::Force);
+        if self.sorted {
+            cpass.set_pipeline(&p.reorder);
+            cpass.dispatch_workgroups(workgroup_count, 1, 1);
+
+            cpass.set_pipeline(&p.force_sorted);
+            cpass.dispatch_workgroups(workgroup_count, 1, 1);
+        } else {
+            cpass.set_pipeline(&p.force);
+            cpass.dispatch_workgroups(workgroup_count, 1, 1);
+        }
+        cpass.set_pipeline(&p.advance);
+        cpass.dispatch_workgroups(1, 1, 1);
+        drop(cpass);
+
+        if let (Some(profiler), Some(buffer)) = (self.profiler.as_mut(), profile) {
+            profiler.resolve(&mut encoder, buffer, Stage::COMPUTE);
+        }
+
+        self.current = 1 - self.current;
+        self.step += 1;
+
+        encoder.finish()
+    }
+
+    /// Respawn particles at random positions and restart the step count. Like
+    /// [`Simulator::set_params`], this recreates every buffer.
+    pub fn respawn(&mut self) {
+        self.step = 0;
+        self.upload(spawn_particles(
+            self.params.num_particles,
+            self.params.bound,
+        ));
+    }
+
+    /// Recreate the buffers for the current params, starting from `particles`
+    fn upload(&mut self, particles: Vec<Particle>) {
+        self.res = Resources::new(
+            &self.device,
+            &self.pipelines,
+            &self.simp,
+            &self.params,
+            &gpu_particles(&particles, self.simp.culture_size),
+            self.cursor,
+            self.step as u32,
+        );
+        self.current = 0;
+        self.bind_stats();
+        self.readback = particles;
+        self.readback_step = Some(self.step);
+    }
+
+    /// Copy the current particles back to the host in spawn order, blocking until the GPU is done
+    pub fn read_particles(&self) -> Vec<Particle> {
+        let data = self.read_buffer(self.particle_buffer(), |_| ());
+        let mut particles = vec![Particle::default(); self.params.num_particles as usize];
+        for &p in bytemuck::cast_slice::<_, GpuParticle>(&data)
+            .iter()
+            .take(particles.len())
+        {
+            particles[p.id as usize] = p.into();
+        }
+        particles
+    }
+
+    /// Counts from the validation pass since the last read, blocking until the GPU is done
+    pub fn read_validation(&self) -> ValidationReport {
+        let buffer = &self.res.validation_buffer;
+        let data = self.read_buffer(buffer, |encoder| encoder.clear_buffer(buffer, 0, None));
+        let [out_of_range, non_finite] = *bytemuck::from_bytes::<[u32; 2]>(&data);
+        ValidationReport {
+            out_of_range,
+            non_finite,
+        }
+    }
+
+    /// Copy a buffer to the host, encoding `then` after the copy
+    fn read_buffer(
+        &self,
+        src: &wgpu::Buffer,
+        then: impl FnOnce(&mut wgpu::CommandEncoder),
+    ) -> Vec<u8> {
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback"),
+            size: src.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(src, 0, &staging, 0, src.size());
+        then(&mut encoder);
+        self.queue.submit([encoder.finish()]);
+
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |r| {
+            r.expect("failed to map readback buffer")
+        });
+        self.wait();
+        slice.get_mapped_range().to_vec()
+    }
+}
+
+impl Simulator for GpuSim {
+    fn step(&mut self, dt: f32) {
+        self.set_dt(dt);
+        let cmd = self.compute();
+        self.queue.submit([cmd]);
+        self.collect_profile();
+    }
+
+    fn step_count(&self) -> u64 {
+        self.step
+    }
+
+    fn particles(&mut self) -> &[Particle] {
+        if self.readback_step != Some(self.step) {
+            self.readback = self.read_particles();
+            self.readback_step = Some(self.step);
+        }
+        &self.readback
+    }
+
+    fn params(&self) -> &SimParams {
+        &self.simp
+    }
+
+    fn set_params(&mut self, simp: SimParams) {
+        let same_shape = simp.num_cultures == self.simp.num_cultures
+            && simp.culture_size == self.simp.culture_size;
+        let params = GpuParams {
+            dt: self.params.dt,
+            seed: self.params.seed,
+            ..GpuParams::new(&simp)
+        };
+        if !self.resizes(&simp) {
+            self.queue
+                .write_buffer(&self.res.params_buffer, 0, bytemuck::bytes_of(&params));
+            self.queue.write_buffer(
+                &self.res.gravity_mesh_buffer,
+                0,
+                bytemuck::cast_slice(simp.mesh.as_flat()),
+            );
+            self.params = params;
+            self.simp = simp;
+            return;
+        }
+        let particles = if same_shape {
+            self.particles().to_vec()
+        } else {
+            self.step = 0;
+            spawn_particles(simp.num_particles(), params.bound)
+        };
+        self.params = params;
+        self.simp = simp;
+        self.upload(particles);
+    }
+}
+
+/// Number of blocks the bin offsets scan splits the bins into, `SCAN_BLOCK` in compute.wgsl
+fn num_scan_blocks(num_bins: usize) -> usize {
+    num_bins.div_ceil(4096)
+}
+
+fn spawn_particles(n: u32, bound: [f32; 2]) -> Vec<Particle> {
+    let mut rng = rand::rng();
+    (0..n)
+        .map(|_| Particle {
+            pos: vec2(
+                rng.random_range(0.0..bound[0]),
+                rng.random_range(0.0..bound[1]),
+            ),
+            vel: vec2(rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0)),
+        })
+        .collect()
+}
+
+/// Convert particles for upload, tagging each with its culture and id. Storage buffers can't be
+/// empty, so pad with a zeroed entry.
+fn gpu_particles(particles: &[Particle], culture_size: u32) -> Vec<GpuParticle> {
+    let mut gpu_particles = particles
+        .iter()
+        .enumerate()
+        .map(|(i, p)| GpuParticle {
+            pos: p.pos.to_array(),
+            vel: p.vel.to_array(),
+            culture: i as u32 / culture_size,
+            id: i as u32,
+        })
+        .collect::<Vec<_>>();
+    if gpu_particles.is_empty() {
+        gpu_particles.push(bytemuck::Zeroable::zeroed());
+    }
+    gpu_particles
+}
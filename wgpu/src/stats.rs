@@ -0,0 +1,319 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use glam::Vec2;
+use particle_life_core::stats::{StatSums, Stats};
+
+/// Sums per culture written by `combine_chunks`, `STAT_FIELDS` in stats.wgsl
+const STAT_FIELDS: usize = 10;
+/// Particles summed per workgroup by `reduce_chunks`, `REDUCE_WG` in stats.wgsl
+const REDUCE_WG: u32 = 256;
+const MAX_BUFFERS: usize = 64;
+
+/// Buffers of the compute pass the stats are read from
+pub(crate) struct StatsInputs<'a> {
+    pub params: &'a wgpu::Buffer,
+    pub particles: [&'a wgpu::Buffer; 2],
+    pub bin_ixs: &'a wgpu::Buffer,
+    pub bins: &'a wgpu::Buffer,
+    pub bin_offsets: &'a wgpu::Buffer,
+    pub block_sums: &'a wgpu::Buffer,
+}
+
+/// Staging buffer holding the sums of one step
+struct Slot {
+    buffer: wgpu::Buffer,
+    step: u64,
+    origin: Vec2,
+    mapped: Arc<AtomicBool>,
+}
+
+/// Per culture stats every `every` steps, reduced on the GPU from the bins each step builds.
+///
+/// Like [`crate::profiler::GpuProfiler`], readback is asynchronous: [`GpuStats::collect`] maps
+/// the staging buffers of submitted steps and returns the ones that are ready. A stats step that
+/// finds every staging buffer in flight is skipped.
+pub struct GpuStats {
+    pub every: u32,
+    layout: wgpu::BindGroupLayout,
+    nearest: wgpu::ComputePipeline,
+    reduce: wgpu::ComputePipeline,
+    combine: wgpu::ComputePipeline,
+    sums_buffer: wgpu::Buffer,
+    /// One per particle buffer, reading the particles a step starts from
+    binds: Vec<wgpu::BindGroup>,
+    num_particles: u32,
+    num_cultures: u32,
+    origin: Vec2,
+    free: Vec<wgpu::Buffer>,
+    /// Copied but not yet submitted
+    encoded: Vec<Slot>,
+    /// Submitted and waiting for the map
+    pending: Vec<Slot>,
+    num_buffers: usize,
+}
+
+impl GpuStats {
+    pub fn new(device: &wgpu::Device, every: u32) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Stats Layout"),
+            entries: &[
+                // params
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // particles
+                storage(1, true),
+                // bin ixs
+                storage(2, true),
+                // bins
+                storage(3, true),
+                // bin offsets
+                storage(4, true),
+                // scan block sums
+                storage(5, true),
+                // nearest neighbour distances
+                storage(6, false),
+                // sums
+                storage(7, false),
+                // sums per chunk
+                storage(8, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Stats Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/stats.wgsl"));
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let sums_buffer = Self::sums_buffer(device, 0);
+        Self {
+            every: every.max(1),
+            nearest: pipeline("Nearest Neighbours", "nearest_neighbours"),
+            reduce: pipeline("Reduce Stat Chunks", "reduce_chunks"),
+            combine: pipeline("Combine Stat Chunks", "combine_chunks"),
+            layout,
+            sums_buffer,
+            binds: vec![],
+            num_particles: 0,
+            num_cultures: 0,
+            origin: Vec2::ZERO,
+            free: vec![],
+            encoded: vec![],
+            pending: vec![],
+            num_buffers: 0,
+        }
+    }
+
+    fn sums_buffer(device: &wgpu::Device, num_cultures: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Stat Sums"),
+            size: (num_cultures.max(1) as usize * STAT_FIELDS * size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Recreate the bind groups for new compute buffers. Staging buffers of the old size are
+    /// dropped, results still in flight are kept.
+    pub(crate) fn bind(
+        &mut self,
+        device: &wgpu::Device,
+        inputs: StatsInputs,
+        num_particles: u32,
+        num_cultures: u32,
+        bound: Vec2,
+    ) {
+        if num_cultures != self.num_cultures {
+            self.sums_buffer = Self::sums_buffer(device, num_cultures);
+            self.num_buffers -= self.free.len();
+            self.free.clear();
+        }
+        self.num_particles = num_particles;
+        self.num_cultures = num_cultures;
+        self.origin = bound / 2.0;
+        let nn_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Nearest Neighbours"),
+            size: (num_particles.max(1) as usize * size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let num_chunks = num_particles.div_ceil(REDUCE_WG).max(1) as usize;
+        let chunk_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Stat Chunk Sums"),
+            size: (num_chunks * num_cultures.max(1) as usize * STAT_FIELDS * size_of::<f32>())
+                as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        self.binds = inputs
+            .particles
+            .iter()
+            .map(|particles| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Stats Bind Group"),
+                    layout: &self.layout,
+                    entries: &[
+                        inputs.params.as_entire_binding(),
+                        particles.as_entire_binding(),
+                        inputs.bin_ixs.as_entire_binding(),
+                        inputs.bins.as_entire_binding(),
+                        inputs.bin_offsets.as_entire_binding(),
+                        inputs.block_sums.as_entire_binding(),
+                        nn_buffer.as_entire_binding(),
+                        self.sums_buffer.as_entire_binding(),
+                        chunk_buffer.as_entire_binding(),
+                    ]
+                    .into_iter()
+                    .enumerate()
+                    .map(|(binding, resource)| wgpu::BindGroupEntry {
+                        binding: binding as u32,
+                        resource,
+                    })
+                    .collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+    }
+
+    /// Take a staging buffer, or None if all of them are in flight
+    fn staging(&mut self, device: &wgpu::Device) -> Option<wgpu::Buffer> {
+        if let Some(buffer) = self.free.pop() {
+            return Some(buffer);
+        }
+        if self.num_buffers == MAX_BUFFERS {
+            return None;
+        }
+        self.num_buffers += 1;
+        Some(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Stats Readback"),
+            size: self.sums_buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }))
+    }
+
+    /// Encode the stats of the particles in `particle_buffer` at `step`, after the pass that
+    /// binned them, if it's a stats step
+    pub(crate) fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        particle_buffer: usize,
+        step: u64,
+    ) {
+        if !step.is_multiple_of(self.every as u64) {
+            return;
+        }
+        let Some(buffer) = self.staging(device) else {
+            return;
+        };
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("stats"),
+            timestamp_writes: None,
+        });
+        cpass.set_bind_group(0, &self.binds[particle_buffer], &[]);
+        cpass.set_pipeline(&self.nearest);
+        cpass.dispatch_workgroups(self.num_particles.div_ceil(64), 1, 1);
+        cpass.set_pipeline(&self.reduce);
+        cpass.dispatch_workgroups(self.num_particles.div_ceil(REDUCE_WG).max(1), 1, 1);
+        cpass.set_pipeline(&self.combine);
+        cpass.dispatch_workgroups(self.num_cultures, 1, 1);
+        drop(cpass);
+
+        encoder.copy_buffer_to_buffer(&self.sums_buffer, 0, &buffer, 0, buffer.size());
+        self.encoded.push(Slot {
+            buffer,
+            step,
+            origin: self.origin,
+            mapped: Arc::new(AtomicBool::new(false)),
+        });
+    }
+
+    /// Start reading back the stats encoded so far and return the ones that are ready, in step
+    /// order. Call after submitting the commands that encoded them. With `wait`, block until
+    /// every submitted step is read.
+    pub fn collect(&mut self, device: &wgpu::Device, wait: bool) -> Vec<Stats> {
+        for slot in self.encoded.drain(..) {
+            let mapped = slot.mapped.clone();
+            slot.buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |r| {
+                    if r.is_ok() {
+                        mapped.store(true, Ordering::Release);
+                    }
+                });
+            self.pending.push(slot);
+        }
+        let poll = if wait {
+            wgpu::PollType::wait_indefinitely()
+        } else {
+            wgpu::PollType::Poll
+        };
+        let _ = device.poll(poll);
+
+        let (ready, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|slot| slot.mapped.load(Ordering::Acquire));
+        self.pending = pending;
+        let mut stats = ready
+            .into_iter()
+            .map(|slot| {
+                let sums = {
+                    let data = slot.buffer.slice(..).get_mapped_range();
+                    bytemuck::cast_slice::<_, f32>(&data)
+                        .chunks_exact(STAT_FIELDS)
+                        .map(|s| StatSums {
+                            count: s[0],
+                            pos: Vec2::new(s[1], s[2]),
+                            pos2: s[3],
+                            vel: Vec2::new(s[4], s[5]),
+                            speed: s[6],
+                            speed2: s[7],
+                            nn: s[8],
+                            nn_count: s[9],
+                        })
+                        .collect::<Vec<_>>()
+                };
+                slot.buffer.unmap();
+                if slot.buffer.size() == self.sums_buffer.size() {
+                    self.free.push(slot.buffer);
+                } else {
+                    self.num_buffers -= 1;
+                }
+                Stats::from_sums(slot.step, &sums, slot.origin)
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by_key(|s| s.step);
+        stats
+    }
+}
@@ -0,0 +1,364 @@
+use egui::Widget;
+use glam::Vec2;
+use particle_life_core::{
+    Mesh, SimParams,
+    cluster::{Cluster, ClusterParams},
+    color::random_colors,
+    correlation::PairCorrelation,
+};
+use winit::{event::WindowEvent, window::Window};
+
+use crate::profiler::ProfileSummary;
+
+/// egui context, input state and renderer for the overlay
+pub struct Gui {
+    ctx: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+}
+
+/// Tessellated overlay for one frame, uploaded by [`Gui::prepare`]
+pub struct GuiFrame {
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    screen: egui_wgpu::ScreenDescriptor,
+    free: Vec<egui::TextureId>,
+}
+
+impl Gui {
+    pub fn new(window: &Window, device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let ctx = egui::Context::default();
+        let state = egui_winit::State::new(
+            ctx.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        let renderer = egui_wgpu::Renderer::new(device, format, Default::default());
+        Self {
+            ctx,
+            state,
+            renderer,
+        }
+    }
+
+    /// Feed a window event to egui. Returns true if egui consumed it.
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.state.on_window_event(window, event).consumed
+    }
+
+    /// Whether the pointer is over or interacting with the overlay
+    pub fn wants_pointer(&self) -> bool {
+        self.ctx.wants_pointer_input() || self.ctx.is_pointer_over_area()
+    }
+
+    /// Run the ui and upload its meshes and textures
+    pub fn prepare(
+        &mut self,
+        window: &Window,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        run_ui: impl FnMut(&egui::Context),
+    ) -> GuiFrame {
+        let input = self.state.take_egui_input(window);
+        let output = self.ctx.run(input, run_ui);
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        let size = window.inner_size();
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: output.pixels_per_point,
+        };
+        let paint_jobs = self.ctx.tessellate(output.shapes, output.pixels_per_point);
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        self.renderer
+            .update_buffers(device, queue, encoder, &paint_jobs, &screen);
+
+        GuiFrame {
+            paint_jobs,
+            screen,
+            free: output.textures_delta.free,
+        }
+    }
+
+    /// Draw a prepared frame on top of whatever the pass already drew
+    pub fn render(&mut self, rpass: &mut wgpu::RenderPass<'static>, frame: &GuiFrame) {
+        self.renderer
+            .render(rpass, &frame.paint_jobs, &frame.screen);
+    }
+
+    /// Release textures egui no longer needs, after the frame has been submitted
+    pub fn finish(&mut self, frame: GuiFrame) {
+        for id in &frame.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+/// Counters shown at the top of the panel
+pub struct Stats<'a> {
+    pub phys_fps: u32,
+    pub render_fps: u32,
+    pub num_particles: u32,
+    /// GPU stage timings when profiling
+    pub profile: Option<ProfileSummary>,
+    /// Tracked clusters, largest first, when the overlay is on
+    pub clusters: &'a [Cluster],
+    /// Latest g(r) when the live plot is on
+    pub pair_correlation: Option<&'a PairCorrelation>,
+}
+
+/// What the user asked for this frame
+#[derive(Default)]
+pub struct PanelResponse {
+    /// Live params (aoe, damping, temperature, repulsion, limits, mesh) were edited
+    pub params_changed: bool,
+    pub colors_changed: bool,
+    /// Respawn with the panel's shape, keeping the mesh when the number of cultures is unchanged
+    pub reset: bool,
+    /// Respawn with a new random mesh and colors
+    pub randomize: bool,
+}
+
+/// Editable copy of the sim config, the wgpu counterpart of macroquad's "Simulation Config"
+pub struct Panel {
+    /// Params matching the running sim, edits apply live
+    pub simp: SimParams,
+    /// Shape applied on reset or randomize
+    pub num_cultures: u32,
+    pub culture_size: u32,
+    pub colors: Vec<[f32; 4]>,
+    /// Stepping is stopped, toggled with p or set by an energy explosion
+    pub paused: bool,
+    /// Track clusters and draw their outlines, toggled with c
+    pub show_clusters: bool,
+    pub clusters: ClusterParams,
+    /// Plot g(r) between `pair_culture` and every culture, out to `pair_radius`
+    pub show_pair_correlation: bool,
+    pub pair_culture: u32,
+    pub pair_radius: f32,
+    /// Show the history plots window
+    pub show_plots: bool,
+}
+
+impl Panel {
+    pub fn new(simp: SimParams, colors: Vec<[f32; 4]>, clusters: ClusterParams) -> Self {
+        Self {
+            num_cultures: simp.num_cultures,
+            culture_size: simp.culture_size,
+            pair_radius: simp.aoe,
+            simp,
+            colors,
+            paused: false,
+            show_clusters: false,
+            clusters,
+            show_pair_correlation: false,
+            pair_culture: 0,
+            show_plots: false,
+        }
+    }
+
+    /// Params for a reset or randomize with the panel's shape. A new random mesh (and colors) is
+    /// rolled when `randomize` is set or the number of cultures changed.
+    pub fn respawn_params(&mut self, randomize: bool) -> SimParams {
+        let mut rng = rand::rng();
+        if randomize || self.num_cultures != self.simp.num_cultures {
+            self.simp.mesh = Mesh::random(self.num_cultures as usize, &mut rng);
+            self.colors = random_colors(self.num_cultures as usize, &mut rng);
+        }
+        self.simp.num_cultures = self.num_cultures;
+        self.simp.culture_size = self.culture_size;
+        self.simp.clone()
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, stats: &Stats) -> PanelResponse {
+        let mut res = PanelResponse::default();
+        egui::Window::new("Simulation Config")
+            .default_open(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Physics FPS: {}  Render FPS: {}",
+                    stats.phys_fps, stats.render_fps
+                ));
+                ui.label(format!("Particles: {}", stats.num_particles));
+                if let Some(profile) = &stats.profile {
+                    ui.collapsing("GPU Stages", |ui| {
+                        ui.monospace(profile.to_string());
+                    });
+                }
+                ui.separator();
+
+                let simp = &mut self.simp;
+                let mut changed = false;
+                changed |= egui::Slider::new(&mut simp.aoe, 1.0..=300.0)
+                    .text("Particle AOE")
+                    .ui(ui)
+                    .changed();
+                changed |= egui::Slider::new(&mut simp.damping, 0.0..=1.0)
+                    .text("Damping")
+                    .ui(ui)
+                    .changed();
+                changed |= egui::Slider::new(&mut simp.temperature, 0.0..=10.0)
+                    .text("Temperature")
+                    .ui(ui)
+                    .changed();
+                changed |= egui::Slider::new(&mut simp.repulsion.radius, 0.0..=simp.aoe)
+                    .text("Repulsion Radius")
+                    .ui(ui)
+                    .changed();
+                changed |= egui::Slider::new(&mut simp.repulsion.strength, 0.0..=10.0)
+                    .text("Repulsion Strength")
+                    .ui(ui)
+                    .changed();
+                changed |= egui::Slider::new(&mut simp.max_force, 0.0..=100.0)
+                    .text("Max Force (0 = off)")
+                    .ui(ui)
+                    .changed();
+                changed |= egui::Slider::new(&mut simp.max_speed, 0.0..=100.0)
+                    .text("Max Speed (0 = off)")
+                    .ui(ui)
+                    .changed();
+
+                ui.separator();
+                ui.label("Gravity Mesh");
+                let n = simp.num_cultures as usize;
+                egui::Grid::new("mesh").show(ui, |ui| {
+                    for i in 0..n {
+                        res.colors_changed |= ui
+                            .color_edit_button_rgba_unmultiplied(&mut self.colors[i])
+                            .changed();
+                        for j in 0..n {
+                            let mut g = simp.mesh.get(i, j);
+                            if egui::DragValue::new(&mut g)
+                                .speed(0.01)
+                                .range(-1.0..=1.0)
+                                .ui(ui)
+                                .changed()
+                            {
+                                simp.mesh.set(i, j, g);
+                                changed = true;
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+                res.params_changed = changed;
+
+                ui.separator();
+                egui::Slider::new(&mut self.num_cultures, 1..=10)
+                    .text("Num Cultures")
+                    .ui(ui);
+                egui::Slider::new(&mut self.culture_size, 1..=20000)
+                    .text("Culture Size")
+                    .ui(ui);
+                ui.horizontal(|ui| {
+                    res.reset = ui.button("Reset").clicked();
+                    res.randomize = ui.button("Randomize").clicked();
+                    ui.checkbox(&mut self.paused, "Paused");
+                    ui.checkbox(&mut self.show_plots, "Plots");
+                });
+                if ui.button("Print SimParams").clicked() {
+                    println!("SimParams\n{}", self.simp.to_json());
+                }
+
+                ui.separator();
+                ui.checkbox(&mut self.show_clusters, "Show clusters");
+                if self.show_clusters {
+                    let mut params = self.clusters;
+                    egui::Slider::new(&mut params.eps, 1.0..=50.0)
+                        .text("Cluster Radius")
+                        .ui(ui);
+                    egui::Slider::new(&mut params.min_points, 1..=20)
+                        .text("Min Neighbours")
+                        .ui(ui);
+                    egui::Slider::new(&mut params.min_size, 1..=500)
+                        .text("Min Cluster Size")
+                        .ui(ui);
+                    if params.validate().is_ok() {
+                        self.clusters = params;
+                    }
+                    ui.label(format!("Clusters: {}", stats.clusters.len()));
+                    for cluster in stats.clusters.iter().take(MAX_LISTED_CLUSTERS) {
+                        ui.monospace(cluster.to_string());
+                    }
+                }
+
+                ui.separator();
+                ui.checkbox(&mut self.show_pair_correlation, "Live g(r)");
+                if self.show_pair_correlation {
+                    self.pair_correlation_plot(ui, stats.pair_correlation);
+                }
+            });
+        res
+    }
+
+    /// g(r) of the chosen culture against every culture, in the colors of the others
+    fn pair_correlation_plot(&mut self, ui: &mut egui::Ui, gr: Option<&PairCorrelation>) {
+        let last = self.simp.num_cultures.saturating_sub(1);
+        self.pair_culture = self.pair_culture.min(last);
+        egui::Slider::new(&mut self.pair_culture, 0..=last)
+            .text("g(r) Culture")
+            .ui(ui);
+        egui::Slider::new(&mut self.pair_radius, 1.0..=300.0)
+            .text("g(r) Radius")
+            .ui(ui);
+        let Some(gr) = gr else {
+            return;
+        };
+        egui_plot::Plot::new("g(r)")
+            .height(150.0)
+            .legend(egui_plot::Legend::default())
+            .show(ui, |plot| {
+                let a = self.pair_culture;
+                for other in 0..self.simp.num_cultures {
+                    let Some(pair) = gr.pair(a, other) else {
+                        continue;
+                    };
+                    let points = pair
+                        .g
+                        .iter()
+                        .enumerate()
+                        .map(|(bin, &g)| [gr.r(bin) as f64, g as f64])
+                        .collect::<Vec<_>>();
+                    let [r, g, b, _] = self.colors.get(other as usize).copied().unwrap_or([1.0; 4]);
+                    plot.line(
+                        egui_plot::Line::new(format!("{a}-{other}"), points)
+                            .color(egui::Rgba::from_rgb(r, g, b)),
+                    );
+                }
+            });
+    }
+}
+
+const MAX_LISTED_CLUSTERS: usize = 10;
+
+/// Outline each cluster in the color of its dominant culture and label it with its id, mapping
+/// world positions to points with `to_screen`
+pub fn draw_clusters(
+    painter: &egui::Painter,
+    clusters: &[Cluster],
+    colors: &[[f32; 4]],
+    to_screen: impl Fn(Vec2) -> egui::Pos2,
+) {
+    for cluster in clusters {
+        let [r, g, b, _] = colors
+            .get(cluster.dominant().0)
+            .copied()
+            .unwrap_or([1.0; 4]);
+        let color = egui::Color32::from(egui::Rgba::from_rgb(r, g, b));
+        let points = cluster.outline.iter().map(|&p| to_screen(p)).collect();
+        painter.add(egui::Shape::closed_line(points, (1.5, color)));
+        painter.text(
+            to_screen(cluster.centroid),
+            egui::Align2::CENTER_CENTER,
+            cluster.id.to_string(),
+            egui::FontId::monospace(12.0),
+            egui::Color32::WHITE,
+        );
+    }
+}
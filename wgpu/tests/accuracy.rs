@@ -0,0 +1,40 @@
+//! The Barnes-Hut theta sweep against the exact forces.
+
+use glam::vec2;
+use particle_life_core::{
+    SimParams, Simulator,
+    accuracy::theta_sweep,
+    cpu::{CpuOptions, CpuSim},
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+#[test]
+fn theta_sweep_error() {
+    let simp = SimParams::random(4, 200, 50.0, 0.5, &mut StdRng::seed_from_u64(3));
+    let opts = CpuOptions {
+        bound: vec2(400.0, 300.0),
+        seed: Some(3),
+        ..Default::default()
+    };
+    let mut sim = CpuSim::new(simp.clone(), opts);
+    for _ in 0..20 {
+        sim.step(1.0);
+    }
+    let particles = sim.particles().to_vec();
+
+    let sweep = theta_sweep(&simp, &particles, opts.bound, &[0.0, 1.5]);
+    let [exact, coarse] = &sweep.thetas[..] else {
+        panic!("expected 2 thetas");
+    };
+    assert_eq!(exact.cultures.len(), 4);
+    assert!(exact.total.samples > 0);
+    assert!(
+        exact.total.max < 1e-4,
+        "theta 0 max error {}",
+        exact.total.max
+    );
+    assert!(coarse.total.mean > exact.total.mean);
+    for c in &coarse.cultures {
+        assert!(c.mean <= c.max);
+    }
+}
@@ -0,0 +1,56 @@
+//! The batch bins reject bad arguments before writing anything. Doesn't need an adapter.
+
+use std::{path::PathBuf, process::Command};
+
+/// An empty directory for `test` under the system temp dir
+fn out_dir(test: &str) -> PathBuf {
+    let out = std::env::temp_dir().join(format!("particle-life-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&out);
+    out
+}
+
+/// Run `bin` with `args` and return its stderr, expecting it to fail
+fn fails(bin: &str, args: &[&str]) -> String {
+    let output = Command::new(bin).args(args).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(!output.status.success(), "{bin} {args:?} succeeded");
+    stderr
+}
+
+#[test]
+fn sweep_rejects_invalid_axes() {
+    let out = out_dir("sweep-axes");
+    let out_arg = out.to_str().unwrap();
+    let bin = env!("CARGO_BIN_EXE_sweep");
+    for (flag, value, named) in [
+        ("--culture-size", "0,10", "culture size 0"),
+        ("--aoe", "0,10", "aoe 0"),
+        ("--damping=-3", "", "damping -3"),
+        ("--cluster-eps", "0", "cluster eps"),
+    ] {
+        let mut args = vec!["--out", out_arg, "--steps", "1", flag];
+        if !value.is_empty() {
+            args.push(value);
+        }
+        let stderr = fails(bin, &args);
+        assert!(stderr.contains(named), "{flag} {value}: {stderr}");
+        assert!(!out.join("results.csv").exists());
+    }
+}
+
+#[test]
+fn evolve_rejects_invalid_settings() {
+    let out = out_dir("evolve-settings");
+    let out_arg = out.to_str().unwrap();
+    let bin = env!("CARGO_BIN_EXE_evolve");
+    for (args, named) in [
+        (&["--damping=-3"][..], "damping must be non-negative"),
+        (&["--particles", "0"], "culture_size must be positive"),
+        (&["--steps", "100", "--warmup", "100"], "warmup"),
+        (&["--elites", "4", "--keep", "5"], "--keep 5"),
+    ] {
+        let stderr = fails(bin, &[&["--out", out_arg][..], args].concat());
+        assert!(stderr.contains(named), "{args:?}: {stderr}");
+        assert!(!out.join("history.csv").exists());
+    }
+}
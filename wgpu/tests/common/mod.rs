@@ -0,0 +1,19 @@
+//! The adapter the GPU tests run on.
+
+use particle_life::sim::{HeadlessOptions, headless_device};
+
+/// Set to let the GPU tests pass without running on machines with no adapter
+const SKIP_GPU: &str = "PARTICLE_LIFE_SKIP_GPU";
+
+/// A headless device and queue. A missing adapter fails the test, or skips it with a message when
+/// `PARTICLE_LIFE_SKIP_GPU` is set.
+pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    match headless_device(&HeadlessOptions::default()) {
+        Ok((device, queue, _)) => Some((device, queue)),
+        Err(e) if std::env::var_os(SKIP_GPU).is_some() => {
+            eprintln!("skipping, no adapter: {e}");
+            None
+        }
+        Err(e) => panic!("no adapter, set {SKIP_GPU}=1 to skip the GPU tests: {e}"),
+    }
+}
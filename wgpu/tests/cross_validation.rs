@@ -0,0 +1,389 @@
+//! Compare the WGSL kernel against the CPU exact-cutoff reference. Needs an adapter, a software
+//! one will do.
+
+mod common;
+
+use glam::{Vec2, vec2};
+use particle_life::sim::GpuSim;
+use particle_life_core::{
+    Particle, SimParams, Simulator,
+    cpu::{CpuBackend, CpuOptions, CpuSim},
+    field::ForceFields,
+    obstacle::CircleObstacle,
+    sim::{Cursor, Repulsion},
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+const TOL: f32 = 1e-3;
+
+fn gpu_sim(simp: &SimParams, particles: &[Particle]) -> Option<GpuSim> {
+    let (device, queue) = common::device()?;
+    Some(GpuSim::with_particles(
+        device,
+        queue,
+        simp.clone(),
+        particles.to_vec(),
+    ))
+}
+
+/// One step of the GPU law on the CPU: exact mesh forces within the cutoff and positions
+/// integrated over `dt`
+fn reference_step(
+    simp: &SimParams,
+    bound: Vec2,
+    particles: &[Particle],
+    cursor: Option<Cursor>,
+    step: u64,
+) -> Vec<Particle> {
+    let opts = CpuOptions {
+        backend: CpuBackend::Naive,
+        bound,
+        seed: Some(0),
+        ..Default::default()
+    };
+    let mut cpu = CpuSim::with_particles(simp.clone(), opts, particles.to_vec());
+    cpu.compute_force_naive();
+
+    particles
+        .iter()
+        .zip(cpu.forces())
+        .map(|(p, &mesh_force)| {
+            let cursor_force = cursor.map_or(Vec2::ZERO, |c| c.force(p.pos));
+            let force = mesh_force + cursor_force + simp.fields.force(p.pos, step as f32);
+            let force = limit(force, simp.max_force);
+            let mut pos = p.pos;
+            let mut vel = limit((p.vel + force) * simp.damping, simp.max_speed);
+            if pos.x <= 0.0 {
+                vel.x = vel.x.abs();
+                pos.x = 0.0;
+            } else if pos.x >= bound.x {
+                vel.x = -vel.x.abs();
+                pos.x = bound.x;
+            }
+            if pos.y <= 0.0 {
+                vel.y = vel.y.abs();
+                pos.y = 0.0;
+            } else if pos.y >= bound.y {
+                vel.y = -vel.y.abs();
+                pos.y = bound.y;
+            }
+            let prev = pos;
+            pos += vel;
+            simp.obstacles.resolve(prev, &mut pos, &mut vel);
+            Particle { pos, vel }
+        })
+        .collect()
+}
+
+fn limit(v: Vec2, max: f32) -> Vec2 {
+    if max > 0.0 {
+        v.clamp_length_max(max)
+    } else {
+        v
+    }
+}
+
+fn assert_agree(gpu: &[Particle], cpu: &[Particle]) {
+    assert_eq!(gpu.len(), cpu.len());
+    for (i, (g, c)) in gpu.iter().zip(cpu).enumerate() {
+        assert!(
+            g.pos.is_finite() && g.vel.is_finite(),
+            "particle {i} is not finite on the GPU: {g:?}"
+        );
+        assert!(
+            g.pos.abs_diff_eq(c.pos, TOL) && g.vel.abs_diff_eq(c.vel, TOL),
+            "particle {i} diverged\n  gpu: {g:?}\n  cpu: {c:?}"
+        );
+    }
+}
+
+/// Run `steps` steps on both backends, comparing after every step, with and without sorting
+fn cross_validate(simp: &SimParams, particles: Vec<Particle>, steps: u64) {
+    for sorted in [false, true] {
+        cross_validate_with(simp, particles.clone(), steps, sorted, None);
+    }
+}
+
+fn cross_validate_with(
+    simp: &SimParams,
+    particles: Vec<Particle>,
+    steps: u64,
+    sorted: bool,
+    cursor: Option<Cursor>,
+) {
+    let Some(mut gpu) = gpu_sim(simp, &particles) else {
+        return;
+    };
+    gpu.set_sorted(sorted);
+    gpu.set_cursor(cursor);
+    let bound = Vec2::from(gpu.gpu_params().bound);
+    let mut cpu = particles;
+    for step in 0..steps {
+        cpu = reference_step(simp, bound, &cpu, cursor, step);
+        gpu.step(1.0);
+        assert_agree(gpu.particles(), &cpu);
+    }
+}
+
+fn params(num_cultures: u32, culture_size: u32, aoe: f32, seed: u64) -> SimParams {
+    let mut simp = SimParams::random(
+        num_cultures,
+        culture_size,
+        aoe,
+        0.5,
+        &mut StdRng::seed_from_u64(seed),
+    );
+    simp.fields = ForceFields::default();
+    simp
+}
+
+fn random_particles(n: u32, lo: Vec2, hi: Vec2, rng: &mut impl Rng) -> Vec<Particle> {
+    (0..n)
+        .map(|_| Particle {
+            pos: vec2(rng.random_range(lo.x..hi.x), rng.random_range(lo.y..hi.y)),
+            vel: vec2(rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0)),
+        })
+        .collect()
+}
+
+#[test]
+fn random_world_single_step() {
+    let simp = params(4, 250, 50.0, 1);
+    let mut rng = StdRng::seed_from_u64(1);
+    let particles = random_particles(1000, Vec2::ZERO, Vec2::splat(1000.0), &mut rng);
+    cross_validate(&simp, particles, 1);
+}
+
+#[test]
+fn random_world_several_steps() {
+    let simp = params(3, 100, 80.0, 2);
+    let mut rng = StdRng::seed_from_u64(2);
+    let particles = random_particles(300, Vec2::ZERO, Vec2::splat(1000.0), &mut rng);
+    cross_validate(&simp, particles, 3);
+}
+
+#[test]
+fn particles_on_bound() {
+    let simp = params(2, 8, 50.0, 3);
+    let edges = [
+        vec2(0.0, 0.0),
+        vec2(1000.0, 0.0),
+        vec2(0.0, 1000.0),
+        vec2(1000.0, 1000.0),
+        vec2(1000.0, 500.0),
+        vec2(500.0, 1000.0),
+        vec2(0.0, 500.0),
+        vec2(500.0, 0.0),
+    ];
+    // Each edge point hosts one particle of each culture, slightly apart, heading out of bounds
+    let particles = (0..2)
+        .flat_map(|c| {
+            edges.iter().map(move |&pos| Particle {
+                pos: (pos + Vec2::splat(c as f32 * 3.0)).min(Vec2::splat(1000.0)),
+                vel: (pos - Vec2::splat(500.0)).normalize_or_zero(),
+            })
+        })
+        .collect();
+    cross_validate(&simp, particles, 2);
+}
+
+#[test]
+fn coincident_particles() {
+    let simp = params(2, 50, 50.0, 4);
+    let mut rng = StdRng::seed_from_u64(4);
+    // Every particle in the second culture sits exactly on one in the first
+    let first = random_particles(50, Vec2::splat(400.0), Vec2::splat(600.0), &mut rng);
+    let particles = first.iter().chain(&first).copied().collect();
+    cross_validate(&simp, particles, 2);
+}
+
+#[test]
+fn empty_bins() {
+    let simp = params(3, 40, 20.0, 5);
+    let mut rng = StdRng::seed_from_u64(5);
+    // Two tight clumps leave almost all of the 25x25 grid empty
+    let mut particles = random_particles(60, Vec2::splat(100.0), Vec2::splat(140.0), &mut rng);
+    particles.extend(random_particles(
+        60,
+        Vec2::splat(850.0),
+        Vec2::splat(900.0),
+        &mut rng,
+    ));
+    cross_validate(&simp, particles, 2);
+}
+
+#[test]
+fn fields_and_obstacles() {
+    let mut simp = params(3, 100, 50.0, 6);
+    simp.fields.gravity = vec2(0.0, 0.3);
+    simp.fields.attractor.center = vec2(500.0, 500.0);
+    simp.fields.attractor.strength = 0.5;
+    simp.fields.vortex.center = vec2(300.0, 700.0);
+    simp.fields.vortex.strength = -0.4;
+    simp.obstacles.circles.push(CircleObstacle {
+        center: vec2(500.0, 500.0),
+        radius: 60.0,
+    });
+    let mut rng = StdRng::seed_from_u64(6);
+    let particles = random_particles(300, Vec2::ZERO, Vec2::splat(1000.0), &mut rng)
+        .into_iter()
+        .map(|mut p| {
+            // Start outside the obstacle
+            if p.pos.distance(vec2(500.0, 500.0)) < 70.0 {
+                p.pos.x += 150.0;
+            }
+            p
+        })
+        .collect();
+    cross_validate(&simp, particles, 2);
+}
+
+#[test]
+fn many_bins() {
+    // A 556x556 grid has more scan blocks than the block sums workgroup has invocations, so each
+    // invocation covers a chunk of blocks
+    let simp = params(2, 300, 0.9, 7);
+    let mut rng = StdRng::seed_from_u64(7);
+    let particles = (0..6)
+        .flat_map(|k| {
+            let lo = vec2(100.0 + k as f32 * 150.0, 50.0 + k as f32 * 170.0);
+            random_particles(100, lo, lo + Vec2::splat(8.0), &mut rng)
+        })
+        .collect();
+    cross_validate(&simp, particles, 2);
+}
+
+#[test]
+fn rectangular_bound() {
+    let mut simp = params(3, 100, 40.0, 8);
+    simp.bound = Some(vec2(1600.0, 500.0));
+    let mut rng = StdRng::seed_from_u64(8);
+    let mut particles = random_particles(280, Vec2::ZERO, vec2(1600.0, 500.0), &mut rng);
+    // A few on the far corners and edges
+    particles.extend(
+        [
+            vec2(1600.0, 500.0),
+            vec2(1600.0, 0.0),
+            vec2(0.0, 500.0),
+            vec2(800.0, 500.0),
+        ]
+        .iter()
+        .map(|&pos| Particle {
+            pos,
+            vel: vec2(1.0, 1.0),
+        }),
+    );
+    particles.extend(random_particles(
+        16,
+        vec2(1550.0, 450.0),
+        vec2(1600.0, 500.0),
+        &mut rng,
+    ));
+    cross_validate(&simp, particles, 3);
+}
+
+#[test]
+fn cursor_force() {
+    let simp = params(2, 100, 50.0, 9);
+    let mut rng = StdRng::seed_from_u64(9);
+    let particles = random_particles(200, Vec2::splat(300.0), Vec2::splat(700.0), &mut rng);
+    for strength in [-2.0, 2.0] {
+        let cursor = Cursor {
+            pos: vec2(500.0, 500.0),
+            radius: 150.0,
+            strength,
+        };
+        cross_validate_with(&simp, particles.clone(), 2, false, Some(cursor));
+    }
+}
+
+#[test]
+fn repulsion() {
+    let mut simp = params(3, 100, 50.0, 10);
+    simp.repulsion = Repulsion {
+        radius: 10.0,
+        strength: 2.0,
+    };
+    let mut rng = StdRng::seed_from_u64(10);
+    // Packed tightly so most pairs are inside the hard-core radius
+    let particles = random_particles(300, vec2(450.0, 450.0), vec2(550.0, 550.0), &mut rng);
+    cross_validate(&simp, particles, 3);
+}
+
+#[test]
+fn force_and_speed_limits() {
+    let mut simp = params(3, 100, 60.0, 11);
+    simp.damping = 0.9;
+    simp.max_force = 5.0;
+    simp.max_speed = 3.0;
+    let mut rng = StdRng::seed_from_u64(11);
+    let particles = random_particles(300, vec2(400.0, 400.0), vec2(600.0, 600.0), &mut rng);
+    cross_validate(&simp, particles, 3);
+}
+
+#[test]
+fn reshaped_worlds_respawn_in_the_new_bound() {
+    let simp = params(2, 50, 30.0, 11);
+    let mut rng = StdRng::seed_from_u64(11);
+    let particles = random_particles(100, Vec2::ZERO, Vec2::splat(100.0), &mut rng);
+    let mut reshaped = params(3, 200, 30.0, 12);
+    reshaped.bound = Some(vec2(2000.0, 1500.0));
+    // Almost surely some of 600 particles land outside the old 100x100 corner
+    let spread = |ps: &[Particle]| ps.iter().any(|p| p.pos.x > 100.0 && p.pos.y > 100.0);
+
+    let opts = CpuOptions {
+        bound: Vec2::splat(100.0),
+        seed: Some(0),
+        ..Default::default()
+    };
+    let mut cpu = CpuSim::with_particles(simp.clone(), opts, particles.clone());
+    cpu.set_params(reshaped.clone());
+    assert_eq!(cpu.particles().len(), 600);
+    assert!(spread(cpu.particles()));
+
+    let mut simp = simp;
+    simp.bound = Some(Vec2::splat(100.0));
+    let Some(mut gpu) = gpu_sim(&simp, &particles) else {
+        return;
+    };
+    gpu.set_params(reshaped);
+    let particles = gpu.particles();
+    assert_eq!(particles.len(), 600);
+    assert!(spread(particles));
+    assert!(
+        particles
+            .iter()
+            .all(|p| p.pos.x < 2000.0 && p.pos.y < 1500.0)
+    );
+}
+
+#[test]
+fn live_params_are_written_in_place() {
+    let simp = params(3, 100, 50.0, 13);
+    let mut rng = StdRng::seed_from_u64(13);
+    let particles = random_particles(300, Vec2::ZERO, Vec2::splat(1000.0), &mut rng);
+    let Some(mut gpu) = gpu_sim(&simp, &particles) else {
+        return;
+    };
+    let bound = Vec2::from(gpu.gpu_params().bound);
+    gpu.step(1.0);
+    let mut cpu = reference_step(&simp, bound, &particles, None, 0);
+
+    let mut edited = params(3, 100, 50.0, 14);
+    edited.damping = 0.8;
+    edited.repulsion = Repulsion {
+        radius: 10.0,
+        strength: 2.0,
+    };
+    assert!(!gpu.resizes(&edited));
+    gpu.set_params(edited.clone());
+    for step in 1..3 {
+        cpu = reference_step(&edited, bound, &cpu, None, step);
+        gpu.step(1.0);
+        assert_agree(gpu.particles(), &cpu);
+    }
+
+    let mut reshaped = edited;
+    reshaped.aoe = 10.0;
+    assert!(gpu.resizes(&reshaped));
+}
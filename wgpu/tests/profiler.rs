@@ -0,0 +1,38 @@
+//! Stage timestamps are collected when the adapter supports them, and profiling is a no-op
+//! otherwise. Needs an adapter.
+
+mod common;
+
+use particle_life::{profiler::Stage, sim::GpuSim};
+use particle_life_core::SimParams;
+use rand::{SeedableRng, rngs::StdRng};
+
+#[test]
+fn compute_stages_are_profiled() {
+    let simp = SimParams::random(3, 100, 50.0, 0.5, &mut StdRng::seed_from_u64(1));
+    let Some((device, queue)) = common::device() else {
+        return;
+    };
+    let mut sim = GpuSim::new(device, queue, simp);
+    if !sim.set_profiling(true, 8) {
+        eprintln!("skipping, no timestamp queries");
+        assert!(sim.profiler().is_none());
+        sim.step_n(4, 1.0);
+        return;
+    }
+
+    for _ in 0..12 {
+        sim.step_n(1, 1.0);
+        sim.wait();
+        sim.collect_profile();
+    }
+    let summary = sim.profiler().unwrap().summary();
+    let stages = summary.stages.iter().map(|t| t.stage).collect::<Vec<_>>();
+    assert_eq!(stages, &Stage::ALL[Stage::COMPUTE]);
+    for t in &summary.stages {
+        assert!(t.samples > 0 && t.samples <= 8, "{summary}");
+        assert!(t.min_ms <= t.mean_ms && t.mean_ms <= t.max_ms, "{summary}");
+    }
+    let json = summary.to_json();
+    assert!(json["force"]["mean_ms"].is_number(), "{json}");
+}
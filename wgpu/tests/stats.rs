@@ -0,0 +1,96 @@
+//! GPU stats against the CPU reference on the same particles, and the stats file formats. The GPU
+//! test needs an adapter.
+
+mod common;
+
+use glam::Vec2;
+use particle_life::sim::{DEFAULT_BOUND, GpuSim};
+use particle_life_core::{
+    SimParams,
+    stats::{CultureStats, Stats, StatsWriter},
+};
+use rand::{SeedableRng, rngs::StdRng};
+
+fn assert_close(gpu: &CultureStats, cpu: &CultureStats) {
+    let close = |a: f32, b: f32| (a - b).abs() <= 1e-3 * (1.0 + a.abs().max(b.abs()));
+    let close2 = |a: Vec2, b: Vec2| close(a.x, b.x) && close(a.y, b.y);
+    assert_eq!(gpu.count, cpu.count);
+    assert!(close2(gpu.centroid, cpu.centroid), "{gpu:?}\n{cpu:?}");
+    assert!(close(gpu.mean_speed, cpu.mean_speed), "{gpu:?}\n{cpu:?}");
+    assert!(
+        close(gpu.kinetic_energy, cpu.kinetic_energy),
+        "{gpu:?}\n{cpu:?}"
+    );
+    assert!(close2(gpu.momentum, cpu.momentum), "{gpu:?}\n{cpu:?}");
+    assert!(close(gpu.spread, cpu.spread), "{gpu:?}\n{cpu:?}");
+    match (gpu.nn_distance, cpu.nn_distance) {
+        (Some(a), Some(b)) => assert!(close(a, b), "{gpu:?}\n{cpu:?}"),
+        (a, b) => assert_eq!(a, b),
+    }
+}
+
+#[test]
+fn gpu_stats_match_cpu() {
+    let simp = SimParams::random(4, 300, 40.0, 0.5, &mut StdRng::seed_from_u64(3));
+    let bound = simp.bound.unwrap_or(DEFAULT_BOUND);
+    for sorted in [false, true] {
+        let Some((device, queue)) = common::device() else {
+            return;
+        };
+        let mut sim = GpuSim::new(device, queue, simp.clone());
+        sim.set_sorted(sorted);
+        sim.set_stats(Some(5));
+        sim.step_n(10, 1.0);
+        let particles = sim.read_particles();
+        sim.step_n(1, 1.0);
+
+        let stats = sim.collect_stats(true);
+        let steps = stats.iter().map(|s| s.step).collect::<Vec<_>>();
+        assert_eq!(steps, [0, 5, 10]);
+        let cpu = Stats::compute(&simp, bound, &particles, 10);
+        let gpu = &stats[2];
+        assert_eq!(gpu.cultures.len(), cpu.cultures.len());
+        assert_close(&gpu.total, &cpu.total);
+        for (gpu, cpu) in gpu.cultures.iter().zip(&cpu.cultures) {
+            assert_close(gpu, cpu);
+        }
+    }
+}
+
+#[test]
+fn stats_files() {
+    let simp = SimParams::random(2, 50, 50.0, 0.5, &mut StdRng::seed_from_u64(4));
+    let bound = Vec2::splat(400.0);
+    let particles = (0..100)
+        .map(|i| particle_life_core::Particle {
+            pos: Vec2::new((i % 10) as f32 * 30.0, (i / 10) as f32 * 30.0),
+            vel: Vec2::new(1.0, 0.0),
+        })
+        .collect::<Vec<_>>();
+    let stats = Stats::compute(&simp, bound, &particles, 7);
+    assert_eq!(stats.total.count, 100);
+    assert_eq!(stats.total.momentum, Vec2::new(100.0, 0.0));
+    assert_eq!(stats.cultures[0].nn_distance, Some(30.0));
+
+    let dir = std::env::temp_dir();
+    let csv = dir.join("particle-life-stats-test.csv");
+    let mut writer = StatsWriter::create(&csv).unwrap();
+    writer.write(&stats).unwrap();
+    writer.flush().unwrap();
+    let text = std::fs::read_to_string(&csv).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("step,culture,count"));
+    assert!(lines[3].starts_with("7,all,100,"));
+
+    let jsonl = dir.join("particle-life-stats-test.jsonl");
+    let mut writer = StatsWriter::create(&jsonl).unwrap();
+    writer.write(&stats).unwrap();
+    writer.write(&stats).unwrap();
+    writer.flush().unwrap();
+    let text = std::fs::read_to_string(&jsonl).unwrap();
+    assert_eq!(text.lines().count(), 2);
+    let json: serde_json::Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+    assert_eq!(json["step"], 7);
+    assert_eq!(json["cultures"].as_array().unwrap().len(), 2);
+}
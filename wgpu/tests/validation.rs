@@ -0,0 +1,84 @@
+//! Bad particles are contained by the binning pass and counted by the validation pass. Needs an
+//! adapter.
+
+mod common;
+
+use glam::{Vec2, vec2};
+use particle_life::sim::{GpuSim, ValidationReport};
+use particle_life_core::{Particle, SimParams, Simulator};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+fn gpu_sim(simp: &SimParams, particles: Vec<Particle>) -> Option<GpuSim> {
+    let (device, queue) = common::device()?;
+    Some(GpuSim::with_particles(
+        device,
+        queue,
+        simp.clone(),
+        particles,
+    ))
+}
+
+#[test]
+fn bad_particles_are_counted_and_contained() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let simp = SimParams::random(2, 50, 50.0, 0.5, &mut rng);
+    let mut particles = (0..100)
+        .map(|_| Particle {
+            pos: vec2(
+                rng.random_range(450.0..550.0),
+                rng.random_range(450.0..550.0),
+            ),
+            vel: Vec2::ZERO,
+        })
+        .collect::<Vec<_>>();
+    // In the middle of the clump, so a NaN leaking into forces would spread
+    particles[0].pos = Vec2::NAN;
+    particles[1].vel = vec2(f32::INFINITY, 0.0);
+    particles[2].pos = vec2(5000.0, -300.0);
+    particles[3].pos = vec2(-1.0, 500.0);
+
+    let Some(mut gpu) = gpu_sim(&simp, particles) else {
+        return;
+    };
+    gpu.set_validation(true);
+    gpu.step(1.0);
+    assert_eq!(
+        gpu.read_validation(),
+        ValidationReport {
+            out_of_range: 2,
+            non_finite: 2,
+        }
+    );
+
+    // Whether the bad particles stay bad depends on how the driver compares NaNs, but they must
+    // not spread
+    gpu.step(1.0);
+    gpu.step(1.0);
+    for (i, p) in gpu.particles().iter().enumerate().skip(2) {
+        assert!(
+            p.pos.is_finite() && p.vel.is_finite(),
+            "particle {i} was poisoned: {p:?}"
+        );
+        assert!(
+            p.pos.cmpge(Vec2::splat(-100.0)).all() && p.pos.cmple(Vec2::splat(1100.0)).all(),
+            "particle {i} escaped: {p:?}"
+        );
+    }
+}
+
+#[test]
+fn validation_is_off_by_default() {
+    let simp = SimParams::random(2, 10, 50.0, 0.5, &mut StdRng::seed_from_u64(2));
+    let particles = vec![
+        Particle {
+            pos: Vec2::NAN,
+            vel: Vec2::ZERO,
+        };
+        20
+    ];
+    let Some(mut gpu) = gpu_sim(&simp, particles) else {
+        return;
+    };
+    gpu.step(1.0);
+    assert_eq!(gpu.read_validation(), ValidationReport::default());
+}